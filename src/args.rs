@@ -1,4 +1,4 @@
-use clap::{Args, Parser, Subcommand, ValueEnum};
+use clap::{ArgGroup, Args, Parser, Subcommand, ValueEnum};
 use std::path::PathBuf;
 
 #[derive(Debug, Clone, ValueEnum)]
@@ -9,6 +9,58 @@ pub enum SortType {
     Minimizer,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+pub enum ErrorModel {
+    None,
+    Illumina,
+    Nanopore,
+    Pacbio,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+pub enum LocateMode {
+    Exact,
+    Iupac,
+    Regex,
+    Fuzzy,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+pub enum UmiFormat {
+    Underscore,
+    Rx,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+pub enum UmiMethod {
+    Unique,
+    Cluster,
+    Adjacency,
+    Directional,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+pub enum PartsMode {
+    RoundRobin,
+    Contiguous,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+#[value(rename_all = "snake_case")]
+pub enum TabColumn {
+    Id,
+    Seq,
+    Qual,
+    Len,
+    Gc,
+    Error,
+    MeanQ,
+    NCount,
+    Softmasked,
+    HeaderFields,
+    Header,
+}
+
 #[derive(Debug, Parser)]
 #[command(version, about = "General purpose fastq toolkit.", long_about = None)]
 pub struct App {
@@ -30,21 +82,61 @@ pub struct GlobalOpts {
         help = "Not applicable to all subcommands. By default set to 0, meaning Rayon will choose automatically."
     )]
     pub threads: usize,
+
+    #[clap(
+        long,
+        global = true,
+        required = false,
+        value_delimiter = ',',
+        help = "SAM/BAM aux tags (e.g. MM,ML,qs, or * for all) to keep in the read header when reading .sam/.bam input."
+    )]
+    pub keep_tags: Vec<String>,
 }
 
 #[derive(Debug, Subcommand)]
 pub enum SubCommand {
     Stats {
+        #[clap(short, long, value_delimiter = ' ', num_args = 1..)]
+        fastq: Vec<PathBuf>,
+
+        #[clap(short, long)]
+        outfile: Option<PathBuf>,
+    },
+    Sanitize {
         #[clap(short, long)]
         fastq: Option<PathBuf>,
 
         #[clap(short, long)]
         outfile: Option<PathBuf>,
     },
-    Sanitize {
+    Seq {
         #[clap(short, long)]
         fastq: Option<PathBuf>,
 
+        #[clap(long, default_value_t = false, conflicts_with_all = ["complement", "reverse"])]
+        revcomp: bool,
+
+        #[clap(long, default_value_t = false)]
+        complement: bool,
+
+        #[clap(long, default_value_t = false)]
+        reverse: bool,
+
+        #[clap(long, default_value_t = false, conflicts_with = "lower")]
+        upper: bool,
+
+        #[clap(long, default_value_t = false)]
+        lower: bool,
+
+        #[clap(long, default_value_t = false, conflicts_with = "dna")]
+        rna: bool,
+
+        #[clap(long, default_value_t = false)]
+        dna: bool,
+
+        #[clap(long, value_name = "Q")]
+        mask_lowqual: Option<u8>,
+
         #[clap(short, long)]
         outfile: Option<PathBuf>,
     },
@@ -55,6 +147,32 @@ pub enum SubCommand {
         #[clap(short, long, default_value_t = 5)]
         num_reads: usize,
 
+        #[clap(short, long, default_value_t = 0)]
+        skip: usize,
+
+        #[clap(short, long)]
+        outfile: Option<PathBuf>,
+    },
+    Tail {
+        #[clap(short, long)]
+        fastq: Option<PathBuf>,
+
+        #[clap(short, long, default_value_t = 5)]
+        num_reads: usize,
+
+        #[clap(short, long)]
+        outfile: Option<PathBuf>,
+    },
+    Range {
+        #[clap(short, long)]
+        fastq: Option<PathBuf>,
+
+        #[clap(long)]
+        start: usize,
+
+        #[clap(long)]
+        end: usize,
+
         #[clap(short, long)]
         outfile: Option<PathBuf>,
     },
@@ -68,6 +186,22 @@ pub enum SubCommand {
         #[clap(short, long)]
         outfile: Option<PathBuf>,
     },
+    Locate {
+        #[clap(short, long)]
+        fastq: Option<PathBuf>,
+
+        #[clap(short, long, value_delimiter = ' ', num_args = 1.., required = true)]
+        patterns: Vec<String>,
+
+        #[clap(value_enum, long, default_value_t = LocateMode::Exact)]
+        mode: LocateMode,
+
+        #[clap(long, default_value_t = 1)]
+        max_mismatches: u8,
+
+        #[clap(short, long)]
+        outfile: Option<PathBuf>,
+    },
     Concat {
         #[clap(short, long, value_delimiter = ' ', num_args = 1..)]
         fastqs: Vec<PathBuf>,
@@ -75,32 +209,155 @@ pub enum SubCommand {
         #[clap(short, long)]
         outfile: Option<PathBuf>,
     },
+    #[command(group(
+        ArgGroup::new("split_by")
+            .required(true)
+            .args(["by_reads", "by_bases", "parts", "by_field"])
+    ))]
+    Split {
+        #[clap(short, long)]
+        fastq: Option<PathBuf>,
+
+        #[clap(long)]
+        by_reads: Option<usize>,
+
+        #[clap(long)]
+        by_bases: Option<usize>,
+
+        #[clap(long)]
+        parts: Option<usize>,
+
+        #[clap(value_enum, long, default_value_t = PartsMode::RoundRobin)]
+        parts_mode: PartsMode,
+
+        #[clap(long)]
+        by_field: Option<String>,
+
+        #[clap(short = 'd', long, default_value = "split")]
+        outdir: PathBuf,
+    },
+    Index {
+        #[clap(short, long)]
+        fastq: PathBuf,
+
+        #[clap(short, long)]
+        outfile: Option<PathBuf>,
+    },
+    Fetch {
+        #[clap(short, long)]
+        fastq: PathBuf,
+
+        #[clap(short, long)]
+        index: Option<PathBuf>,
+
+        #[clap(long, value_delimiter = ' ', num_args = 1..)]
+        ids: Vec<String>,
+
+        #[clap(long)]
+        id_file: Option<PathBuf>,
+
+        #[clap(short, long)]
+        outfile: Option<PathBuf>,
+    },
+    #[command(group(
+        ArgGroup::new("regions")
+            .required(true)
+            .multiple(false)
+            .args(["region", "bed"])
+    ))]
+    Subseq {
+        #[clap(short, long)]
+        fastq: Option<PathBuf>,
+
+        #[clap(long, allow_hyphen_values = true)]
+        region: Option<String>,
+
+        #[clap(long)]
+        bed: Option<PathBuf>,
+
+        #[clap(short, long)]
+        outfile: Option<PathBuf>,
+    },
+    Interleave {
+        #[clap(short = '1', long)]
+        fastq_1: PathBuf,
+
+        #[clap(short = '2', long)]
+        fastq_2: PathBuf,
+
+        #[clap(short, long)]
+        outfile: Option<PathBuf>,
+    },
+    Deinterleave {
+        #[clap(short, long)]
+        fastq: Option<PathBuf>,
+
+        #[clap(short = '1', long)]
+        outfile_1: PathBuf,
+
+        #[clap(short = '2', long)]
+        outfile_2: PathBuf,
+    },
+    MergePairs {
+        #[clap(short = '1', long)]
+        fastq_1: PathBuf,
+
+        #[clap(short = '2', long)]
+        fastq_2: PathBuf,
+
+        #[clap(long, default_value_t = 10)]
+        min_overlap: usize,
+
+        #[clap(long, default_value_t = 0.1)]
+        max_mismatch_rate: f64,
+
+        #[clap(long, default_value_t = false)]
+        allow_dovetail: bool,
+
+        #[clap(short, long)]
+        outfile: Option<PathBuf>,
+
+        #[clap(long, default_value = "unmerged_R1.fastq.gz")]
+        unmerged_1: PathBuf,
+
+        #[clap(long, default_value = "unmerged_R2.fastq.gz")]
+        unmerged_2: PathBuf,
+
+        #[clap(long, default_value = "merge_pairs_report.json")]
+        report: PathBuf,
+    },
     Filter {
         #[clap(short, long)]
         fastq: Option<PathBuf>,
 
-        #[clap(short, long, default_value_t = 0)]
+        #[clap(long, default_value_t = 0)]
         min_len: usize,
 
-        #[clap(short, long, default_value_t = usize::MAX)]
+        #[clap(long, default_value_t = usize::MAX)]
         max_len: usize,
 
-        #[clap(short, long, default_value_t = 0.0)]
+        #[clap(long, default_value_t = 0.0)]
         min_error: f64,
 
-        #[clap(short, long, default_value_t = 1.0)]
+        #[clap(long, default_value_t = 1.0)]
         max_error: f64,
 
-        #[clap(short, long, default_value_t = 0)]
+        #[clap(long, default_value_t = 0.0)]
+        min_gc: f64,
+
+        #[clap(long, default_value_t = 1.0)]
+        max_gc: f64,
+
+        #[clap(long, default_value_t = 0)]
         min_softmasked: usize,
 
-        #[clap(short, long, default_value_t = usize::MAX)]
+        #[clap(long, default_value_t = usize::MAX)]
         max_softmasked: usize,
 
-        #[clap(short, long, default_value_t = 0)]
+        #[clap(long, default_value_t = 0)]
         min_ambiguous: usize,
 
-        #[clap(short, long, default_value_t = usize::MAX)]
+        #[clap(long, default_value_t = usize::MAX)]
         max_ambiguous: usize,
 
         #[clap(short, long)]
@@ -138,10 +395,55 @@ pub enum SubCommand {
         #[clap(short, long)]
         outfile: Option<PathBuf>,
     },
+    Fa2Fq {
+        #[clap(short, long)]
+        fasta: Option<PathBuf>,
+
+        #[clap(
+            long,
+            default_value_t = 30,
+            help = "Constant phred assigned to every base."
+        )]
+        phred: u8,
+
+        #[clap(
+            short,
+            long,
+            help = "Companion .qual file with per-base phred scores. Overrides --phred."
+        )]
+        qual: Option<PathBuf>,
+
+        #[clap(short, long)]
+        outfile: Option<PathBuf>,
+    },
     Fq2Tab {
         #[clap(short, long)]
         fastq: Option<PathBuf>,
 
+        #[clap(
+            short,
+            long,
+            value_enum,
+            value_delimiter = ',',
+            default_value = "id,len,error,mean_q"
+        )]
+        columns: Vec<TabColumn>,
+
+        #[clap(
+            short,
+            long,
+            default_value_t = false,
+            help = "Also include id, header, seq and qual, so that the table can be converted back with tab2-fq."
+        )]
+        lossless: bool,
+
+        #[clap(short, long)]
+        outfile: Option<PathBuf>,
+    },
+    Tab2Fq {
+        #[clap(long)]
+        tsv: Option<PathBuf>,
+
         #[clap(short, long)]
         outfile: Option<PathBuf>,
     },
@@ -182,15 +484,104 @@ pub enum SubCommand {
         #[clap(long, default_value_t = 2)]
         max_mismatches: u8,
 
+        #[clap(long, default_value_t = 1)]
+        max_indels: u8,
+
         #[clap(long, default_value_t = 10)]
         barcode_margin: usize,
 
+        #[clap(long, default_value_t = false)]
+        amplicon: bool,
+
         #[clap(short, long)]
         outfile: Option<PathBuf>,
 
         #[clap(short, long, default_value = "barcodes.tsv")]
         barcodes_tsv: PathBuf,
     },
+    ExtractUmi {
+        #[clap(short, long)]
+        fastq: Option<PathBuf>,
+
+        #[clap(short, long)]
+        pattern: String,
+
+        #[clap(long, default_value_t = 0)]
+        position: usize,
+
+        #[clap(long, required = false, value_delimiter = ' ')]
+        barcode_forward: Option<Vec<String>>,
+
+        #[clap(long, default_value_t = 2)]
+        max_mismatches: u8,
+
+        #[clap(long, default_value_t = 1)]
+        max_indels: u8,
+
+        #[clap(long, default_value_t = 10)]
+        barcode_margin: usize,
+
+        #[clap(value_enum, long, default_value_t = UmiFormat::Underscore)]
+        umi_format: UmiFormat,
+
+        #[clap(short, long)]
+        outfile: Option<PathBuf>,
+
+        #[clap(long)]
+        no_umi_outfile: Option<PathBuf>,
+    },
+    UmiDedup {
+        #[clap(short, long)]
+        fastq: Option<PathBuf>,
+
+        #[clap(value_enum, long, default_value_t = UmiMethod::Directional)]
+        method: UmiMethod,
+
+        #[clap(long, default_value_t = 1)]
+        max_distance: usize,
+
+        #[clap(short, long, default_value_t = 15)]
+        kmer_size: usize,
+
+        #[clap(long, default_value_t = false)]
+        consensus: bool,
+
+        #[clap(short, long)]
+        outfile: Option<PathBuf>,
+
+        #[clap(long, default_value = "umi_groups.tsv")]
+        group_stats: PathBuf,
+    },
+    Evaluate {
+        #[clap(long, help = "Barcodes truth from `mock --barcodes`.")]
+        truth: PathBuf,
+
+        #[clap(short, long, help = "Barcodes report from `trim`.")]
+        barcodes_tsv: PathBuf,
+
+        #[clap(short, long)]
+        outfile: Option<PathBuf>,
+
+        #[clap(long, default_value = "evaluate_barcodes.tsv")]
+        barcodes_report: PathBuf,
+
+        #[clap(long, default_value = "evaluate_confusion.tsv")]
+        confusion_report: PathBuf,
+    },
+    Profile {
+        #[clap(short, long)]
+        fastq: Option<PathBuf>,
+
+        #[clap(
+            long,
+            default_value_t = 1000,
+            help = "Positions from this one on share one quality distribution."
+        )]
+        max_positions: usize,
+
+        #[clap(short, long)]
+        outfile: Option<PathBuf>,
+    },
     Mock {
         #[clap(short, long, default_value_t = 10)]
         num_reads: usize,
@@ -201,6 +592,19 @@ pub enum SubCommand {
         #[clap(long, default_value_t = 10)]
         max_len: usize,
 
+        #[clap(
+            long,
+            default_value = "uniform",
+            help = "uniform (--min-len to --max-len), normal:MEAN,SD, lognormal:MEAN,SD or empirical:FILE (one length per line)."
+        )]
+        length_dist: String,
+
+        #[clap(
+            long,
+            help = "Read profile (see `profile`) to draw read lengths and qualities from. Overrides --length-dist and --phred."
+        )]
+        profile: Option<PathBuf>,
+
         #[clap(long, default_value_t = 30)]
         phred: u8,
 
@@ -210,14 +614,104 @@ pub enum SubCommand {
         #[clap(long)]
         suffix_seq: Option<String>,
 
+        #[clap(value_enum, long, default_value_t = ErrorModel::None)]
+        error_model: ErrorModel,
+
+        #[clap(long)]
+        seed: Option<u64>,
+
+        #[clap(
+            long,
+            help = "Write injected errors to this file. Requires --error-model."
+        )]
+        truth: Option<PathBuf>,
+
+        #[clap(
+            long,
+            help = "Sample reads from this FASTA instead of random sequences."
+        )]
+        reference: Option<PathBuf>,
+
+        #[clap(long, requires = "reference", help = "Overrides --num-reads.")]
+        coverage: Option<f64>,
+
+        #[clap(long, default_value_t = false, requires_all = ["reference", "outfile", "outfile_2"])]
+        paired: bool,
+
+        #[clap(long, default_value = "normal:300,30")]
+        insert_dist: String,
+
+        #[clap(
+            long,
+            conflicts_with_all = ["prefix_seq", "suffix_seq", "paired"],
+            help = "Tsv with sample, barcode_forward, barcode_reverse and optionally adapter_forward, adapter_reverse columns."
+        )]
+        barcodes: Option<PathBuf>,
+
+        #[clap(long, default_value_t = 0.0)]
+        barcode_mutation_rate: f64,
+
+        #[clap(long, default_value_t = 0.0)]
+        adapter_truncation_rate: f64,
+
+        #[clap(long, default_value_t = false)]
+        random_orientation: bool,
+
+        #[clap(long, default_value_t = 0.0)]
+        chimera_rate: f64,
+
+        #[clap(long, default_value = "barcodes_truth.tsv")]
+        barcodes_truth: PathBuf,
+
         #[clap(short, long)]
         outfile: Option<PathBuf>,
+
+        #[clap(short = '2', long, requires = "paired")]
+        outfile_2: Option<PathBuf>,
     },
     Renumber {
         #[clap(short, long)]
         fastq: Option<PathBuf>,
 
+        #[clap(
+            long,
+            default_value = "{prefix}_{n}",
+            help = "Placeholders: {prefix}, {n}, {id}, {sample}, {file}, {len}, {mean_q}, {hash}. E.g. {prefix}_{n:08}."
+        )]
+        template: String,
+
+        #[clap(long, default_value = "read")]
+        prefix: String,
+
+        #[clap(long, help = "Sample name. Defaults to the input file name.")]
+        sample: Option<String>,
+
+        #[clap(long, default_value_t = false)]
+        keep_header: bool,
+
+        #[clap(long, help = "Write a tsv mapping of old to new read ids.")]
+        mapping: Option<PathBuf>,
+
+        #[clap(short, long)]
+        outfile: Option<PathBuf>,
+    },
+    Pipeline {
+        #[clap(short, long)]
+        fastq: Option<PathBuf>,
+
+        #[clap(short, long, help = "TOML file with the steps to run, in order.")]
+        recipe: PathBuf,
+
         #[clap(short, long)]
         outfile: Option<PathBuf>,
+
+        #[clap(long, default_value = "pipeline_report.json")]
+        report: PathBuf,
     },
 }
+
+#[test]
+fn test_cli_args() {
+    use clap::CommandFactory;
+    App::command().debug_assert();
+}
@@ -0,0 +1,307 @@
+use crate::bam::tags::{KeepTags, aux_to_text, join_sam_header, split_sam_header, text_to_aux};
+use crate::errors::AppError;
+use crate::fastx::FastxRecord;
+use bio_utils_rs::nucleotide::{PHRED_OFFSET, reverse_complement};
+use rstest::rstest;
+use std::io::{ErrorKind, Read, Write};
+
+/// 4 bit nucleotide encoding used in BAM.
+const SEQ_DECODE: &[u8; 16] = b"=ACMGRSVTWYHKDBN";
+
+/// Secondary and supplementary alignments are not separate reads.
+const FLAG_SKIP: u16 = 0x100 | 0x800;
+const FLAG_REVERSE: u16 = 0x10;
+const FLAG_UNMAPPED: u16 = 0x4;
+
+/// BAM bin for unmapped reads without position.
+const UNMAPPED_BIN: u16 = 4680;
+
+/// Maximum read name length allowed by the SAM spec.
+const MAX_NAME_LEN: usize = 254;
+
+pub const SAM_HEADER: &[u8] = b"@HD\tVN:1.6\tSO:unknown\n@PG\tID:fastq_rs\tPN:fastq_rs\n";
+
+#[inline]
+fn seq_encode(nt: u8) -> u8 {
+    match nt.to_ascii_uppercase() {
+        b'=' => 0,
+        b'A' => 1,
+        b'C' => 2,
+        b'M' => 3,
+        b'G' => 4,
+        b'R' => 5,
+        b'S' => 6,
+        b'V' => 7,
+        b'T' => 8,
+        b'W' => 9,
+        b'Y' => 10,
+        b'H' => 11,
+        b'K' => 12,
+        b'D' => 13,
+        b'B' => 14,
+        _ => 15,
+    }
+}
+
+fn get(buf: &[u8], start: usize, len: usize) -> Result<&[u8], AppError> {
+    buf.get(start..start + len)
+        .ok_or(AppError::BamError("truncated BAM record".to_string()))
+}
+
+fn get_u16(buf: &[u8], start: usize) -> Result<u16, AppError> {
+    let b = get(buf, start, 2)?;
+    Ok(u16::from_le_bytes([b[0], b[1]]))
+}
+
+fn get_u32(buf: &[u8], start: usize) -> Result<u32, AppError> {
+    let b = get(buf, start, 4)?;
+    Ok(u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+}
+
+/// Reads a little endian u32, or `None` at a clean end of file.
+fn read_u32<R: Read>(reader: &mut R) -> Result<Option<u32>, AppError> {
+    let mut b = [0u8; 4];
+    let mut n = 0;
+
+    while n < 4 {
+        match reader.read(&mut b[n..]) {
+            Ok(0) if n == 0 => return Ok(None),
+            Ok(0) => return Err(AppError::BamError("truncated BAM file".to_string())),
+            Ok(i) => n += i,
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e.into()),
+        }
+    }
+
+    Ok(Some(u32::from_le_bytes(b)))
+}
+
+fn skip<R: Read>(reader: &mut R, n: u64) -> Result<(), AppError> {
+    let skipped = std::io::copy(&mut reader.take(n), &mut std::io::sink())?;
+
+    match skipped == n {
+        true => Ok(()),
+        false => Err(AppError::BamError("truncated BAM header".to_string())),
+    }
+}
+
+/// Reads (unaligned) BAM records from a decompressed BAM stream. Selected aux
+/// tags are added to the read header, in the same way as `samtools fastq -T`.
+pub struct BamReader<R: Read> {
+    reader: R,
+    buf: Vec<u8>,
+    keep_tags: KeepTags,
+}
+
+impl<R: Read> BamReader<R> {
+    pub fn new(mut reader: R, keep_tags: KeepTags) -> Result<Self, AppError> {
+        let mut magic = [0u8; 4];
+        reader.read_exact(&mut magic)?;
+
+        if &magic != b"BAM\x01" {
+            return Err(AppError::BamError("not a BAM file".to_string()));
+        }
+
+        // We don't need the header text or reference sequences.
+        let l_text = read_u32(&mut reader)?.unwrap_or_default();
+        skip(&mut reader, l_text as u64)?;
+
+        let n_ref = read_u32(&mut reader)?.unwrap_or_default();
+        for _ in 0..n_ref {
+            let l_name = read_u32(&mut reader)?.unwrap_or_default();
+            skip(&mut reader, l_name as u64 + 4)?;
+        }
+
+        Ok(Self {
+            reader,
+            buf: Vec::new(),
+            keep_tags,
+        })
+    }
+
+    pub fn next_record(&mut self) -> Result<Option<FastxRecord>, AppError> {
+        loop {
+            let block_size = match read_u32(&mut self.reader)? {
+                Some(block_size) => block_size as usize,
+                None => return Ok(None),
+            };
+
+            self.buf.resize(block_size, 0);
+            self.reader.read_exact(&mut self.buf)?;
+
+            let flag = get_u16(&self.buf, 14)?;
+            if flag & FLAG_SKIP != 0 {
+                continue;
+            }
+
+            return parse_record(&self.buf, flag, &self.keep_tags).map(Some);
+        }
+    }
+}
+
+fn parse_record(buf: &[u8], flag: u16, keep_tags: &KeepTags) -> Result<FastxRecord, AppError> {
+    let l_read_name = get(buf, 8, 1)?[0] as usize;
+    let n_cigar_op = get_u16(buf, 12)? as usize;
+    let l_seq = get_u32(buf, 16)? as usize;
+
+    let mut offset = 32;
+
+    // Read name is NUL terminated.
+    let name = get(buf, offset, l_read_name.saturating_sub(1))?;
+    offset += l_read_name + 4 * n_cigar_op;
+
+    let packed = get(buf, offset, l_seq.div_ceil(2))?;
+    offset += l_seq.div_ceil(2);
+
+    let mut seq: Vec<u8> = (0..l_seq)
+        .map(|i| {
+            let nibble = match i % 2 {
+                0 => packed[i / 2] >> 4,
+                _ => packed[i / 2] & 0x0f,
+            };
+            SEQ_DECODE[nibble as usize]
+        })
+        .collect();
+
+    // Missing qualities are stored as 0xff.
+    let qual = get(buf, offset, l_seq)?;
+    offset += l_seq;
+
+    let mut qual = match qual.first() {
+        Some(0xff) | None => None,
+        Some(_) => Some(
+            qual.iter()
+                .map(|q| q + PHRED_OFFSET as u8)
+                .collect::<Vec<u8>>(),
+        ),
+    };
+
+    // Restore the original read orientation.
+    if flag & FLAG_REVERSE != 0 {
+        seq = reverse_complement(&seq);
+        if let Some(qual) = qual.as_mut() {
+            qual.reverse();
+        }
+    }
+
+    let tags = aux_to_text(&buf[offset..])?;
+
+    Ok(FastxRecord {
+        id: join_sam_header(name, &tags, keep_tags),
+        seq,
+        qual,
+    })
+}
+
+/// Writes records as unaligned BAM. SAM tags in the read header (see
+/// `split_sam_header`) become aux tags and any other description is stored in `CO`.
+///
+/// Should wrap a `BgzfWriter`.
+pub struct BamWriter<W: Write> {
+    writer: W,
+    buf: Vec<u8>,
+}
+
+impl<W: Write> BamWriter<W> {
+    pub fn new(mut writer: W) -> Result<Self, AppError> {
+        writer.write_all(b"BAM\x01")?;
+        writer.write_all(&(SAM_HEADER.len() as u32).to_le_bytes())?;
+        writer.write_all(SAM_HEADER)?;
+        // No reference sequences.
+        writer.write_all(&0u32.to_le_bytes())?;
+
+        Ok(Self {
+            writer,
+            buf: Vec::new(),
+        })
+    }
+
+    pub fn write_record(&mut self, record: &FastxRecord) -> Result<(), AppError> {
+        let (name, tags, comment) = split_sam_header(&record.id);
+
+        if name.is_empty() || name.len() > MAX_NAME_LEN {
+            return Err(AppError::BamError(format!(
+                "read name must be 1-{MAX_NAME_LEN} characters: {}",
+                String::from_utf8_lossy(name)
+            )));
+        }
+
+        let l_seq = record.seq.len();
+        let buf = &mut self.buf;
+        buf.clear();
+
+        buf.extend_from_slice(&(-1i32).to_le_bytes()); // refID
+        buf.extend_from_slice(&(-1i32).to_le_bytes()); // pos
+        buf.push(name.len() as u8 + 1);
+        buf.push(0); // mapq
+        buf.extend_from_slice(&UNMAPPED_BIN.to_le_bytes());
+        buf.extend_from_slice(&0u16.to_le_bytes()); // n_cigar_op
+        buf.extend_from_slice(&FLAG_UNMAPPED.to_le_bytes());
+        buf.extend_from_slice(&(l_seq as u32).to_le_bytes());
+        buf.extend_from_slice(&(-1i32).to_le_bytes()); // next refID
+        buf.extend_from_slice(&(-1i32).to_le_bytes()); // next pos
+        buf.extend_from_slice(&0i32.to_le_bytes()); // tlen
+
+        buf.extend_from_slice(name);
+        buf.push(0);
+
+        buf.extend(
+            record
+                .seq
+                .chunks(2)
+                .map(|c| seq_encode(c[0]) << 4 | c.get(1).map_or(0, |nt| seq_encode(*nt))),
+        );
+
+        match record.qual.as_ref() {
+            Some(qual) => buf.extend(qual.iter().map(|q| q.saturating_sub(PHRED_OFFSET as u8))),
+            None => buf.extend(std::iter::repeat_n(0xff, l_seq)),
+        }
+
+        for tag in tags {
+            text_to_aux(tag, buf)?;
+        }
+
+        if !comment.is_empty() {
+            buf.extend_from_slice(b"COZ");
+            buf.extend_from_slice(&comment);
+            buf.push(0);
+        }
+
+        self.writer
+            .write_all(&(self.buf.len() as u32).to_le_bytes())?;
+        self.writer.write_all(&self.buf)?;
+
+        Ok(())
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+#[rstest]
+#[case(b"read_1".as_slice(), b"ACGTN".as_slice(), Some(b"II#I5".as_slice()))]
+#[case(b"read_2 ch=1 start=2", b"ACG", None)]
+#[case(
+    b"read_3\tqs:i:12\tMM:Z:C+m?,1;\tML:B:C,12,200",
+    b"ACGTA",
+    Some(b"IIIII".as_slice())
+)]
+#[case(b"read_4\tzf:f:-1.5\tzi:i:-40000\tzs:B:s,-1,2", b"A", Some(b"I".as_slice()))]
+fn test_bam_round_trip(#[case] id: &[u8], #[case] seq: &[u8], #[case] qual: Option<&[u8]>) {
+    let record = FastxRecord {
+        id: id.to_vec(),
+        seq: seq.to_vec(),
+        qual: qual.map(|q| q.to_vec()),
+    };
+
+    let mut writer = BamWriter::new(Vec::new()).unwrap();
+    writer.write_record(&record).unwrap();
+
+    let bytes = writer.into_inner();
+    let keep_tags = KeepTags::new(&["*".to_string()]).unwrap();
+    let mut reader = BamReader::new(bytes.as_slice(), keep_tags).unwrap();
+
+    assert_eq!(reader.next_record().unwrap(), Some(record));
+    assert_eq!(reader.next_record().unwrap(), None);
+}
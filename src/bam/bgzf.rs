@@ -0,0 +1,263 @@
+use flate2::Compression;
+use flate2::Crc;
+use flate2::read::DeflateDecoder;
+use flate2::write::DeflateEncoder;
+use rstest::rstest;
+use std::io::{BufRead, Error, ErrorKind, Read, Result, Seek, SeekFrom, Write};
+
+/// Maximum number of uncompressed bytes per block (same as htslib).
+const MAX_BLOCK_SIZE: usize = 0xff00;
+
+/// Empty block marking the end of a BGZF file.
+const EOF_BLOCK: [u8; 28] = [
+    0x1f, 0x8b, 0x08, 0x04, 0x00, 0x00, 0x00, 0x00, 0x00, 0xff, 0x06, 0x00, 0x42, 0x43, 0x02, 0x00,
+    0x1b, 0x00, 0x03, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+];
+
+/// Blocked gzip writer, as required by BAM. Every block is a valid gzip
+/// member, so the output can be read with a regular multi member gzip decoder.
+///
+/// `finish` must be called to write the end of file marker.
+pub struct BgzfWriter<W: Write> {
+    inner: W,
+    buf: Vec<u8>,
+}
+
+impl<W: Write> BgzfWriter<W> {
+    pub fn new(inner: W) -> Self {
+        Self {
+            inner,
+            buf: Vec::with_capacity(MAX_BLOCK_SIZE),
+        }
+    }
+
+    fn write_block(&mut self) -> Result<()> {
+        if self.buf.is_empty() {
+            return Ok(());
+        }
+
+        let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&self.buf)?;
+        let compressed = encoder.finish()?;
+
+        let mut crc = Crc::new();
+        crc.update(&self.buf);
+
+        // Total block size minus one, including the 18 byte header and 8 byte footer.
+        let block_size = (compressed.len() + 25) as u16;
+
+        self.inner.write_all(&[
+            0x1f, 0x8b, 0x08, 0x04, 0x00, 0x00, 0x00, 0x00, 0x00, 0xff, 0x06, 0x00, 0x42, 0x43,
+            0x02, 0x00,
+        ])?;
+        self.inner.write_all(&block_size.to_le_bytes())?;
+        self.inner.write_all(&compressed)?;
+        self.inner.write_all(&crc.sum().to_le_bytes())?;
+        self.inner
+            .write_all(&(self.buf.len() as u32).to_le_bytes())?;
+
+        self.buf.clear();
+
+        Ok(())
+    }
+
+    /// Writes any buffered data and the end of file marker.
+    pub fn finish(mut self) -> Result<W> {
+        self.write_block()?;
+        self.inner.write_all(&EOF_BLOCK)?;
+        self.inner.flush()?;
+
+        Ok(self.inner)
+    }
+}
+
+impl<W: Write> Write for BgzfWriter<W> {
+    fn write(&mut self, data: &[u8]) -> Result<usize> {
+        let n = std::cmp::min(data.len(), MAX_BLOCK_SIZE - self.buf.len());
+        self.buf.extend_from_slice(&data[..n]);
+
+        if self.buf.len() == MAX_BLOCK_SIZE {
+            self.write_block()?;
+        }
+
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.write_block()?;
+        self.inner.flush()
+    }
+}
+
+/// Reads BGZF files block by block, keeping track of virtual offsets
+/// (`compressed block offset << 16 | offset within block`) for random access.
+pub struct BgzfReader<R: Read> {
+    inner: R,
+    block: Vec<u8>,
+    pos: usize,
+    block_offset: u64,
+    next_block_offset: u64,
+}
+
+impl<R: Read> BgzfReader<R> {
+    pub fn new(inner: R) -> Self {
+        Self {
+            inner,
+            block: Vec::with_capacity(MAX_BLOCK_SIZE),
+            pos: 0,
+            block_offset: 0,
+            next_block_offset: 0,
+        }
+    }
+
+    /// Reads and decompresses the next block. Returns `false` at end of file.
+    fn read_block(&mut self) -> Result<bool> {
+        let mut header = [0u8; 18];
+        let mut n = 0;
+
+        while n < header.len() {
+            match self.inner.read(&mut header[n..])? {
+                0 if n == 0 => return Ok(false),
+                0 => return Err(invalid_data("truncated BGZF block")),
+                i => n += i,
+            }
+        }
+
+        if header[..4] != [0x1f, 0x8b, 0x08, 0x04] || header[10..14] != [0x06, 0x00, 0x42, 0x43] {
+            return Err(invalid_data(
+                "not a BGZF file (use `bgzip` instead of `gzip` to compress it)",
+            ));
+        }
+
+        let block_size = u16::from_le_bytes([header[16], header[17]]) as usize + 1;
+
+        let mut data = vec![0u8; block_size.saturating_sub(header.len())];
+        self.inner.read_exact(&mut data)?;
+
+        if data.len() < 8 {
+            return Err(invalid_data("truncated BGZF block"));
+        }
+
+        self.block.clear();
+        DeflateDecoder::new(&data[..data.len() - 8]).read_to_end(&mut self.block)?;
+
+        self.block_offset = self.next_block_offset;
+        self.next_block_offset += block_size as u64;
+        self.pos = 0;
+
+        Ok(true)
+    }
+
+    pub fn virtual_offset(&self) -> u64 {
+        match self.pos < self.block.len() {
+            true => self.block_offset << 16 | self.pos as u64,
+            false => self.next_block_offset << 16,
+        }
+    }
+}
+
+impl<R: Read + Seek> BgzfReader<R> {
+    pub fn seek_virtual(&mut self, virtual_offset: u64) -> Result<()> {
+        let block_offset = virtual_offset >> 16;
+
+        self.inner.seek(SeekFrom::Start(block_offset))?;
+        self.next_block_offset = block_offset;
+        self.block.clear();
+        self.pos = 0;
+
+        self.read_block()?;
+        self.pos = std::cmp::min((virtual_offset & 0xffff) as usize, self.block.len());
+
+        Ok(())
+    }
+}
+
+impl<R: Read> Read for BgzfReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let data = self.fill_buf()?;
+        let n = std::cmp::min(data.len(), buf.len());
+        buf[..n].copy_from_slice(&data[..n]);
+        self.consume(n);
+
+        Ok(n)
+    }
+}
+
+impl<R: Read> BufRead for BgzfReader<R> {
+    fn fill_buf(&mut self) -> Result<&[u8]> {
+        // Blocks can be empty (e.g. the end of file marker).
+        while self.pos >= self.block.len() {
+            if !self.read_block()? {
+                return Ok(&[]);
+            }
+        }
+
+        Ok(&self.block[self.pos..])
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.pos = std::cmp::min(self.pos + amt, self.block.len());
+    }
+}
+
+fn invalid_data(msg: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, msg)
+}
+
+#[cfg(test)]
+fn bgzf_data(len: usize) -> (Vec<u8>, Vec<u8>) {
+    // Poorly compressible data, so that blocks have different compressed sizes.
+    let data: Vec<u8> = (0..len as u64)
+        .map(|i| (i.wrapping_mul(2654435761) >> 7) as u8)
+        .collect();
+
+    let mut writer = BgzfWriter::new(Vec::new());
+    writer.write_all(&data).unwrap();
+
+    (data, writer.finish().unwrap())
+}
+
+#[rstest]
+#[case(0)]
+#[case(10)]
+#[case(MAX_BLOCK_SIZE)]
+#[case(3 * MAX_BLOCK_SIZE + 100)]
+fn test_bgzf_round_trip(#[case] len: usize) {
+    let (data, compressed) = bgzf_data(len);
+
+    assert!(compressed.ends_with(&EOF_BLOCK));
+
+    let mut decompressed = Vec::new();
+    BgzfReader::new(compressed.as_slice())
+        .read_to_end(&mut decompressed)
+        .unwrap();
+    assert_eq!(decompressed, data);
+
+    // Every block is a gzip member.
+    let mut decompressed = Vec::new();
+    flate2::read::MultiGzDecoder::new(compressed.as_slice())
+        .read_to_end(&mut decompressed)
+        .unwrap();
+    assert_eq!(decompressed, data);
+}
+
+#[rstest]
+#[case(0)]
+#[case(1)]
+#[case(1000)]
+#[case(MAX_BLOCK_SIZE - 1)]
+fn test_bgzf_seek_virtual(#[case] within_block: usize) {
+    let (data, compressed) = bgzf_data(2 * MAX_BLOCK_SIZE + 100);
+
+    // The second block starts right after the first one.
+    let first_block_size = u16::from_le_bytes([compressed[16], compressed[17]]) as u64 + 1;
+    let virtual_offset = first_block_size << 16 | within_block as u64;
+
+    let mut reader = BgzfReader::new(std::io::Cursor::new(compressed));
+    reader.seek_virtual(virtual_offset).unwrap();
+    assert_eq!(reader.virtual_offset(), virtual_offset);
+
+    let mut rest = Vec::new();
+    reader.read_to_end(&mut rest).unwrap();
+    assert_eq!(rest, data[MAX_BLOCK_SIZE + within_block..]);
+}
@@ -0,0 +1,8 @@
+pub mod bam;
+pub mod bgzf;
+pub mod sam;
+pub mod tags;
+pub use bam::{BamReader, BamWriter};
+pub use bgzf::{BgzfReader, BgzfWriter};
+pub use sam::{SamReader, SamWriter};
+pub use tags::KeepTags;
@@ -0,0 +1,132 @@
+use crate::bam::bam::SAM_HEADER;
+use crate::bam::tags::{KeepTags, join_sam_header, split_sam_header};
+use crate::errors::AppError;
+use crate::fastx::FastxRecord;
+use bio_utils_rs::nucleotide::reverse_complement;
+use std::io::{BufRead, Write};
+
+const FLAG_SKIP: u16 = 0x100 | 0x800;
+const FLAG_REVERSE: u16 = 0x10;
+
+/// Reads SAM records (header lines are skipped). Selected tags are added to
+/// the read header, same as for BAM.
+pub struct SamReader<R: BufRead> {
+    reader: R,
+    line: Vec<u8>,
+    keep_tags: KeepTags,
+}
+
+impl<R: BufRead> SamReader<R> {
+    pub fn new(reader: R, keep_tags: KeepTags) -> Self {
+        Self {
+            reader,
+            line: Vec::new(),
+            keep_tags,
+        }
+    }
+
+    pub fn next_record(&mut self) -> Result<Option<FastxRecord>, AppError> {
+        loop {
+            self.line.clear();
+
+            if self.reader.read_until(b'\n', &mut self.line)? == 0 {
+                return Ok(None);
+            }
+
+            let line = self.line.trim_ascii_end();
+
+            if line.is_empty() || line.starts_with(b"@") {
+                continue;
+            }
+
+            let fields: Vec<&[u8]> = line.split(|c| *c == b'\t').collect();
+
+            if fields.len() < 11 {
+                return Err(AppError::BamError(format!(
+                    "expected at least 11 SAM columns, got {}",
+                    fields.len()
+                )));
+            }
+
+            let flag: u16 = std::str::from_utf8(fields[1])?
+                .parse()
+                .map_err(|_| AppError::BamError("invalid SAM flag".to_string()))?;
+
+            if flag & FLAG_SKIP != 0 {
+                continue;
+            }
+
+            let mut seq = match fields[9] {
+                b"*" => Vec::new(),
+                seq => seq.to_vec(),
+            };
+
+            let mut qual = match fields[10] {
+                b"*" => None,
+                qual => Some(qual.to_vec()),
+            };
+
+            if flag & FLAG_REVERSE != 0 {
+                seq = reverse_complement(&seq);
+                if let Some(qual) = qual.as_mut() {
+                    qual.reverse();
+                }
+            }
+
+            return Ok(Some(FastxRecord {
+                id: join_sam_header(fields[0], &fields[11..], &self.keep_tags),
+                seq,
+                qual,
+            }));
+        }
+    }
+}
+
+/// Writes records as unaligned SAM.
+pub struct SamWriter<W: Write> {
+    writer: W,
+}
+
+impl<W: Write> SamWriter<W> {
+    pub fn new(mut writer: W) -> Result<Self, AppError> {
+        writer.write_all(SAM_HEADER)?;
+        Ok(Self { writer })
+    }
+
+    pub fn write_record(&mut self, record: &FastxRecord) -> Result<(), AppError> {
+        let (name, tags, comment) = split_sam_header(&record.id);
+        let w = &mut self.writer;
+
+        w.write_all(name)?;
+        w.write_all(b"\t4\t*\t0\t0\t*\t*\t0\t0\t")?;
+
+        match record.seq.is_empty() {
+            true => w.write_all(b"*")?,
+            false => w.write_all(&record.seq)?,
+        }
+        w.write_all(b"\t")?;
+
+        match record.qual.as_deref() {
+            Some(qual) if !qual.is_empty() => w.write_all(qual)?,
+            _ => w.write_all(b"*")?,
+        }
+
+        for tag in tags {
+            w.write_all(b"\t")?;
+            w.write_all(tag)?;
+        }
+
+        if !comment.is_empty() {
+            w.write_all(b"\tCO:Z:")?;
+            w.write_all(&comment)?;
+        }
+
+        w.write_all(b"\n")?;
+
+        Ok(())
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
@@ -0,0 +1,310 @@
+use crate::errors::AppError;
+use rstest::rstest;
+
+/// Aux tags to keep in the FASTQ header when reading SAM/BAM. By default, none are kept.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct KeepTags(Vec<[u8; 2]>);
+
+impl KeepTags {
+    /// Tags are kept if listed here (or if the list contains `*`).
+    pub fn new(tags: &[String]) -> Result<Self, AppError> {
+        let tags = tags
+            .iter()
+            .map(|t| match t.as_bytes() {
+                [a, b] => Ok([*a, *b]),
+                b"*" => Ok(*b"**"),
+                _ => Err(AppError::InvalidArgumentError(format!(
+                    "invalid SAM tag `{t}`, expected two characters"
+                ))),
+            })
+            .collect::<Result<Vec<[u8; 2]>, AppError>>()?;
+
+        Ok(Self(tags))
+    }
+
+    #[inline]
+    pub fn keep(&self, tag: &[u8]) -> bool {
+        self.0.iter().any(|t| t == b"**" || t == tag)
+    }
+}
+
+/// Whether a header field looks like a SAM tag, e.g. `MM:Z:C+m?,1;`.
+#[inline]
+pub fn is_sam_tag(field: &[u8]) -> bool {
+    matches!(
+        field,
+        [a, b, b':', b'A' | b'i' | b'f' | b'Z' | b'H' | b'B', b':', ..]
+            if a.is_ascii_alphabetic() && b.is_ascii_alphanumeric()
+    )
+}
+
+/// Splits a FASTQ header into the read name, SAM tags (tab separated fields after
+/// the name, as written by `samtools fastq -T`) and the remaining free text comment.
+pub fn split_sam_header(header: &[u8]) -> (&[u8], Vec<&[u8]>, Vec<u8>) {
+    let (name, rest) = match header.iter().position(|c| c.is_ascii_whitespace()) {
+        Some(i) => (&header[..i], &header[i + 1..]),
+        None => (header, &b""[..]),
+    };
+
+    let mut tags: Vec<&[u8]> = Vec::new();
+    let mut comment: Vec<u8> = Vec::new();
+
+    for field in rest.split(|c| *c == b'\t').filter(|f| !f.is_empty()) {
+        match is_sam_tag(field) {
+            true => tags.push(field),
+            // Tabs are not allowed in SAM tag values, so comment fields are joined by spaces.
+            false => {
+                if !comment.is_empty() {
+                    comment.push(b' ');
+                }
+                comment.extend_from_slice(field);
+            }
+        }
+    }
+
+    (name, tags, comment)
+}
+
+fn take<'a>(data: &mut &'a [u8], n: usize) -> Result<&'a [u8], AppError> {
+    if data.len() < n {
+        return Err(AppError::BamError("truncated aux data".to_string()));
+    }
+
+    let (head, tail) = data.split_at(n);
+    *data = tail;
+
+    Ok(head)
+}
+
+fn take_nul_terminated<'a>(data: &mut &'a [u8]) -> Result<&'a [u8], AppError> {
+    let end = data
+        .iter()
+        .position(|c| *c == 0)
+        .ok_or(AppError::BamError("unterminated aux string".to_string()))?;
+
+    let value = take(data, end)?;
+    take(data, 1)?;
+
+    Ok(value)
+}
+
+/// Size in bytes of numeric aux values, by type.
+fn numeric_size(value_type: u8) -> Option<usize> {
+    match value_type {
+        b'c' | b'C' => Some(1),
+        b's' | b'S' => Some(2),
+        b'i' | b'I' | b'f' => Some(4),
+        _ => None,
+    }
+}
+
+fn numeric_to_text(value_type: u8, bytes: &[u8]) -> String {
+    match value_type {
+        b'c' => (bytes[0] as i8).to_string(),
+        b'C' => bytes[0].to_string(),
+        b's' => i16::from_le_bytes([bytes[0], bytes[1]]).to_string(),
+        b'S' => u16::from_le_bytes([bytes[0], bytes[1]]).to_string(),
+        b'i' => i32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]).to_string(),
+        b'I' => u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]).to_string(),
+        _ => f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]).to_string(),
+    }
+}
+
+/// Parses binary BAM aux data into SAM text tags, e.g. `qs:i:12`.
+pub fn aux_to_text(mut data: &[u8]) -> Result<Vec<Vec<u8>>, AppError> {
+    let mut tags: Vec<Vec<u8>> = Vec::new();
+
+    while !data.is_empty() {
+        let header = take(&mut data, 3)?;
+        let (tag, value_type) = (&header[..2], header[2]);
+
+        let mut text: Vec<u8> = tag.to_vec();
+
+        match value_type {
+            b'A' => {
+                text.extend_from_slice(b":A:");
+                text.push(take(&mut data, 1)?[0]);
+            }
+            b'Z' | b'H' => {
+                text.extend_from_slice(&[b':', value_type, b':']);
+                text.extend_from_slice(take_nul_terminated(&mut data)?);
+            }
+            b'B' => {
+                let subtype = take(&mut data, 1)?[0];
+                let size = numeric_size(subtype).ok_or(AppError::BamError(format!(
+                    "invalid B array type {subtype}"
+                )))?;
+                let count = take(&mut data, 4)?;
+                let count = u32::from_le_bytes([count[0], count[1], count[2], count[3]]) as usize;
+
+                text.extend_from_slice(b":B:");
+                text.push(subtype);
+
+                for _ in 0..count {
+                    text.push(b',');
+                    let value = take(&mut data, size)?;
+                    text.extend_from_slice(numeric_to_text(subtype, value).as_bytes());
+                }
+            }
+            _ => {
+                let size = numeric_size(value_type)
+                    .ok_or(AppError::BamError(format!("invalid aux type {value_type}")))?;
+                let value = take(&mut data, size)?;
+
+                text.extend_from_slice(match value_type {
+                    b'f' => b":f:",
+                    _ => b":i:",
+                });
+                text.extend_from_slice(numeric_to_text(value_type, value).as_bytes());
+            }
+        }
+
+        tags.push(text);
+    }
+
+    Ok(tags)
+}
+
+fn parse_number<T: std::str::FromStr>(value: &[u8]) -> Result<T, AppError> {
+    std::str::from_utf8(value)?
+        .parse::<T>()
+        .map_err(|_| AppError::BamError(format!("invalid number in SAM tag: {value:?}")))
+}
+
+fn push_numeric(out: &mut Vec<u8>, value_type: u8, value: &[u8]) -> Result<(), AppError> {
+    match value_type {
+        b'c' => out.extend_from_slice(&parse_number::<i8>(value)?.to_le_bytes()),
+        b'C' => out.extend_from_slice(&parse_number::<u8>(value)?.to_le_bytes()),
+        b's' => out.extend_from_slice(&parse_number::<i16>(value)?.to_le_bytes()),
+        b'S' => out.extend_from_slice(&parse_number::<u16>(value)?.to_le_bytes()),
+        b'i' => out.extend_from_slice(&parse_number::<i32>(value)?.to_le_bytes()),
+        b'I' => out.extend_from_slice(&parse_number::<u32>(value)?.to_le_bytes()),
+        b'f' => out.extend_from_slice(&parse_number::<f32>(value)?.to_le_bytes()),
+        _ => {
+            return Err(AppError::BamError(format!(
+                "invalid numeric tag type {value_type}"
+            )));
+        }
+    }
+
+    Ok(())
+}
+
+/// Encodes a SAM text tag (e.g. `ML:B:C,12,200`) as binary BAM aux data.
+pub fn text_to_aux(tag: &[u8], out: &mut Vec<u8>) -> Result<(), AppError> {
+    if !is_sam_tag(tag) {
+        return Err(AppError::BamError(format!(
+            "invalid SAM tag `{}`",
+            String::from_utf8_lossy(tag)
+        )));
+    }
+
+    let (name, value_type, value) = (&tag[..2], tag[3], &tag[5..]);
+    out.extend_from_slice(name);
+
+    match value_type {
+        b'A' => {
+            out.push(b'A');
+            out.push(*value.first().unwrap_or(&b' '));
+        }
+        b'Z' | b'H' => {
+            out.push(value_type);
+            out.extend_from_slice(value);
+            out.push(0);
+        }
+        b'f' => {
+            out.push(b'f');
+            push_numeric(out, b'f', value)?;
+        }
+        b'i' => {
+            // Smallest type that fits, like samtools.
+            let v = parse_number::<i64>(value)?;
+            let value_type = match v {
+                0..=255 => b'C',
+                -128..0 => b'c',
+                256..=65535 => b'S',
+                -32768..-128 => b's',
+                65536..=4294967295 => b'I',
+                _ => b'i',
+            };
+            out.push(value_type);
+            push_numeric(out, value_type, value)?;
+        }
+        _ => {
+            // B array, `subtype,v1,v2,...`.
+            let mut fields = value.split(|c| *c == b',');
+            let subtype = fields
+                .next()
+                .and_then(|s| s.first().copied())
+                .unwrap_or(b'C');
+
+            if numeric_size(subtype).is_none() {
+                return Err(AppError::BamError(format!(
+                    "invalid B array type {subtype}"
+                )));
+            }
+
+            let values: Vec<&[u8]> = fields.collect();
+
+            out.push(b'B');
+            out.push(subtype);
+            out.extend_from_slice(&(values.len() as u32).to_le_bytes());
+
+            for v in values {
+                push_numeric(out, subtype, v)?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Inverse of `split_sam_header`. Only tags selected in `keep_tags` are kept,
+/// except `CO` which holds the original free text comment.
+pub fn join_sam_header<T: AsRef<[u8]>>(name: &[u8], tags: &[T], keep_tags: &KeepTags) -> Vec<u8> {
+    let mut header = name.to_vec();
+
+    for tag in tags.iter().map(|t| t.as_ref()) {
+        if tag.starts_with(b"CO:Z:") {
+            header.push(b' ');
+            header.extend_from_slice(&tag[5..]);
+        }
+    }
+
+    for tag in tags.iter().map(|t| t.as_ref()) {
+        if !tag.starts_with(b"CO:") && keep_tags.keep(&tag[..2]) {
+            header.push(b'\t');
+            header.extend_from_slice(tag);
+        }
+    }
+
+    header
+}
+
+#[rstest]
+#[case(b"qs:i:12", true)]
+#[case(b"MM:Z:C+m?,1;", true)]
+#[case(b"ML:B:C,12,200", true)]
+#[case(b"1:N:0:ATCACG", false)]
+#[case(b"ch=1", false)]
+#[case(b"qs:x:12", false)]
+fn test_is_sam_tag(#[case] field: &[u8], #[case] expected: bool) {
+    assert_eq!(is_sam_tag(field), expected);
+}
+
+#[rstest]
+#[case(b"read_1", b"read_1", vec![], b"")]
+#[case(b"read_1 1:N:0:ATCACG", b"read_1", vec![], b"1:N:0:ATCACG")]
+#[case(b"read_1\tqs:i:12\tch=1\tRG:Z:run 1", b"read_1", vec![&b"qs:i:12"[..], b"RG:Z:run 1"], b"ch=1")]
+fn test_split_sam_header(
+    #[case] header: &[u8],
+    #[case] expected_name: &[u8],
+    #[case] expected_tags: Vec<&[u8]>,
+    #[case] expected_comment: &[u8],
+) {
+    let (name, tags, comment) = split_sam_header(header);
+
+    assert_eq!(name, expected_name);
+    assert_eq!(tags, expected_tags);
+    assert_eq!(comment, expected_comment);
+}
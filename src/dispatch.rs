@@ -1,42 +1,174 @@
 use crate::args::{App, SubCommand};
+use crate::bam::KeepTags;
 use crate::concat::fastq_concat;
 use crate::errors::AppError;
+use crate::evaluate::fastq_evaluate;
+use crate::extract_umi::fastq_extract_umi;
+use crate::fa2fq::fastq_fa2fq;
+use crate::fetch::fastq_fetch;
 use crate::filter::fastq_filter;
 use crate::fq2fa::fastq_fq2fa;
 use crate::fq2tab::fastq_fq2tab;
 use crate::grep::fastq_grep;
-use crate::head::fastq_head;
+use crate::head::{fastq_head, fastq_range};
+use crate::index::fastq_index;
+use crate::interleave::{fastq_deinterleave, fastq_interleave};
+use crate::locate::fastq_locate;
+use crate::merge_pairs::fastq_merge_pairs;
+use crate::mock::barcodes::BarcodeSimulator;
 use crate::mock::fastq_mock;
+use crate::pipeline::fastq_pipeline;
+use crate::profile::fastq_profile;
 use crate::renumber::fastq_renumber;
 use crate::sample::fastq_sample;
 use crate::sanitize::fastq_sanitize;
+use crate::seq::fastq_seq;
 use crate::sort::fastq_sort;
+use crate::split::{SplitBy, fastq_split};
 use crate::stats::fastq_stats;
+use crate::subseq::fastq_subseq;
+use crate::tab2fq::fastq_tab2fq;
+use crate::tail::fastq_tail;
 use crate::trim::fastq_trim;
+use crate::umi_dedup::fastq_umi_dedup;
 
 pub fn dispatch(args: App) -> Result<(), AppError> {
+    let keep_tags = KeepTags::new(&args.global_opts.keep_tags)?;
+
     match args.command {
         SubCommand::Stats { fastq, outfile } => {
             let _ = fastq_stats(fastq, outfile)?;
         }
         SubCommand::Sanitize { fastq, outfile } => fastq_sanitize(fastq, outfile)?,
+        SubCommand::Seq {
+            fastq,
+            revcomp,
+            complement,
+            reverse,
+            upper,
+            lower,
+            rna,
+            dna,
+            mask_lowqual,
+            outfile,
+        } => fastq_seq(
+            fastq,
+            &keep_tags,
+            revcomp,
+            complement,
+            reverse,
+            upper,
+            lower,
+            rna,
+            dna,
+            mask_lowqual,
+            outfile,
+        )?,
         SubCommand::Head {
+            fastq,
+            num_reads,
+            skip,
+            outfile,
+        } => fastq_head(fastq, &keep_tags, num_reads, skip, outfile)?,
+        SubCommand::Tail {
             fastq,
             num_reads,
             outfile,
-        } => fastq_head(fastq, num_reads, outfile)?,
+        } => fastq_tail(fastq, &keep_tags, num_reads, outfile)?,
+        SubCommand::Range {
+            fastq,
+            start,
+            end,
+            outfile,
+        } => fastq_range(fastq, &keep_tags, start, end, outfile)?,
         SubCommand::Grep {
             fastq,
             pattern,
             outfile,
-        } => fastq_grep(fastq, pattern, outfile)?,
+        } => fastq_grep(fastq, &keep_tags, pattern, outfile)?,
+        SubCommand::Locate {
+            fastq,
+            patterns,
+            mode,
+            max_mismatches,
+            outfile,
+        } => fastq_locate(fastq, patterns, mode, max_mismatches, outfile)?,
         SubCommand::Concat { fastqs, outfile } => fastq_concat(fastqs, outfile)?,
+        SubCommand::Split {
+            fastq,
+            by_reads,
+            by_bases,
+            parts,
+            parts_mode,
+            by_field,
+            outdir,
+        } => {
+            // Exactly one is set, which is enforced by clap.
+            let split_by = match (by_reads, by_bases, parts, by_field) {
+                (Some(n), _, _, _) => SplitBy::Reads(n),
+                (_, Some(n), _, _) => SplitBy::Bases(n),
+                (_, _, Some(n), _) => SplitBy::Parts(n, parts_mode),
+                (_, _, _, Some(key)) => SplitBy::Field(key),
+                _ => unreachable!(),
+            };
+
+            fastq_split(fastq, &keep_tags, split_by, outdir)?
+        }
+        SubCommand::Index { fastq, outfile } => fastq_index(fastq, outfile)?,
+        SubCommand::Fetch {
+            fastq,
+            index,
+            ids,
+            id_file,
+            outfile,
+        } => fastq_fetch(fastq, index, ids, id_file, outfile)?,
+        SubCommand::Subseq {
+            fastq,
+            region,
+            bed,
+            outfile,
+        } => fastq_subseq(fastq, &keep_tags, region, bed, outfile)?,
+        SubCommand::Interleave {
+            fastq_1,
+            fastq_2,
+            outfile,
+        } => fastq_interleave(fastq_1, fastq_2, outfile)?,
+        SubCommand::Deinterleave {
+            fastq,
+            outfile_1,
+            outfile_2,
+        } => fastq_deinterleave(fastq, outfile_1, outfile_2)?,
+        SubCommand::MergePairs {
+            fastq_1,
+            fastq_2,
+            min_overlap,
+            max_mismatch_rate,
+            allow_dovetail,
+            outfile,
+            unmerged_1,
+            unmerged_2,
+            report,
+        } => {
+            let _ = fastq_merge_pairs(
+                fastq_1,
+                fastq_2,
+                min_overlap,
+                max_mismatch_rate,
+                allow_dovetail,
+                outfile,
+                unmerged_1,
+                unmerged_2,
+                report,
+            )?;
+        }
         SubCommand::Filter {
             fastq,
             min_len,
             max_len,
             min_error,
             max_error,
+            min_gc,
+            max_gc,
             min_softmasked,
             max_softmasked,
             min_ambiguous,
@@ -44,10 +176,13 @@ pub fn dispatch(args: App) -> Result<(), AppError> {
             outfile,
         } => fastq_filter(
             fastq,
+            &keep_tags,
             min_len,
             max_len,
             min_error,
             max_error,
+            min_gc,
+            max_gc,
             min_softmasked,
             max_softmasked,
             min_ambiguous,
@@ -62,20 +197,67 @@ pub fn dispatch(args: App) -> Result<(), AppError> {
             barcode_forward,
             barcode_reverse,
             max_mismatches,
+            max_indels,
             barcode_margin,
+            amplicon,
             outfile,
             barcodes_tsv,
         } => fastq_trim(
             fastq,
+            &keep_tags,
             min_len,
             trim_start,
             trim_end,
             barcode_forward,
             barcode_reverse,
             max_mismatches,
+            max_indels,
             barcode_margin,
+            amplicon,
             outfile,
-            barcodes_tsv,
+            Some(barcodes_tsv),
+        )?,
+        SubCommand::ExtractUmi {
+            fastq,
+            pattern,
+            position,
+            barcode_forward,
+            max_mismatches,
+            max_indels,
+            barcode_margin,
+            umi_format,
+            outfile,
+            no_umi_outfile,
+        } => fastq_extract_umi(
+            fastq,
+            &keep_tags,
+            pattern,
+            position,
+            barcode_forward,
+            max_mismatches,
+            max_indels,
+            barcode_margin,
+            umi_format,
+            outfile,
+            no_umi_outfile,
+        )?,
+        SubCommand::UmiDedup {
+            fastq,
+            method,
+            max_distance,
+            kmer_size,
+            consensus,
+            outfile,
+            group_stats,
+        } => fastq_umi_dedup(
+            fastq,
+            &keep_tags,
+            method,
+            max_distance,
+            kmer_size,
+            consensus,
+            outfile,
+            group_stats,
         )?,
         SubCommand::Sort {
             fastq,
@@ -88,6 +270,7 @@ pub fn dispatch(args: App) -> Result<(), AppError> {
             outfile,
         } => fastq_sort(
             fastq,
+            &keep_tags,
             &by,
             reverse,
             window_size,
@@ -97,20 +280,121 @@ pub fn dispatch(args: App) -> Result<(), AppError> {
             outfile,
         )?,
         SubCommand::Fq2Fa { fastq, outfile } => fastq_fq2fa(fastq, outfile)?,
-        SubCommand::Fq2Tab { fastq, outfile } => fastq_fq2tab(fastq, outfile)?,
-        SubCommand::Sample { fastq, by, outfile } => fastq_sample(fastq, by, outfile)?,
+        SubCommand::Fa2Fq {
+            fasta,
+            phred,
+            qual,
+            outfile,
+        } => fastq_fa2fq(fasta, phred, qual, outfile)?,
+        SubCommand::Fq2Tab {
+            fastq,
+            columns,
+            lossless,
+            outfile,
+        } => fastq_fq2tab(fastq, &keep_tags, columns, lossless, outfile)?,
+        SubCommand::Tab2Fq { tsv, outfile } => fastq_tab2fq(tsv, outfile)?,
+        SubCommand::Sample { fastq, by, outfile } => fastq_sample(fastq, &keep_tags, by, outfile)?,
+        SubCommand::Evaluate {
+            truth,
+            barcodes_tsv,
+            outfile,
+            barcodes_report,
+            confusion_report,
+        } => {
+            let _ = fastq_evaluate(
+                truth,
+                barcodes_tsv,
+                outfile,
+                barcodes_report,
+                confusion_report,
+            )?;
+        }
+        SubCommand::Profile {
+            fastq,
+            max_positions,
+            outfile,
+        } => {
+            let _ = fastq_profile(fastq, max_positions, outfile)?;
+        }
         SubCommand::Mock {
             num_reads,
             min_len,
             max_len,
+            length_dist,
+            profile,
             phred,
             prefix_seq,
             suffix_seq,
+            error_model,
+            seed,
+            truth,
+            reference,
+            coverage,
+            paired,
+            insert_dist,
+            barcodes,
+            barcode_mutation_rate,
+            adapter_truncation_rate,
+            random_orientation,
+            chimera_rate,
+            barcodes_truth,
             outfile,
+            outfile_2,
         } => fastq_mock(
-            num_reads, min_len, max_len, phred, prefix_seq, suffix_seq, outfile,
+            num_reads,
+            min_len,
+            max_len,
+            length_dist,
+            profile,
+            phred,
+            prefix_seq,
+            suffix_seq,
+            error_model,
+            seed,
+            truth,
+            reference,
+            coverage,
+            paired,
+            insert_dist,
+            barcodes
+                .map(|sheet| {
+                    BarcodeSimulator::new(
+                        sheet,
+                        barcode_mutation_rate,
+                        adapter_truncation_rate,
+                        random_orientation,
+                        chimera_rate,
+                    )
+                })
+                .transpose()?,
+            barcodes_truth,
+            outfile,
+            outfile_2,
         )?,
-        SubCommand::Renumber { fastq, outfile } => fastq_renumber(fastq, outfile)?,
+        SubCommand::Renumber {
+            fastq,
+            template,
+            prefix,
+            sample,
+            keep_header,
+            mapping,
+            outfile,
+        } => fastq_renumber(
+            fastq,
+            &keep_tags,
+            template,
+            prefix,
+            sample,
+            keep_header,
+            mapping,
+            outfile,
+        )?,
+        SubCommand::Pipeline {
+            fastq,
+            recipe,
+            outfile,
+            report,
+        } => fastq_pipeline(fastq, &keep_tags, recipe, outfile, report)?,
     }
 
     Ok(())
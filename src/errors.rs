@@ -3,6 +3,7 @@ use std::str::Utf8Error;
 use bio_utils_rs::errors::BioError;
 use thiserror::Error;
 
+#[allow(clippy::enum_variant_names)]
 #[derive(Debug, Error)]
 pub enum AppError {
     #[error("Invalid sampling factor: {0}")]
@@ -14,9 +15,30 @@ pub enum AppError {
     #[error("Utf8 encoding error")]
     Utf8EncodingError(String),
 
-    #[error("Invalid argument")]
+    #[error("Invalid argument: {0}")]
     InvalidArgumentError(String),
 
+    #[error("Input has no quality scores (FASTA), which is required by: {0}")]
+    MissingQualityError(String),
+
+    #[error("Qual file error: {0}")]
+    QualFileError(String),
+
+    #[error("Index error: {0}")]
+    IndexError(String),
+
+    #[error("Paired read error: {0}")]
+    PairingError(String),
+
+    #[error("SAM/BAM error: {0}")]
+    BamError(String),
+
+    #[error("Table write error: {0}")]
+    TableWriteError(String),
+
+    #[error("Recipe parsing error: {0}")]
+    RecipeParsingError(String),
+
     #[error(transparent)]
     BioError(#[from] BioError),
 }
@@ -38,3 +60,17 @@ impl From<Utf8Error> for AppError {
         AppError::Utf8EncodingError(err.to_string())
     }
 }
+
+#[cfg(feature = "parquet")]
+impl From<arrow_schema::ArrowError> for AppError {
+    fn from(err: arrow_schema::ArrowError) -> Self {
+        AppError::TableWriteError(err.to_string())
+    }
+}
+
+#[cfg(feature = "parquet")]
+impl From<parquet::errors::ParquetError> for AppError {
+    fn from(err: parquet::errors::ParquetError) -> Self {
+        AppError::TableWriteError(err.to_string())
+    }
+}
@@ -0,0 +1,479 @@
+use crate::errors::AppError;
+use crate::fastx::text_reader;
+use crate::table::{ColumnType, TableColumn, TableFormat, TableValue, table_writer};
+use bio_utils_rs::io::write_json;
+use bio_utils_rs::nucleotide::reverse_complement;
+use rstest::rstest;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::io::BufRead;
+use std::path::{Path, PathBuf};
+
+/// Label for reads without a (matching) barcode or sample.
+const UNASSIGNED: &str = "unassigned";
+/// Truth label for chimeric reads, which can not be assigned correctly.
+const CHIMERA: &str = "chimera";
+
+/// Barcodes and barcode spans (0-based, end exclusive) of a read, either true or as
+/// found by `trim`. Barcodes are 5' -> 3', like in the barcode sheet.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct BarcodeCall {
+    pub orientation: Option<String>,
+    pub barcode_forward: Option<String>,
+    pub barcode_reverse: Option<String>,
+    pub forward: Option<(usize, usize)>,
+    pub reverse: Option<(usize, usize)>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TruthRecord {
+    pub sample: String,
+    pub chimera: bool,
+    pub call: BarcodeCall,
+}
+
+#[derive(Debug, Serialize, PartialEq)]
+pub struct BarcodeMetrics {
+    pub side: String,
+    pub barcode: String,
+    pub true_positives: usize,
+    pub false_positives: usize,
+    pub false_negatives: usize,
+    pub precision: Option<f64>,
+    pub recall: Option<f64>,
+}
+
+/// Difference between the found and the true trim position (found - true), for reads
+/// with a correctly identified barcode. For the forward barcode the trim position is
+/// the barcode end, for the reverse barcode the barcode start.
+#[derive(Debug, Default, Serialize, PartialEq)]
+pub struct PositionErrors {
+    pub side: String,
+    pub num_reads: usize,
+    pub exact: usize,
+    pub mean_absolute_error: Option<f64>,
+    pub histogram: BTreeMap<i64, usize>,
+}
+
+#[derive(Debug, Default, Serialize, PartialEq)]
+pub struct EvaluationReport {
+    pub num_reads: usize,
+    /// Reads in the trim report.
+    pub num_trimmed: usize,
+    /// Reads in the trim report without truth.
+    pub num_unknown: usize,
+    pub num_chimeras: usize,
+    pub correct_orientation: usize,
+    pub correct_sample: usize,
+    pub barcodes: Vec<BarcodeMetrics>,
+    /// True sample -> assigned sample -> number of reads.
+    pub confusion: BTreeMap<String, BTreeMap<String, usize>>,
+    pub position_errors: Vec<PositionErrors>,
+}
+
+/// Reads a tsv with header into rows of (column -> value). `N/A` and empty values
+/// are left out.
+fn read_tsv_rows(path: &Path) -> Result<Vec<HashMap<String, String>>, AppError> {
+    if TableFormat::from_path(Some(path)) != TableFormat::Text {
+        return Err(AppError::InvalidArgumentError(format!(
+            "{} is not a tsv file, evaluate only reads tsv truth and barcode tables",
+            path.display()
+        )));
+    }
+
+    let reader = text_reader(Some(path.to_path_buf()))?;
+    let mut lines = reader.lines();
+
+    let header: Vec<String> = match lines.next() {
+        Some(header) => header?.trim_end().split('\t').map(String::from).collect(),
+        None => return Ok(Vec::new()),
+    };
+
+    let mut rows = Vec::new();
+
+    for line in lines {
+        let line = line?;
+
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let row: HashMap<String, String> = header
+            .iter()
+            .zip(line.trim_end_matches(['\r', '\n']).split('\t'))
+            .filter(|(_, value)| !value.is_empty() && *value != "N/A")
+            .map(|(column, value)| (column.clone(), value.to_string()))
+            .collect();
+
+        rows.push(row);
+    }
+
+    Ok(rows)
+}
+
+fn required<'a>(row: &'a HashMap<String, String>, column: &str) -> Result<&'a str, AppError> {
+    row.get(column).map(|v| v.as_str()).ok_or_else(|| {
+        AppError::InvalidArgumentError(format!("missing value for column `{column}`"))
+    })
+}
+
+fn parse_span(
+    row: &HashMap<String, String>,
+    side: &str,
+) -> Result<Option<(usize, usize)>, AppError> {
+    let parse = |column: String| -> Result<Option<usize>, AppError> {
+        row.get(&column)
+            .map(|v| {
+                v.parse().map_err(|_| {
+                    AppError::InvalidArgumentError(format!("invalid value `{v}` in `{column}`"))
+                })
+            })
+            .transpose()
+    };
+
+    let start = parse(format!("barcode_{side}_start"))?;
+    let end = parse(format!("barcode_{side}_end"))?;
+
+    Ok(start.zip(end))
+}
+
+/// `reverse_complemented` is for the trim report, which has the reverse barcode as
+/// searched for (reverse complemented).
+fn parse_call(
+    row: &HashMap<String, String>,
+    reverse_complemented: bool,
+) -> Result<BarcodeCall, AppError> {
+    let barcode_reverse = row
+        .get("barcode_reverse")
+        .map(|b| match reverse_complemented {
+            true => String::from_utf8_lossy(&reverse_complement(b.as_bytes())).to_string(),
+            false => b.clone(),
+        });
+
+    Ok(BarcodeCall {
+        orientation: row.get("orientation").cloned(),
+        barcode_forward: row.get("barcode_forward").cloned(),
+        barcode_reverse,
+        forward: parse_span(row, "forward")?,
+        reverse: parse_span(row, "reverse")?,
+    })
+}
+
+fn ratio(numerator: usize, denominator: usize) -> Option<f64> {
+    (denominator > 0).then(|| numerator as f64 / denominator as f64)
+}
+
+/// Scores barcode calls against the truth. Reads without a call count as unassigned.
+pub fn evaluate(
+    truth: &HashMap<String, TruthRecord>,
+    calls: &HashMap<String, BarcodeCall>,
+) -> EvaluationReport {
+    let mut report = EvaluationReport {
+        num_reads: truth.len(),
+        num_trimmed: calls.len(),
+        num_unknown: calls.keys().filter(|id| !truth.contains_key(*id)).count(),
+        ..Default::default()
+    };
+
+    // Sample per barcode pair, from the non-chimeric truth.
+    let samples: HashMap<(Option<&str>, Option<&str>), &str> = truth
+        .values()
+        .filter(|t| !t.chimera)
+        .map(|t| {
+            (
+                (
+                    t.call.barcode_forward.as_deref(),
+                    t.call.barcode_reverse.as_deref(),
+                ),
+                t.sample.as_str(),
+            )
+        })
+        .collect();
+
+    // (side, barcode) -> (tp, fp, fn)
+    let mut counts: BTreeMap<(&str, String), (usize, usize, usize)> = BTreeMap::new();
+    let mut position_errors: [Vec<i64>; 2] = [Vec::new(), Vec::new()];
+
+    let no_call = BarcodeCall::default();
+
+    for (id, t) in truth {
+        let call = calls.get(id).unwrap_or(&no_call);
+
+        report.num_chimeras += t.chimera as usize;
+
+        if call.orientation.is_some() && call.orientation == t.call.orientation {
+            report.correct_orientation += 1;
+        }
+
+        let true_sample = match t.chimera {
+            true => CHIMERA,
+            false => t.sample.as_str(),
+        };
+        let assigned_sample = match (&call.barcode_forward, &call.barcode_reverse) {
+            (None, None) => UNASSIGNED,
+            (forward, reverse) => samples
+                .get(&(forward.as_deref(), reverse.as_deref()))
+                .copied()
+                .unwrap_or(UNASSIGNED),
+        };
+
+        report.correct_sample += (true_sample == assigned_sample) as usize;
+        *report
+            .confusion
+            .entry(true_sample.to_string())
+            .or_default()
+            .entry(assigned_sample.to_string())
+            .or_insert(0) += 1;
+
+        let sides = [
+            (
+                "forward",
+                &t.call.barcode_forward,
+                &call.barcode_forward,
+                t.call.forward.map(|(_, end)| end),
+                call.forward.map(|(_, end)| end),
+            ),
+            (
+                "reverse",
+                &t.call.barcode_reverse,
+                &call.barcode_reverse,
+                t.call.reverse.map(|(start, _)| start),
+                call.reverse.map(|(start, _)| start),
+            ),
+        ];
+
+        for (i, (side, true_barcode, called_barcode, true_pos, called_pos)) in
+            sides.into_iter().enumerate()
+        {
+            if true_barcode.is_some() && true_barcode == called_barcode {
+                let c = counts
+                    .entry((side, true_barcode.clone().unwrap()))
+                    .or_default();
+                c.0 += 1;
+
+                if let (Some(true_pos), Some(called_pos)) = (true_pos, called_pos) {
+                    position_errors[i].push(called_pos as i64 - true_pos as i64);
+                }
+
+                continue;
+            }
+
+            if let Some(barcode) = called_barcode {
+                counts.entry((side, barcode.clone())).or_default().1 += 1;
+            }
+
+            if let Some(barcode) = true_barcode {
+                counts.entry((side, barcode.clone())).or_default().2 += 1;
+            }
+        }
+    }
+
+    report.barcodes = counts
+        .into_iter()
+        .map(|((side, barcode), (tp, fp, fn_))| BarcodeMetrics {
+            side: side.to_string(),
+            barcode,
+            true_positives: tp,
+            false_positives: fp,
+            false_negatives: fn_,
+            precision: ratio(tp, tp + fp),
+            recall: ratio(tp, tp + fn_),
+        })
+        .collect();
+
+    report.position_errors = ["forward", "reverse"]
+        .into_iter()
+        .zip(position_errors)
+        .map(|(side, errors)| {
+            let mut histogram: BTreeMap<i64, usize> = BTreeMap::new();
+            errors
+                .iter()
+                .for_each(|e| *histogram.entry(*e).or_insert(0) += 1);
+
+            PositionErrors {
+                side: side.to_string(),
+                num_reads: errors.len(),
+                exact: errors.iter().filter(|&&e| e == 0).count(),
+                mean_absolute_error: (!errors.is_empty()).then(|| {
+                    errors.iter().map(|e| e.unsigned_abs()).sum::<u64>() as f64
+                        / errors.len() as f64
+                }),
+                histogram,
+            }
+        })
+        .collect();
+
+    report
+}
+
+fn write_barcodes_report(report: &EvaluationReport, outfile: PathBuf) -> Result<(), AppError> {
+    let mut writer = table_writer(
+        Some(outfile),
+        vec![
+            TableColumn::new("side", ColumnType::DictUtf8),
+            TableColumn::new("barcode", ColumnType::DictUtf8),
+            TableColumn::new("true_positives", ColumnType::UInt64),
+            TableColumn::new("false_positives", ColumnType::UInt64),
+            TableColumn::new("false_negatives", ColumnType::UInt64),
+            TableColumn::new("precision", ColumnType::Float64),
+            TableColumn::new("recall", ColumnType::Float64),
+        ],
+    )?;
+
+    for m in &report.barcodes {
+        writer.write_row(&[
+            TableValue::Str(m.side.as_bytes()),
+            TableValue::Str(m.barcode.as_bytes()),
+            TableValue::UInt(m.true_positives as u64),
+            TableValue::UInt(m.false_positives as u64),
+            TableValue::UInt(m.false_negatives as u64),
+            m.precision
+                .map(TableValue::Float)
+                .unwrap_or(TableValue::Null),
+            m.recall.map(TableValue::Float).unwrap_or(TableValue::Null),
+        ])?;
+    }
+
+    writer.finish()
+}
+
+fn write_confusion_report(report: &EvaluationReport, outfile: PathBuf) -> Result<(), AppError> {
+    let mut writer = table_writer(
+        Some(outfile),
+        vec![
+            TableColumn::new("true_sample", ColumnType::DictUtf8),
+            TableColumn::new("assigned_sample", ColumnType::DictUtf8),
+            TableColumn::new("num_reads", ColumnType::UInt64),
+        ],
+    )?;
+
+    for (true_sample, assigned) in &report.confusion {
+        for (assigned_sample, count) in assigned {
+            writer.write_row(&[
+                TableValue::Str(true_sample.as_bytes()),
+                TableValue::Str(assigned_sample.as_bytes()),
+                TableValue::UInt(*count as u64),
+            ])?;
+        }
+    }
+
+    writer.finish()
+}
+
+/// # Arguments
+/// * `truth` - Barcodes truth tsv from `mock --barcodes`.
+/// * `barcodes_tsv` - Barcodes report tsv from `trim`.
+/// * `outfile` - Where to write the json report (optional, defaults to stdout).
+/// * `barcodes_report` - Per barcode precision/recall table.
+/// * `confusion_report` - Sample confusion matrix table (long format).
+pub fn fastq_evaluate(
+    truth: PathBuf,
+    barcodes_tsv: PathBuf,
+    outfile: Option<PathBuf>,
+    barcodes_report: PathBuf,
+    confusion_report: PathBuf,
+) -> Result<EvaluationReport, AppError> {
+    let mut truth_records: HashMap<String, TruthRecord> = HashMap::new();
+
+    for row in read_tsv_rows(&truth)? {
+        truth_records.insert(
+            required(&row, "read_name")?.to_string(),
+            TruthRecord {
+                sample: required(&row, "sample")?.to_string(),
+                chimera: row.contains_key("chimera_sample"),
+                call: parse_call(&row, false)?,
+            },
+        );
+    }
+
+    let mut calls: HashMap<String, BarcodeCall> = HashMap::new();
+
+    for row in read_tsv_rows(&barcodes_tsv)? {
+        calls.insert(
+            required(&row, "read_name")?.to_string(),
+            parse_call(&row, true)?,
+        );
+    }
+
+    let report = evaluate(&truth_records, &calls);
+
+    write_barcodes_report(&report, barcodes_report)?;
+    write_confusion_report(&report, confusion_report)?;
+    write_json(outfile, &report)?;
+
+    Ok(report)
+}
+
+#[rstest]
+#[case(9, 2)]
+#[case(8, 3)]
+fn test_evaluate(#[case] r1_end: usize, #[case] expected_exact: usize) {
+    let call = |forward: Option<&str>, reverse: Option<&str>, end: usize| BarcodeCall {
+        orientation: Some("forward".to_string()),
+        barcode_forward: forward.map(String::from),
+        barcode_reverse: reverse.map(String::from),
+        forward: forward.map(|_| (0, end)),
+        reverse: reverse.map(|_| (100, 108)),
+    };
+    let truth_record = |sample: &str, forward: &str, reverse: &str, chimera: bool| TruthRecord {
+        sample: sample.to_string(),
+        chimera,
+        call: call(Some(forward), Some(reverse), 8),
+    };
+
+    let truth: HashMap<String, TruthRecord> = [
+        ("r1", truth_record("s1", "AAAA", "CCCC", false)),
+        ("r2", truth_record("s1", "AAAA", "CCCC", false)),
+        ("r3", truth_record("s2", "GGGG", "TTTT", false)),
+        ("r4", truth_record("s2", "GGGG", "TTTT", true)),
+    ]
+    .into_iter()
+    .map(|(id, t)| (id.to_string(), t))
+    .collect();
+
+    let calls: HashMap<String, BarcodeCall> = [
+        // Correct, trimmed at `r1_end` (the true end is 8).
+        ("r1", call(Some("AAAA"), Some("CCCC"), r1_end)),
+        // Reverse barcode missed.
+        ("r2", call(Some("AAAA"), None, 8)),
+        // Misassigned.
+        ("r3", call(Some("AAAA"), Some("CCCC"), 8)),
+        // Chimera assigned to a sample.
+        ("r4", call(Some("GGGG"), Some("TTTT"), 8)),
+    ]
+    .into_iter()
+    .map(|(id, c)| (id.to_string(), c))
+    .collect();
+
+    let report = evaluate(&truth, &calls);
+
+    assert_eq!(report.num_reads, 4);
+    assert_eq!(report.num_chimeras, 1);
+    assert_eq!(report.correct_sample, 1);
+    assert_eq!(report.confusion["s1"][UNASSIGNED], 1);
+    assert_eq!(report.confusion["s2"]["s1"], 1);
+    assert_eq!(report.confusion[CHIMERA]["s2"], 1);
+
+    let forward_a = report
+        .barcodes
+        .iter()
+        .find(|m| m.side == "forward" && m.barcode == "AAAA")
+        .unwrap();
+    assert_eq!(
+        (
+            forward_a.true_positives,
+            forward_a.false_positives,
+            forward_a.false_negatives
+        ),
+        (2, 1, 0)
+    );
+    assert_eq!(forward_a.recall, Some(1.0));
+
+    let forward_errors = &report.position_errors[0];
+    assert_eq!(forward_errors.num_reads, 3);
+    assert_eq!(forward_errors.exact, expected_exact);
+    assert_eq!(
+        forward_errors.histogram.get(&1).copied().unwrap_or(0),
+        3 - expected_exact
+    );
+}
@@ -0,0 +1,2 @@
+pub mod evaluate;
+pub use evaluate::{EvaluationReport, fastq_evaluate};
@@ -0,0 +1,283 @@
+use crate::args::UmiFormat;
+use crate::bam::KeepTags;
+use crate::bam::tags::split_sam_header;
+use crate::errors::AppError;
+use crate::fastx::{FastxRecord, fastx_records, fastx_writer};
+use crate::locate::iupac_bases;
+#[cfg(test)]
+use crate::test_utils::TempFile;
+use crate::trim::ReadTrimmer;
+use log::warn;
+use rstest::rstest;
+use std::path::PathBuf;
+
+/// Extracts a UMI at a fixed position of the read, optionally relative to the end of
+/// the forward barcode.
+pub struct UmiExtractor {
+    /// IUPAC pattern the UMI has to match, e.g. `NNNNNNNN`.
+    pub pattern: Vec<u8>,
+    /// Offset of the UMI from the start of the read (or from the end of the forward barcode).
+    pub position: usize,
+    /// Locates the forward barcode the UMI is anchored to.
+    pub anchor: Option<ReadTrimmer>,
+}
+
+impl UmiExtractor {
+    pub fn new(
+        pattern: &str,
+        position: usize,
+        anchor: Option<ReadTrimmer>,
+    ) -> Result<Self, AppError> {
+        let pattern = pattern.to_ascii_uppercase().into_bytes();
+
+        if pattern.is_empty() || pattern.iter().any(|&nt| iupac_bases(nt).is_none()) {
+            return Err(AppError::InvalidArgumentError(format!(
+                "invalid UMI pattern `{}`",
+                String::from_utf8_lossy(&pattern)
+            )));
+        }
+
+        Ok(Self {
+            pattern,
+            position,
+            anchor,
+        })
+    }
+
+    /// Removes the UMI (and its qualities) from the read and returns it. Returns `None`
+    /// (leaving the read as is) if the barcode is not found, the read is too short or
+    /// the UMI does not match the pattern.
+    pub fn extract(&self, record: &mut FastxRecord) -> Option<Vec<u8>> {
+        let anchor = match &self.anchor {
+            Some(trimmer) => trimmer.trim(&record.seq)?.barcode_forward?.end,
+            None => 0,
+        };
+
+        let start = anchor + self.position;
+        let end = start + self.pattern.len();
+
+        if end > record.seq.len() {
+            return None;
+        }
+
+        let umi = record.seq[start..end].to_ascii_uppercase();
+
+        let matches = umi.iter().zip(self.pattern.iter()).all(|(nt, code)| {
+            *code == b'N' || iupac_bases(*code).is_some_and(|bases| bases.contains(nt))
+        });
+
+        if !matches {
+            return None;
+        }
+
+        record.seq.drain(start..end);
+        if let Some(qual) = record.qual.as_mut() {
+            qual.drain(start..end);
+        }
+
+        Some(umi)
+    }
+}
+
+/// Adds the UMI to a read header, either appended to the read name (`read_1_ACGT`, like
+/// umi_tools) or as a `RX:Z:` SAM tag field (replacing an existing `RX` tag).
+pub fn add_umi(header: &[u8], umi: &[u8], format: UmiFormat) -> Vec<u8> {
+    let mut new_header: Vec<u8> = Vec::with_capacity(header.len() + umi.len() + 6);
+
+    match format {
+        UmiFormat::Underscore => {
+            let name_end = header
+                .iter()
+                .position(|c| c.is_ascii_whitespace())
+                .unwrap_or(header.len());
+
+            new_header.extend_from_slice(&header[..name_end]);
+            new_header.push(b'_');
+            new_header.extend_from_slice(umi);
+            new_header.extend_from_slice(&header[name_end..]);
+        }
+        UmiFormat::Rx => {
+            for (i, field) in header.split(|&c| c == b'\t').enumerate() {
+                if i > 0 && field.starts_with(b"RX:Z:") {
+                    continue;
+                }
+                if i > 0 {
+                    new_header.push(b'\t');
+                }
+                new_header.extend_from_slice(field);
+            }
+            new_header.extend_from_slice(b"\tRX:Z:");
+            new_header.extend_from_slice(umi);
+        }
+    }
+
+    new_header
+}
+
+/// UMI of a read, from a `RX:Z:` tag or else the last `_` separated part of the read name,
+/// if that is a nucleotide sequence (so `read_1` has no UMI).
+pub fn read_umi(header: &[u8]) -> Option<&[u8]> {
+    let (name, tags, _) = split_sam_header(header);
+
+    if let Some(tag) = tags.iter().find(|tag| tag.starts_with(b"RX:Z:")) {
+        return Some(&tag[5..]);
+    }
+
+    name.iter()
+        .rposition(|&c| c == b'_')
+        .map(|i| &name[i + 1..])
+        .filter(|umi| !umi.is_empty() && umi.iter().all(|c| b"ACGTN".contains(c)))
+}
+
+/// # Arguments
+/// * `fastq` - Path to FASTQ/FASTA file (optional, defaults to stdin).
+/// * `keep_tags` - SAM/BAM aux tags to keep in the read headers.
+/// * `pattern` - IUPAC pattern of the UMI, e.g. `NNNNNNNN`.
+/// * `position` - Offset of the UMI from the read start (or the forward barcode end).
+/// * `barcode_forward` - Forward barcodes to anchor the UMI to, located like in `trim`.
+/// * `umi_format` - How the UMI is added to the read header.
+/// * `outfile` - Output file (optional, defaults to stdout).
+/// * `no_umi_outfile` - Output file for reads without UMI (optional, by default they are dropped).
+pub fn fastq_extract_umi(
+    fastq: Option<PathBuf>,
+    keep_tags: &KeepTags,
+    pattern: String,
+    position: usize,
+    barcode_forward: Option<Vec<String>>,
+    max_mismatches: u8,
+    max_indels: u8,
+    barcode_margin: usize,
+    umi_format: UmiFormat,
+    outfile: Option<PathBuf>,
+    no_umi_outfile: Option<PathBuf>,
+) -> Result<(), AppError> {
+    let anchor = barcode_forward.map(|barcodes| {
+        ReadTrimmer::new(
+            0,
+            0,
+            Some(barcodes),
+            None,
+            max_mismatches,
+            max_indels,
+            barcode_margin,
+            false,
+        )
+    });
+    let extractor = UmiExtractor::new(&pattern, position, anchor)?;
+
+    let records = fastx_records(fastq, keep_tags)?;
+    let mut writer = fastx_writer(outfile)?;
+    let mut no_umi_writer = no_umi_outfile
+        .map(|path| fastx_writer(Some(path)))
+        .transpose()?;
+    let mut num_no_umi: usize = 0;
+
+    for record in records {
+        let mut record = record?;
+
+        match extractor.extract(&mut record) {
+            Some(umi) => {
+                record.id = add_umi(&record.id, &umi, umi_format);
+                writer.write_record(&record)?;
+            }
+            None => {
+                num_no_umi += 1;
+                if let Some(no_umi_writer) = no_umi_writer.as_mut() {
+                    no_umi_writer.write_record(&record)?;
+                }
+            }
+        }
+    }
+
+    writer.finish()?;
+
+    match no_umi_writer {
+        Some(no_umi_writer) => no_umi_writer.finish()?,
+        None if num_no_umi > 0 => warn!("Dropped {num_no_umi} reads without UMI"),
+        None => {}
+    }
+
+    Ok(())
+}
+
+#[rstest]
+#[case("NNNN", 0, None, b"ACGTTTTTTT".as_slice(), Some(b"ACGT".as_slice()), b"TTTTTT".as_slice())]
+#[case("NNNN", 2, None, b"AAACGTTTTT".as_slice(), Some(b"ACGT".as_slice()), b"AATTTT".as_slice())]
+#[case("NNNNTG", 0, None, b"ACGTTGCC".as_slice(), Some(b"ACGTTG".as_slice()), b"CC".as_slice())]
+#[case("NNNNTG", 0, None, b"ACGTAACC".as_slice(), None, b"ACGTAACC".as_slice())]
+#[case("NNNN", 0, Some("GGGGGGGG"), b"TTGGGGGGGGCATGAAAAAAAAAAAAAAAAAAAA".as_slice(), Some(b"CATG".as_slice()), b"TTGGGGGGGGAAAAAAAAAAAAAAAAAAAA".as_slice())]
+#[case("NNNN", 0, Some("GGGGGGGG"), b"TTCCCCCCCCCATGAAAAAAAAAAAAAAAAAAAA".as_slice(), None, b"TTCCCCCCCCCATGAAAAAAAAAAAAAAAAAAAA".as_slice())]
+fn test_extract_umi(
+    #[case] pattern: &str,
+    #[case] position: usize,
+    #[case] barcode: Option<&str>,
+    #[case] seq: &[u8],
+    #[case] expected_umi: Option<&[u8]>,
+    #[case] expected_seq: &[u8],
+) {
+    let anchor = barcode.map(|barcode| {
+        ReadTrimmer::new(0, 0, Some(vec![barcode.to_string()]), None, 1, 0, 10, false)
+    });
+    let extractor = UmiExtractor::new(pattern, position, anchor).unwrap();
+
+    let mut record = FastxRecord {
+        id: b"read".to_vec(),
+        seq: seq.to_vec(),
+        qual: Some(vec![b'I'; seq.len()]),
+    };
+
+    assert_eq!(extractor.extract(&mut record).as_deref(), expected_umi);
+    assert_eq!(record.seq, expected_seq);
+    assert_eq!(record.qual.unwrap().len(), expected_seq.len());
+}
+
+#[rstest]
+#[case(None)]
+#[case(Some("no_umi.fasta"))]
+fn test_fastq_extract_umi(#[case] no_umi_name: Option<&str>) {
+    let fasta = TempFile::with_content("in.fasta", ">r1\nACGTTT\n>r2\nAC\n>r3\nTTTTGG\n");
+    let outfile = TempFile::new("out.fasta");
+    let no_umi_outfile = no_umi_name.map(TempFile::new);
+
+    fastq_extract_umi(
+        Some(fasta.path()),
+        &KeepTags::default(),
+        "NNNN".to_string(),
+        0,
+        None,
+        0,
+        0,
+        0,
+        UmiFormat::Underscore,
+        Some(outfile.path()),
+        no_umi_outfile.as_ref().map(|path| path.path()),
+    )
+    .unwrap();
+
+    assert_eq!(outfile.read_to_string(), ">r1_ACGT\nTT\n>r3_TTTT\nGG\n");
+    if let Some(no_umi_outfile) = no_umi_outfile {
+        assert_eq!(no_umi_outfile.read_to_string(), ">r2\nAC\n");
+    }
+}
+
+#[rstest]
+#[case(b"read_1 desc".as_slice(), UmiFormat::Underscore, b"read_1_ACGT desc".as_slice())]
+#[case(b"read_1\tqs:i:12".as_slice(), UmiFormat::Rx, b"read_1\tqs:i:12\tRX:Z:ACGT".as_slice())]
+#[case(b"read_1\tRX:Z:TTTT\tqs:i:12".as_slice(), UmiFormat::Rx, b"read_1\tqs:i:12\tRX:Z:ACGT".as_slice())]
+#[case(b"read_1 desc\tRX:Z:TTTT".as_slice(), UmiFormat::Rx, b"read_1 desc\tRX:Z:ACGT".as_slice())]
+fn test_add_umi(#[case] header: &[u8], #[case] format: UmiFormat, #[case] expected: &[u8]) {
+    let new_header = add_umi(header, b"ACGT", format);
+
+    assert_eq!(new_header, expected);
+    assert_eq!(read_umi(&new_header), Some(b"ACGT".as_slice()));
+}
+
+#[rstest]
+#[case(b"read_1_ACGT desc".as_slice(), Some(b"ACGT".as_slice()))]
+#[case(b"read_1\tRX:Z:ACGT".as_slice(), Some(b"ACGT".as_slice()))]
+#[case(b"read_1 desc".as_slice(), None)]
+#[case(b"read_1_".as_slice(), None)]
+#[case(b"read".as_slice(), None)]
+fn test_read_umi(#[case] header: &[u8], #[case] expected: Option<&[u8]>) {
+    assert_eq!(read_umi(header), expected);
+}
@@ -0,0 +1,2 @@
+pub mod extract_umi;
+pub use extract_umi::{UmiExtractor, add_umi, fastq_extract_umi, read_umi};
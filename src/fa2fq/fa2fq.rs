@@ -0,0 +1,161 @@
+use crate::bam::KeepTags;
+use crate::errors::AppError;
+use crate::fastx::{fastx_records, text_reader};
+use bio_utils_rs::io::get_bufwriter;
+use bio_utils_rs::nucleotide::PHRED_OFFSET;
+use rstest::rstest;
+use std::io::BufRead;
+use std::path::PathBuf;
+
+/// Highest phred that can be encoded as a printable ASCII character ('~').
+const MAX_PHRED: u8 = 126 - PHRED_OFFSET as u8;
+
+/// Streaming reader for `.qual` files, i.e. FASTA-like records
+/// with whitespace separated phred scores instead of bases.
+struct QualReader<R: BufRead> {
+    reader: R,
+    line: String,
+}
+
+impl<R: BufRead> QualReader<R> {
+    fn new(reader: R) -> Self {
+        Self {
+            reader,
+            line: String::new(),
+        }
+    }
+
+    /// Returns the next `(id, phred_scores)`. Phred scores are not offset.
+    fn next_record(&mut self) -> Result<Option<(String, Vec<u8>)>, AppError> {
+        // Skip to the next header, unless we already have it from the last call.
+        while !self.line.starts_with('>') {
+            self.line.clear();
+            if self.reader.read_line(&mut self.line)? == 0 {
+                return Ok(None);
+            }
+        }
+
+        let id = self.line[1..]
+            .split_whitespace()
+            .next()
+            .unwrap_or_default()
+            .to_string();
+
+        let mut scores: Vec<u8> = Vec::new();
+
+        loop {
+            self.line.clear();
+            if self.reader.read_line(&mut self.line)? == 0 || self.line.starts_with('>') {
+                break;
+            }
+
+            for score in self.line.split_whitespace() {
+                let score = score.parse::<u8>().map_err(|_| {
+                    AppError::QualFileError(format!("invalid phred score '{score}' for {id}"))
+                })?;
+                scores.push(score);
+            }
+        }
+
+        Ok(Some((id, scores)))
+    }
+}
+
+#[inline]
+fn encode_phred(phred: u8) -> Result<u8, AppError> {
+    if phred > MAX_PHRED {
+        return Err(AppError::InvalidArgumentError(format!(
+            "phred must be at most {MAX_PHRED}, got {phred}"
+        )));
+    }
+
+    Ok(phred + PHRED_OFFSET as u8)
+}
+
+/// Converts FASTA (including line-wrapped) to FASTQ. Qualities are either
+/// a constant `phred` for every base, or read from a companion `.qual` file
+/// with records in the same order as the FASTA.
+pub fn fastq_fa2fq(
+    fasta: Option<PathBuf>,
+    phred: u8,
+    qual: Option<PathBuf>,
+    outfile: Option<PathBuf>,
+) -> Result<(), AppError> {
+    let actual_phred = encode_phred(phred)?;
+
+    let records = fastx_records(fasta, &KeepTags::default())?;
+    let mut writer = get_bufwriter(outfile)?;
+
+    let mut qual_reader = match qual {
+        Some(qual) => Some(QualReader::new(text_reader(Some(qual))?)),
+        None => None,
+    };
+
+    for record in records {
+        let mut record = record?;
+        let qual = match qual_reader.as_mut() {
+            None => vec![actual_phred; record.seq.len()],
+            Some(qual_reader) => {
+                let record_id = record
+                    .id
+                    .split(|c| c.is_ascii_whitespace())
+                    .next()
+                    .unwrap_or_default();
+                let record_id = std::str::from_utf8(record_id)?;
+
+                let (qual_id, scores) =
+                    qual_reader
+                        .next_record()?
+                        .ok_or(AppError::QualFileError(format!(
+                            "missing qualities for {record_id}"
+                        )))?;
+
+                if qual_id != record_id {
+                    return Err(AppError::QualFileError(format!(
+                        "expected qualities for {record_id}, found {qual_id}"
+                    )));
+                }
+
+                if scores.len() != record.seq.len() {
+                    return Err(AppError::QualFileError(format!(
+                        "{record_id} has {} bases but {} phred scores",
+                        record.seq.len(),
+                        scores.len()
+                    )));
+                }
+
+                scores
+                    .into_iter()
+                    .map(encode_phred)
+                    .collect::<Result<Vec<u8>, AppError>>()?
+            }
+        };
+
+        record.qual = Some(qual);
+        record.write(&mut writer)?;
+    }
+
+    writer.flush()?;
+
+    Ok(())
+}
+
+#[rstest]
+#[case(b">r1\n30 30\n20\n>r2 desc\n10\n", vec![("r1", vec![30, 30, 20]), ("r2", vec![10])])]
+#[case(b"\n>r1\n\n>r2\n40\n", vec![("r1", vec![]), ("r2", vec![40])])]
+#[case(b"", vec![])]
+fn test_qual_reader(#[case] content: &[u8], #[case] expected: Vec<(&str, Vec<u8>)>) {
+    let mut reader = QualReader::new(std::io::Cursor::new(content));
+
+    let mut records: Vec<(String, Vec<u8>)> = Vec::new();
+    while let Some(record) = reader.next_record().unwrap() {
+        records.push(record);
+    }
+
+    let expected: Vec<(String, Vec<u8>)> = expected
+        .into_iter()
+        .map(|(id, scores)| (id.to_string(), scores))
+        .collect();
+
+    assert_eq!(records, expected);
+}
@@ -0,0 +1,2 @@
+pub mod fa2fq;
+pub use fa2fq::fastq_fa2fq;
@@ -0,0 +1,242 @@
+use crate::bam::{BamReader, BamWriter, BgzfWriter, KeepTags, SamReader, SamWriter};
+use crate::errors::AppError;
+use bio_utils_rs::io::{get_bufwriter, needletail_reader};
+use bio_utils_rs::nucleotide::reverse_complement;
+use flate2::read::MultiGzDecoder;
+use needletail::parser::SequenceRecord;
+use rstest::rstest;
+use std::fs::File;
+use std::io::{BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+
+/// Owned FASTQ/FASTA record. A missing quality means the record is FASTA.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FastxRecord {
+    pub id: Vec<u8>,
+    pub seq: Vec<u8>,
+    pub qual: Option<Vec<u8>>,
+}
+
+impl FastxRecord {
+    pub fn from_needletail(record: &SequenceRecord) -> Self {
+        Self {
+            id: record.id().to_vec(),
+            seq: record.seq().to_vec(),
+            qual: record.qual().map(|q| q.to_vec()),
+        }
+    }
+
+    /// Keeps only `seq[start..end]` (and the matching qualities).
+    #[inline]
+    pub fn keep(&mut self, start: usize, end: usize) {
+        self.seq.truncate(end);
+        self.seq.drain(..start);
+
+        if let Some(qual) = self.qual.as_mut() {
+            qual.truncate(end);
+            qual.drain(..start);
+        }
+    }
+
+    /// Reverse complements the sequence (and reverses the qualities).
+    pub fn reverse_complement(&mut self) {
+        self.seq = reverse_complement(&self.seq);
+
+        if let Some(qual) = self.qual.as_mut() {
+            qual.reverse();
+        }
+    }
+
+    /// Writes the record as FASTQ if it has qualities, otherwise as FASTA.
+    pub fn write<W: Write + ?Sized>(&self, writer: &mut W) -> Result<(), AppError> {
+        match self.qual.as_ref() {
+            Some(qual) => {
+                writer.write_all(b"@")?;
+                writer.write_all(&self.id)?;
+                writer.write_all(b"\n")?;
+                writer.write_all(&self.seq)?;
+                writer.write_all(b"\n+\n")?;
+                writer.write_all(qual)?;
+                writer.write_all(b"\n")?;
+            }
+            None => {
+                writer.write_all(b">")?;
+                writer.write_all(&self.id)?;
+                writer.write_all(b"\n")?;
+                writer.write_all(&self.seq)?;
+                writer.write_all(b"\n")?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Sequence file format, inferred from the file extension.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FastxFormat {
+    /// FASTQ/FASTA, optionally gzipped. Also used for stdin/stdout.
+    Fastx,
+    Sam,
+    Bam,
+    Cram,
+}
+
+impl FastxFormat {
+    pub fn from_path(path: Option<&Path>) -> Self {
+        let extension = path
+            .and_then(|p| p.extension())
+            .map(|e| e.to_string_lossy().to_lowercase());
+
+        match extension.as_deref() {
+            Some("sam") => Self::Sam,
+            Some("bam") => Self::Bam,
+            Some("cram") => Self::Cram,
+            _ => Self::Fastx,
+        }
+    }
+}
+
+fn cram_error() -> AppError {
+    AppError::BamError(
+        "CRAM is not supported, convert with `samtools view -b` to BAM first".to_string(),
+    )
+}
+
+/// Iterates over SAM/BAM records. A malformed record is returned as an error, after
+/// which iteration stops.
+fn alignment_records<F>(
+    mut next_record: F,
+) -> impl Iterator<Item = Result<FastxRecord, AppError>> + Send
+where
+    F: FnMut() -> Result<Option<FastxRecord>, AppError> + Send,
+{
+    let mut failed = false;
+
+    std::iter::from_fn(move || {
+        if failed {
+            return None;
+        }
+
+        next_record().inspect_err(|_| failed = true).transpose()
+    })
+}
+
+/// Iterates over all records in a FASTQ/FASTA file (or stdin),
+/// skipping malformatted records. Can be used with `par_bridge`.
+/// Files ending in `.bam` or `.sam` are read as (unaligned) SAM/BAM, where
+/// malformed or truncated records are errors. Aux tags in `keep_tags` are added
+/// to the read header.
+pub fn fastx_records(
+    fastx: Option<PathBuf>,
+    keep_tags: &KeepTags,
+) -> Result<Box<dyn Iterator<Item = Result<FastxRecord, AppError>> + Send>, AppError> {
+    match FastxFormat::from_path(fastx.as_deref()) {
+        FastxFormat::Bam => {
+            let reader = BufReader::new(MultiGzDecoder::new(BufReader::new(File::open(
+                fastx.unwrap_or_default(),
+            )?)));
+            let mut reader = BamReader::new(reader, keep_tags.clone())?;
+
+            return Ok(Box::new(alignment_records(move || reader.next_record())));
+        }
+        FastxFormat::Sam => {
+            let mut reader = SamReader::new(text_reader(fastx)?, keep_tags.clone());
+
+            return Ok(Box::new(alignment_records(move || reader.next_record())));
+        }
+        FastxFormat::Cram => return Err(cram_error()),
+        FastxFormat::Fastx => {}
+    }
+
+    let mut reader = needletail_reader(fastx)?;
+
+    Ok(Box::new(std::iter::from_fn(move || {
+        loop {
+            match reader.next()? {
+                Ok(record) => return Some(Ok(FastxRecord::from_needletail(&record))),
+                Err(_) => continue,
+            }
+        }
+    })))
+}
+
+/// Writes FASTQ/FASTA, or unaligned SAM/BAM if the output file ends in `.sam`/`.bam`.
+pub enum FastxWriter {
+    Fastx(Box<dyn Write + Send>),
+    Sam(SamWriter<Box<dyn Write + Send>>),
+    Bam(BamWriter<BgzfWriter<Box<dyn Write + Send>>>),
+}
+
+impl FastxWriter {
+    pub fn write_record(&mut self, record: &FastxRecord) -> Result<(), AppError> {
+        match self {
+            Self::Fastx(writer) => record.write(writer),
+            Self::Sam(writer) => writer.write_record(record),
+            Self::Bam(writer) => writer.write_record(record),
+        }
+    }
+
+    /// Always remember to finish, BAM output needs an end of file marker.
+    pub fn finish(self) -> Result<(), AppError> {
+        let mut writer = match self {
+            Self::Fastx(writer) => writer,
+            Self::Sam(writer) => writer.into_inner(),
+            Self::Bam(writer) => writer.into_inner().finish()?,
+        };
+
+        writer.flush()?;
+
+        Ok(())
+    }
+}
+
+pub fn fastx_writer(outfile: Option<PathBuf>) -> Result<FastxWriter, AppError> {
+    let format = FastxFormat::from_path(outfile.as_deref());
+    let writer = get_bufwriter(outfile)?;
+
+    match format {
+        FastxFormat::Fastx => Ok(FastxWriter::Fastx(writer)),
+        FastxFormat::Sam => Ok(FastxWriter::Sam(SamWriter::new(writer)?)),
+        FastxFormat::Bam => Ok(FastxWriter::Bam(BamWriter::new(BgzfWriter::new(writer))?)),
+        FastxFormat::Cram => Err(cram_error()),
+    }
+}
+
+/// Buffered reader for non-sequence text files (e.g. tsv), with gzip
+/// support for files ending in `.gz`. Defaults to stdin.
+pub fn text_reader(path: Option<PathBuf>) -> Result<BufReader<Box<dyn Read + Send>>, AppError> {
+    let reader: Box<dyn Read + Send> = match path {
+        Some(path) => {
+            let f = File::open(&path)?;
+
+            match path.extension().is_some_and(|e| e == "gz") {
+                true => Box::new(MultiGzDecoder::new(f)),
+                false => Box::new(f),
+            }
+        }
+        None => Box::new(std::io::stdin()),
+    };
+
+    Ok(BufReader::new(reader))
+}
+
+#[rstest]
+#[case(b"r1\t4\t*\t0\t0\t*\t*\t0\t0\tACGT\tIIII\nr2\t4\t*\t0\t0\t*\t*\t0\t0\tAC\tII\n".as_slice(), 2, false)]
+// Iteration stops at the truncated record.
+#[case(b"r1\t4\t*\t0\t0\t*\t*\t0\t0\tACGT\tIIII\nr2\t4\t*\nr3\t4\t*\t0\t0\t*\t*\t0\t0\tAC\tII\n".as_slice(), 1, true)]
+fn test_alignment_records(
+    #[case] sam: &'static [u8],
+    #[case] expected_records: usize,
+    #[case] expected_error: bool,
+) {
+    let mut reader = SamReader::new(sam, KeepTags::default());
+    let results: Vec<Result<FastxRecord, AppError>> =
+        alignment_records(move || reader.next_record()).collect();
+
+    assert_eq!(
+        results.iter().filter(|r| r.is_ok()).count(),
+        expected_records
+    );
+    assert_eq!(results.last().is_some_and(|r| r.is_err()), expected_error);
+}
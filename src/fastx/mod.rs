@@ -0,0 +1,2 @@
+pub mod fastx;
+pub use fastx::{FastxFormat, FastxRecord, FastxWriter, fastx_records, fastx_writer, text_reader};
@@ -0,0 +1,139 @@
+use crate::errors::AppError;
+use crate::fastx::text_reader;
+use crate::index::{FastxIndex, IndexEntry, index_path, open_indexed, read_record};
+use bio_utils_rs::io::get_bufwriter;
+use rstest::rstest;
+use std::io::{BufRead, Write};
+use std::path::PathBuf;
+
+/// 1-based, inclusive start and end.
+pub type Region = (usize, usize);
+
+/// Splits a query into a read id and an optional 1-based, inclusive region, like
+/// `samtools faidx` (`read_1:10-20`, `read_1:10` or `read_1`). Ids that are in the index
+/// are never split, so ids containing `:` (e.g. Illumina) also work.
+pub fn parse_region<'a>(
+    query: &'a str,
+    index: &FastxIndex,
+) -> Result<(&'a str, Option<Region>), AppError> {
+    if index.get(query).is_some() {
+        return Ok((query, None));
+    }
+
+    let (id, region) = match query.rsplit_once(':') {
+        Some(split) => split,
+        None => return Ok((query, None)),
+    };
+
+    let invalid = || AppError::InvalidArgumentError(format!("invalid region `{query}`"));
+
+    let (start, end) = match region.split_once('-') {
+        Some((start, end)) => (start, Some(end)),
+        None => (region, None),
+    };
+
+    let start: usize = start.replace(',', "").parse().map_err(|_| invalid())?;
+    let end: usize = match end {
+        Some(end) => end.replace(',', "").parse().map_err(|_| invalid())?,
+        None => usize::MAX,
+    };
+
+    if start == 0 || end < start {
+        return Err(invalid());
+    }
+
+    Ok((id, Some((start, end))))
+}
+
+/// Clips a region (1-based, inclusive) to a read of `len` bases. Regions that start
+/// after the end of the read are invalid.
+fn clip_region(query: &str, (start, end): Region, len: usize) -> Result<Region, AppError> {
+    if start > len {
+        return Err(AppError::InvalidArgumentError(format!(
+            "region `{query}` starts after the end of the read ({len} bp)"
+        )));
+    }
+
+    Ok((start, end.min(len)))
+}
+
+/// Retrieves reads (or sub-sequences, see `parse_region`) by id using an index built with
+/// `fastq_index`. Reads are written in query order.
+pub fn fastq_fetch(
+    fastq: PathBuf,
+    index: Option<PathBuf>,
+    ids: Vec<String>,
+    id_file: Option<PathBuf>,
+    outfile: Option<PathBuf>,
+) -> Result<(), AppError> {
+    let index = FastxIndex::load(&index.unwrap_or_else(|| index_path(&fastq)))?;
+
+    let mut queries = ids;
+    if let Some(id_file) = id_file {
+        for line in text_reader(Some(id_file))?.lines() {
+            let line = line?;
+            let query = line.trim();
+
+            if !query.is_empty() {
+                queries.push(query.to_string());
+            }
+        }
+    }
+
+    let mut writer = get_bufwriter(outfile)?;
+
+    for query in queries.iter() {
+        let (id, region) = parse_region(query, &index)?;
+
+        let IndexEntry { offset, .. } = *index.get(id).ok_or(AppError::IndexError(format!(
+            "read `{id}` not found in index"
+        )))?;
+
+        let mut reader = open_indexed(&fastq, offset)?;
+
+        let mut record = read_record(&mut reader)?.ok_or(AppError::IndexError(format!(
+            "read `{id}` not found at offset {offset}, the index might be outdated"
+        )))?;
+
+        if let Some(region) = region {
+            let (start, end) = clip_region(query, region, record.seq.len())?;
+
+            record.keep(start - 1, end);
+            record.id = format!("{id}:{start}-{end}").into_bytes();
+        }
+
+        record.write(&mut writer)?;
+    }
+
+    writer.flush()?;
+
+    Ok(())
+}
+
+#[rstest]
+#[case("read_1", ("read_1", None))]
+#[case("read_1:10-20", ("read_1", Some((10, 20))))]
+#[case("read_1:1,000-2,000", ("read_1", Some((1000, 2000))))]
+#[case("read_1:10", ("read_1", Some((10, usize::MAX))))]
+fn test_parse_region(#[case] query: &str, #[case] expected: (&str, Option<Region>)) {
+    let index = FastxIndex::default();
+    assert_eq!(parse_region(query, &index).unwrap(), expected);
+}
+
+#[rstest]
+#[case("read_1:0-10")]
+#[case("read_1:20-10")]
+#[case("read_1:x-10")]
+fn test_parse_region_invalid(#[case] query: &str) {
+    let index = FastxIndex::default();
+    assert!(parse_region(query, &index).is_err());
+}
+
+#[rstest]
+#[case((10, 20), 100, Some((10, 20)))]
+#[case((10, usize::MAX), 100, Some((10, 100)))]
+#[case((100, 200), 100, Some((100, 100)))]
+#[case((101, 200), 100, None)]
+fn test_clip_region(#[case] region: Region, #[case] len: usize, #[case] expected: Option<Region>) {
+    assert_eq!(clip_region("read_1", region, len).ok(), expected);
+}
@@ -0,0 +1,2 @@
+pub mod fetch;
+pub use fetch::{Region, fastq_fetch, parse_region};
@@ -1,55 +1,155 @@
+use crate::bam::KeepTags;
 use crate::errors::AppError;
-use bio_utils_rs::io::{get_bufwriter, needletail_reader};
-use bio_utils_rs::nucleotide::{mean_error_and_phred, nucleotide_counts};
+use crate::fastx::{fastx_records, fastx_writer};
+use bio_utils_rs::nucleotide::{gc_content, mean_error_and_phred, nucleotide_counts};
+use rstest::rstest;
+use serde::Deserialize;
 use std::path::PathBuf;
 
+/// Per read filter criteria, shared between `filter` and `pipeline`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ReadFilter {
+    pub min_len: usize,
+    pub max_len: usize,
+    pub min_error: f64,
+    pub max_error: f64,
+    pub min_gc: f64,
+    pub max_gc: f64,
+    pub min_softmasked: usize,
+    pub max_softmasked: usize,
+    pub min_ambiguous: usize,
+    pub max_ambiguous: usize,
+}
+
+impl Default for ReadFilter {
+    fn default() -> Self {
+        Self {
+            min_len: 0,
+            max_len: usize::MAX,
+            min_error: 0.0,
+            max_error: 1.0,
+            min_gc: 0.0,
+            max_gc: 1.0,
+            min_softmasked: 0,
+            max_softmasked: usize::MAX,
+            min_ambiguous: 0,
+            max_ambiguous: usize::MAX,
+        }
+    }
+}
+
+impl ReadFilter {
+    /// True if any of the error options are set, which means
+    /// reads without quality scores (FASTA) cannot be filtered.
+    pub fn requires_quality(&self) -> bool {
+        self.min_error > 0.0 || self.max_error < 1.0
+    }
+
+    pub fn passes(&self, seq: &[u8], qual: Option<&[u8]>) -> Result<bool, AppError> {
+        // Early return for too short/long reads.
+        let record_len = seq.len();
+        if record_len < self.min_len || record_len > self.max_len {
+            return Ok(false);
+        }
+
+        // Early return for too low/high error rate.
+        if self.requires_quality() {
+            let qual = qual.ok_or(AppError::MissingQualityError(
+                "min_error/max_error".to_string(),
+            ))?;
+
+            let (mean_error, _) = mean_error_and_phred(qual);
+            if mean_error < self.min_error || mean_error > self.max_error {
+                return Ok(false);
+            }
+        }
+
+        // Early return for too low/high GC content.
+        if self.min_gc > 0.0 || self.max_gc < 1.0 {
+            let gc = gc_content(seq);
+            if gc < self.min_gc || gc > self.max_gc {
+                return Ok(false);
+            }
+        }
+
+        // Early return for too few/many softmasked or ambiguous nucleotides.
+        let (_, num_softmasked, num_ambiguous) = nucleotide_counts(seq);
+        if num_softmasked < self.min_softmasked || num_softmasked > self.max_softmasked {
+            return Ok(false);
+        }
+        if num_ambiguous < self.min_ambiguous || num_ambiguous > self.max_ambiguous {
+            return Ok(false);
+        }
+
+        Ok(true)
+    }
+}
+
 pub fn fastq_filter(
     fastq: Option<PathBuf>,
+    keep_tags: &KeepTags,
     min_len: usize,
     max_len: usize,
     min_error: f64,
     max_error: f64,
+    min_gc: f64,
+    max_gc: f64,
     min_softmasked: usize,
     max_softmasked: usize,
     min_ambiguous: usize,
     max_ambiguous: usize,
     outfile: Option<PathBuf>,
 ) -> Result<(), AppError> {
-    let mut reader = needletail_reader(fastq)?;
-    let mut writer = get_bufwriter(outfile)?;
-
-    while let Some(record) = reader.next() {
-        let record = match record {
-            Ok(record) => record,
-            Err(_) => continue,
-        };
+    let records = fastx_records(fastq, keep_tags)?;
+    let mut writer = fastx_writer(outfile)?;
 
-        let record_seq = record.seq();
-        let record_qual = record.qual().expect("No quality in record"); // Make this better.
+    let read_filter = ReadFilter {
+        min_len,
+        max_len,
+        min_error,
+        max_error,
+        min_gc,
+        max_gc,
+        min_softmasked,
+        max_softmasked,
+        min_ambiguous,
+        max_ambiguous,
+    };
 
-        // Early return for too short/long reads.
-        let record_len = record_seq.len();
-        if record_len < min_len || record_len > max_len {
-            continue;
-        }
-
-        // Early return for too low/high error rate.
-        let (mean_error, _) = mean_error_and_phred(record_qual);
-        if mean_error < min_error || mean_error > max_error {
-            continue;
-        }
+    for record in records {
+        let record = record?;
 
-        // Early return for too few/many softmasked or ambiguous nucleotides.
-        let (_, num_softmasked, num_ambiguous) = nucleotide_counts(&record_seq);
-        if num_softmasked < min_softmasked || num_softmasked > max_softmasked {
-            continue;
-        }
-        if num_ambiguous < min_ambiguous || num_ambiguous > max_ambiguous {
+        if !read_filter.passes(&record.seq, record.qual.as_deref())? {
             continue;
         }
 
-        record.write(&mut writer, None)?;
+        writer.write_record(&record)?;
     }
 
+    writer.finish()?;
+
     Ok(())
 }
+
+#[rstest]
+#[case(ReadFilter { min_len: 4, ..Default::default() }, Some(b"IIII".as_slice()), Some(true))]
+#[case(ReadFilter { min_len: 4, ..Default::default() }, None, Some(true))]
+#[case(ReadFilter { min_len: 5, ..Default::default() }, None, Some(false))]
+#[case(ReadFilter { max_gc: 0.4, ..Default::default() }, None, Some(false))]
+#[case(ReadFilter { max_error: 0.01, ..Default::default() }, Some(b"IIII".as_slice()), Some(true))]
+#[case(ReadFilter { max_error: 0.01, ..Default::default() }, None, None)]
+fn test_read_filter(
+    #[case] read_filter: ReadFilter,
+    #[case] qual: Option<&[u8]>,
+    #[case] expected: Option<bool>,
+) {
+    // No quality (FASTA) is only an error for the error rate options.
+    match read_filter.passes(b"ACGT", qual) {
+        Ok(passes) => assert_eq!(Some(passes), expected),
+        Err(err) => {
+            assert!(matches!(err, AppError::MissingQualityError(_)));
+            assert_eq!(expected, None);
+        }
+    }
+}
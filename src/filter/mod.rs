@@ -1,2 +1,2 @@
 pub mod filter;
-pub use filter::fastq_filter;
+pub use filter::{ReadFilter, fastq_filter};
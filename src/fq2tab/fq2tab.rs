@@ -1,49 +1,285 @@
+use crate::args::TabColumn;
+use crate::bam::KeepTags;
 use crate::errors::AppError;
-use bio_utils_rs::io::{get_bufwriter, needletail_reader};
-use bio_utils_rs::nucleotide::mean_error_and_phred;
+use crate::fastx::{FastxRecord, fastx_records};
+use crate::table::{ColumnType, TableColumn, TableFormat, TableValue, table_writer};
+#[cfg(test)]
+use crate::test_utils::TempFile;
+use bio_utils_rs::nucleotide::{gc_content, mean_error_and_phred, nucleotide_counts};
+use rstest::rstest;
 use std::path::PathBuf;
 
-pub fn fastq_fq2tab(fastq: Option<PathBuf>, outfile: Option<PathBuf>) -> Result<(), AppError> {
-    let mut reader = needletail_reader(fastq)?;
-    let mut writer = get_bufwriter(outfile.clone())?;
+/// Columns required to reconstruct the original reads with `tab2fq`.
+pub const LOSSLESS_COLUMNS: [TabColumn; 4] = [
+    TabColumn::Id,
+    TabColumn::Header,
+    TabColumn::Seq,
+    TabColumn::Qual,
+];
 
-    writer.write_all(b"read_id\tread_length\tread_error\tread_phred\n")?;
+impl TabColumn {
+    pub fn header(&self) -> &'static str {
+        match self {
+            TabColumn::Id => "read_id",
+            TabColumn::Seq => "seq",
+            TabColumn::Qual => "qual",
+            TabColumn::Len => "read_length",
+            TabColumn::Gc => "gc_content",
+            TabColumn::Error => "read_error",
+            TabColumn::MeanQ => "read_phred",
+            TabColumn::NCount => "n_count",
+            TabColumn::Softmasked => "softmasked",
+            TabColumn::HeaderFields => "header_fields",
+            TabColumn::Header => "header",
+        }
+    }
 
-    while let Some(record) = reader.next() {
-        let record = match record {
-            Ok(record) => record,
-            Err(_) => continue,
-        };
+    pub fn column_type(&self) -> ColumnType {
+        match self {
+            TabColumn::Id
+            | TabColumn::Seq
+            | TabColumn::Qual
+            | TabColumn::HeaderFields
+            | TabColumn::Header => ColumnType::Utf8,
+            TabColumn::Gc | TabColumn::Error => ColumnType::Float64,
+            TabColumn::Len | TabColumn::MeanQ | TabColumn::NCount | TabColumn::Softmasked => {
+                ColumnType::UInt64
+            }
+        }
+    }
 
-        let record_qual = match record.qual() {
-            Some(record_qual) => record_qual,
-            None => continue,
-        };
+    pub fn requires_quality(&self) -> bool {
+        matches!(self, TabColumn::Qual | TabColumn::Error | TabColumn::MeanQ)
+    }
+}
+
+/// Splits a FASTQ header into the read id and the (possibly empty) description.
+#[inline]
+pub fn split_header(header: &[u8]) -> (&[u8], &[u8]) {
+    match header.iter().position(|c| c.is_ascii_whitespace()) {
+        Some(i) => (&header[..i], &header[i + 1..]),
+        None => (header, b""),
+    }
+}
+
+/// Escapes backslashes and tabs, so that a header fits in a tsv field.
+pub fn escape_tabs(header: &[u8]) -> Vec<u8> {
+    let mut escaped = Vec::with_capacity(header.len());
+
+    for &c in header {
+        match c {
+            b'\\' => escaped.extend_from_slice(b"\\\\"),
+            b'\t' => escaped.extend_from_slice(b"\\t"),
+            _ => escaped.push(c),
+        }
+    }
 
-        let record_seq = record.seq();
+    escaped
+}
+
+/// Inverse of `escape_tabs`. Unknown escapes are kept as is.
+pub fn unescape_tabs(field: &[u8]) -> Vec<u8> {
+    let mut header = Vec::with_capacity(field.len());
+    let mut chars = field.iter().copied().peekable();
+
+    while let Some(c) = chars.next() {
+        match (c, chars.peek()) {
+            (b'\\', Some(b'\\')) => {
+                chars.next();
+                header.push(b'\\');
+            }
+            (b'\\', Some(b't')) => {
+                chars.next();
+                header.push(b'\t');
+            }
+            _ => header.push(c),
+        }
+    }
 
-        let (mean_read_error, mean_read_phred) = mean_error_and_phred(record_qual);
+    header
+}
 
-        // Read id.
-        writer.write_all(record.id())?;
-        writer.write_all(b"\t")?;
+/// Adds the lossless columns (if not already present) to the selected columns. The qual
+/// column is only added if the reads have qualities (i.e. not for FASTA).
+pub fn with_lossless_columns(mut columns: Vec<TabColumn>, has_quality: bool) -> Vec<TabColumn> {
+    for column in LOSSLESS_COLUMNS {
+        if !columns.contains(&column) && (has_quality || column != TabColumn::Qual) {
+            columns.push(column);
+        }
+    }
 
-        // Read length.
-        writer.write_all(record_seq.len().to_string().as_bytes())?;
-        writer.write_all(b"\t")?;
+    columns
+}
 
-        // Read error
-        writer.write_all(mean_read_error.to_string().as_bytes())?;
-        writer.write_all(b"\t")?;
+/// `header` is the full read header, escaped for tsv output.
+fn column_value<'a>(
+    column: &TabColumn,
+    record: &'a FastxRecord,
+    header: &'a [u8],
+) -> TableValue<'a> {
+    let qual = record.qual.as_deref().unwrap_or_default();
+    let (id, description) = split_header(&record.id);
 
-        // Read phred
-        writer.write_all(mean_read_phred.to_string().as_bytes())?;
-        writer.write_all(b"\t")?;
-        writer.write_all(b"\n")?;
+    match column {
+        TabColumn::Id => TableValue::Str(id),
+        TabColumn::Seq => TableValue::Str(&record.seq),
+        TabColumn::Qual => TableValue::Str(qual),
+        TabColumn::Len => TableValue::UInt(record.seq.len() as u64),
+        TabColumn::Gc => TableValue::Float(gc_content(&record.seq) as f64),
+        TabColumn::Error => {
+            let (mean_read_error, _) = mean_error_and_phred(qual);
+            TableValue::Float(mean_read_error)
+        }
+        TabColumn::MeanQ => {
+            let (_, mean_read_phred) = mean_error_and_phred(qual);
+            TableValue::UInt(mean_read_phred as u64)
+        }
+        TabColumn::NCount => {
+            let n_count = record
+                .seq
+                .iter()
+                .filter(|nt| matches!(nt, b'N' | b'n'))
+                .count();
+            TableValue::UInt(n_count as u64)
+        }
+        TabColumn::Softmasked => {
+            let (_, num_softmasked, _) = nucleotide_counts(&record.seq);
+            TableValue::UInt(num_softmasked as u64)
+        }
+        TabColumn::HeaderFields => TableValue::Str(description),
+        TabColumn::Header => TableValue::Str(header),
     }
+}
+
+pub fn fastq_fq2tab(
+    fastq: Option<PathBuf>,
+    keep_tags: &KeepTags,
+    columns: Vec<TabColumn>,
+    lossless: bool,
+    outfile: Option<PathBuf>,
+) -> Result<(), AppError> {
+    let mut records = fastx_records(fastq, keep_tags)?.peekable();
 
-    // Always remember to flush.
-    writer.flush()?;
+    // Whether reads have qualities is only known from the first record.
+    let has_quality = !matches!(records.peek(), Some(Ok(record)) if record.qual.is_none());
+
+    let columns = match lossless {
+        true => with_lossless_columns(columns, has_quality),
+        false => columns,
+    };
+
+    if columns.is_empty() {
+        return Err(AppError::InvalidArgumentError(
+            "at least one column is required".to_string(),
+        ));
+    }
+
+    let quality_column = columns.iter().find(|c| c.requires_quality());
+
+    // Fail before writing the table header.
+    if let Some(column) = quality_column
+        && !has_quality
+    {
+        return Err(AppError::MissingQualityError(format!(
+            "fq2tab column {}",
+            column.header()
+        )));
+    }
+
+    // Tsv, Parquet or Arrow IPC depending on the output file extension.
+    let is_text = TableFormat::from_path(outfile.as_deref()) == TableFormat::Text;
+    let table_columns = columns
+        .iter()
+        .map(|c| TableColumn::new(c.header(), c.column_type()))
+        .collect();
+    let mut writer = table_writer(outfile, table_columns)?;
+
+    for record in records {
+        let mut record = record?;
+
+        if let Some(column) = quality_column
+            && record.qual.is_none()
+        {
+            return Err(AppError::MissingQualityError(format!(
+                "fq2tab column {}",
+                column.header()
+            )));
+        }
+
+        let header = match is_text {
+            true => escape_tabs(&record.id),
+            false => record.id.clone(),
+        };
+
+        // Tabs in the header fields would break the tsv, so we replace them.
+        if is_text {
+            let id_len = split_header(&record.id).0.len();
+            record.id[id_len..]
+                .iter_mut()
+                .skip(1)
+                .filter(|c| **c == b'\t')
+                .for_each(|c| *c = b' ');
+        }
+
+        let row: Vec<TableValue> = columns
+            .iter()
+            .map(|column| column_value(column, &record, &header))
+            .collect();
+
+        writer.write_row(&row)?;
+    }
+
+    writer.finish()?;
 
     Ok(())
 }
+
+#[rstest]
+#[case(b"read_1", b"read_1", b"")]
+#[case(b"read_1 ch=1 barcode=bc01", b"read_1", b"ch=1 barcode=bc01")]
+#[case(b"read_1\tdesc", b"read_1", b"desc")]
+fn test_split_header(
+    #[case] header: &[u8],
+    #[case] expected_id: &[u8],
+    #[case] expected_description: &[u8],
+) {
+    assert_eq!(split_header(header), (expected_id, expected_description));
+}
+
+#[rstest]
+#[case(b"read_1", b"read_1")]
+#[case(b"read_1\tqs:i:12\tRX:Z:ACGT", b"read_1\\tqs:i:12\\tRX:Z:ACGT")]
+#[case(b"read_1  a\\tb", b"read_1  a\\\\tb")]
+fn test_escape_tabs(#[case] header: &[u8], #[case] expected: &[u8]) {
+    let escaped = escape_tabs(header);
+
+    assert_eq!(escaped, expected);
+    assert_eq!(unescape_tabs(&escaped), header);
+}
+
+#[rstest]
+#[case(vec![TabColumn::Len], true, Some("read_length\tread_id\theader\tseq\n2\tr1\tr1 desc\tAC\n"))]
+#[case(vec![TabColumn::Qual], false, None)]
+#[case(vec![TabColumn::MeanQ], true, None)]
+fn test_fq2tab_fasta(
+    #[case] columns: Vec<TabColumn>,
+    #[case] lossless: bool,
+    #[case] expected: Option<&str>,
+) {
+    let fasta = TempFile::with_content("reads.fasta", ">r1 desc\nAC\n");
+    let outfile = TempFile::new("reads.tsv");
+
+    let result = fastq_fq2tab(
+        Some(fasta.path()),
+        &KeepTags::default(),
+        columns,
+        lossless,
+        Some(outfile.path()),
+    );
+
+    // Quality columns fail before anything is written.
+    match expected {
+        Some(expected) => assert_eq!(outfile.read_to_string(), expected),
+        None => assert!(result.is_err() && !outfile.exists()),
+    }
+}
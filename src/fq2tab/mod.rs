@@ -1,2 +1,4 @@
 pub mod fq2tab;
-pub use fq2tab::fastq_fq2tab;
+pub use fq2tab::{
+    LOSSLESS_COLUMNS, escape_tabs, fastq_fq2tab, split_header, unescape_tabs, with_lossless_columns,
+};
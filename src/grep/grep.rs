@@ -1,31 +1,32 @@
+use crate::bam::KeepTags;
 use crate::errors::AppError;
-use bio_utils_rs::io::{get_bufwriter, needletail_reader};
+use crate::fastx::{fastx_records, fastx_writer};
 use regex::Regex;
 use std::path::PathBuf;
 
+/// Writes the reads whose header matches `pattern`, in the input format (FASTQ/FASTA).
 pub fn fastq_grep(
     fastq: Option<PathBuf>,
+    keep_tags: &KeepTags,
     pattern: String,
     outfile: Option<PathBuf>,
 ) -> Result<(), AppError> {
-    let mut reader = needletail_reader(fastq)?;
-    let mut writer = get_bufwriter(outfile)?;
+    let records = fastx_records(fastq, keep_tags)?;
+    let mut writer = fastx_writer(outfile)?;
 
     let pattern =
         Regex::new(pattern.as_str()).map_err(|err| AppError::RegexParsingError(err.to_string()))?;
 
-    while let Some(record) = reader.next() {
-        let record = match record {
-            Ok(record) => record,
-            Err(_) => continue,
-        };
+    for record in records {
+        let record = record?;
 
-        let haystack = std::str::from_utf8(record.id())?;
-        match pattern.captures(haystack) {
-            Some(_) => record.write(&mut writer, None)?,
-            None => continue,
+        let haystack = std::str::from_utf8(&record.id)?;
+        if pattern.is_match(haystack) {
+            writer.write_record(&record)?;
         }
     }
 
+    writer.finish()?;
+
     Ok(())
 }
@@ -1,32 +1,97 @@
+use crate::bam::KeepTags;
 use crate::errors::AppError;
-use bio_utils_rs::io::{get_bufwriter, needletail_reader};
+use crate::fastx::{fastx_records, fastx_writer};
+#[cfg(test)]
+use crate::test_utils::TempFile;
+use rstest::rstest;
 use std::path::PathBuf;
 
+/// Writes `num_reads` reads after skipping the first `skip` reads, in the input format
+/// (FASTQ/FASTA). Malformatted FASTQ/FASTA records are skipped and not counted.
 pub fn fastq_head(
     fastq: Option<PathBuf>,
+    keep_tags: &KeepTags,
     num_reads: usize,
+    skip: usize,
     outfile: Option<PathBuf>,
 ) -> Result<(), AppError> {
-    let mut reader = needletail_reader(fastq)?;
-    let mut writer = get_bufwriter(outfile)?;
+    let records = fastx_records(fastq, keep_tags)?;
+    let mut writer = fastx_writer(outfile)?;
 
-    let mut n: usize = 0;
+    for (i, record) in records.enumerate().take(skip.saturating_add(num_reads)) {
+        let record = record?;
 
-    while let Some(record) = reader.next() {
-        let record = match record {
-            Ok(record) => {
-                n += 1;
-                record
-            }
-            Err(_) => continue,
-        };
-
-        record.write(&mut writer, None)?;
-
-        if n >= num_reads {
-            break;
+        if i < skip {
+            continue;
         }
+
+        writer.write_record(&record)?;
     }
 
+    writer.finish()?;
+
     Ok(())
 }
+
+/// Writes reads `start..=end` (1-based record numbers).
+pub fn fastq_range(
+    fastq: Option<PathBuf>,
+    keep_tags: &KeepTags,
+    start: usize,
+    end: usize,
+    outfile: Option<PathBuf>,
+) -> Result<(), AppError> {
+    if start == 0 || end < start {
+        return Err(AppError::InvalidArgumentError(format!(
+            "invalid record range {start}-{end}, expected 1 <= start <= end"
+        )));
+    }
+
+    fastq_head(fastq, keep_tags, end - start + 1, start - 1, outfile)
+}
+
+#[rstest]
+#[case(2, 0, ">r1\nA\n>r2\nAC\n")]
+#[case(1, 1, ">r2\nAC\n")]
+#[case(10, 2, ">r3\nACG\n")]
+#[case(1, 3, "")]
+#[case(1, 10, "")]
+fn test_fastq_head(#[case] num_reads: usize, #[case] skip: usize, #[case] expected: &str) {
+    let fasta = TempFile::with_content("in.fasta", ">r1\nA\n>r2\nAC\n>r3\nACG\n");
+    let outfile = TempFile::new("out.fasta");
+
+    fastq_head(
+        Some(fasta.path()),
+        &KeepTags::default(),
+        num_reads,
+        skip,
+        Some(outfile.path()),
+    )
+    .unwrap();
+
+    assert_eq!(outfile.read_to_string(), expected);
+}
+
+#[rstest]
+#[case(1, 3, Some(">r1\nA\n>r2\nAC\n>r3\nACG\n"))]
+#[case(1, 10, Some(">r1\nA\n>r2\nAC\n>r3\nACG\n"))]
+#[case(2, 2, Some(">r2\nAC\n"))]
+#[case(4, 10, Some(""))]
+#[case(0, 2, None)]
+#[case(3, 2, None)]
+fn test_fastq_range(#[case] start: usize, #[case] end: usize, #[case] expected: Option<&str>) {
+    let fasta = TempFile::with_content("in.fasta", ">r1\nA\n>r2\nAC\n>r3\nACG\n");
+    let outfile = TempFile::new("out.fasta");
+
+    let result = fastq_range(
+        Some(fasta.path()),
+        &KeepTags::default(),
+        start,
+        end,
+        Some(outfile.path()),
+    );
+
+    let output = result.is_ok().then(|| outfile.read_to_string());
+
+    assert_eq!(output.as_deref(), expected);
+}
@@ -1,2 +1,2 @@
 pub mod head;
-pub use head::fastq_head;
+pub use head::{fastq_head, fastq_range};
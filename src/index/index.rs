@@ -0,0 +1,260 @@
+use crate::bam::BgzfReader;
+use crate::errors::AppError;
+use crate::fastx::{FastxRecord, text_reader};
+use crate::fq2tab::split_header;
+use bio_utils_rs::io::get_bufwriter;
+use rstest::rstest;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+/// Position of a read in an indexed file. For BGZF compressed files, `offset`
+/// is a virtual offset (`compressed block offset << 16 | offset within block`).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct IndexEntry {
+    pub length: u64,
+    pub offset: u64,
+}
+
+/// Read id -> position, loaded from a `.fqi` file (`read_id\tlength\toffset`).
+#[derive(Debug, Default)]
+pub struct FastxIndex {
+    entries: HashMap<String, IndexEntry>,
+}
+
+impl FastxIndex {
+    pub fn load(path: &Path) -> Result<Self, AppError> {
+        let reader = text_reader(Some(path.to_path_buf()))?;
+        let mut entries: HashMap<String, IndexEntry> = HashMap::new();
+
+        for (i, line) in reader.lines().enumerate() {
+            let line = line?;
+
+            let parsed = match line.split('\t').collect::<Vec<&str>>().as_slice() {
+                [id, length, offset] => length
+                    .parse()
+                    .ok()
+                    .zip(offset.parse().ok())
+                    .map(|(length, offset)| (id.to_string(), IndexEntry { length, offset })),
+                _ => None,
+            };
+
+            let (id, entry) = parsed.ok_or(AppError::IndexError(format!(
+                "malformed line {} in {}",
+                i + 1,
+                path.display()
+            )))?;
+
+            // Like faidx, the first occurrence of duplicated ids is used.
+            entries.entry(id).or_insert(entry);
+        }
+
+        Ok(Self { entries })
+    }
+
+    #[inline]
+    pub fn get(&self, id: &str) -> Option<&IndexEntry> {
+        self.entries.get(id)
+    }
+}
+
+/// Default index location, `<fastq>.fqi`.
+pub fn index_path(fastq: &Path) -> PathBuf {
+    let mut path = fastq.as_os_str().to_owned();
+    path.push(".fqi");
+    PathBuf::from(path)
+}
+
+/// Compressed files must be BGZF (e.g. from `bgzip`) to support random access.
+fn is_compressed(fastq: &Path) -> bool {
+    fastq.extension().is_some_and(|e| e == "gz" || e == "bgz")
+}
+
+/// Reader that knows its current offset (plain byte offset or BGZF virtual offset).
+pub trait Tell: BufRead {
+    fn tell(&self) -> u64;
+}
+
+struct CountingReader<R: BufRead> {
+    inner: R,
+    pos: u64,
+}
+
+impl<R: BufRead> Read for CountingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.pos += n as u64;
+        Ok(n)
+    }
+}
+
+impl<R: BufRead> BufRead for CountingReader<R> {
+    fn fill_buf(&mut self) -> std::io::Result<&[u8]> {
+        self.inner.fill_buf()
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.pos += amt as u64;
+        self.inner.consume(amt);
+    }
+}
+
+impl<R: BufRead> Tell for CountingReader<R> {
+    fn tell(&self) -> u64 {
+        self.pos
+    }
+}
+
+impl<R: Read> Tell for BgzfReader<R> {
+    fn tell(&self) -> u64 {
+        self.virtual_offset()
+    }
+}
+
+/// Opens `fastq` positioned at `offset`, as stored in the index.
+pub fn open_indexed(fastq: &Path, offset: u64) -> Result<Box<dyn Tell>, AppError> {
+    let mut f = File::open(fastq)?;
+
+    match is_compressed(fastq) {
+        true => {
+            let mut reader = BgzfReader::new(BufReader::new(f));
+            reader.seek_virtual(offset)?;
+            Ok(Box::new(reader))
+        }
+        false => {
+            f.seek(SeekFrom::Start(offset))?;
+            Ok(Box::new(CountingReader {
+                inner: BufReader::new(f),
+                pos: offset,
+            }))
+        }
+    }
+}
+
+fn read_line<R: BufRead + ?Sized>(reader: &mut R, line: &mut Vec<u8>) -> Result<usize, AppError> {
+    line.clear();
+    let n = reader.read_until(b'\n', line)?;
+
+    while line.last().is_some_and(|c| matches!(c, b'\n' | b'\r')) {
+        line.pop();
+    }
+
+    Ok(n)
+}
+
+/// Reads a single FASTQ (4 line) or FASTA (multi line) record at the current
+/// position, skipping empty lines. Returns `None` at end of file.
+pub fn read_record<R: BufRead + ?Sized>(reader: &mut R) -> Result<Option<FastxRecord>, AppError> {
+    let mut header: Vec<u8> = Vec::new();
+
+    loop {
+        if read_line(reader, &mut header)? == 0 {
+            return Ok(None);
+        }
+
+        if !header.is_empty() {
+            break;
+        }
+    }
+
+    let mut line: Vec<u8> = Vec::new();
+
+    match header.first() {
+        Some(b'@') => {
+            read_line(reader, &mut line)?;
+            let seq = line.clone();
+
+            read_line(reader, &mut line)?;
+            if !line.starts_with(b"+") {
+                return Err(AppError::IndexError(format!(
+                    "malformed FASTQ record `{}`",
+                    String::from_utf8_lossy(&header)
+                )));
+            }
+
+            read_line(reader, &mut line)?;
+            if line.len() != seq.len() {
+                return Err(AppError::IndexError(format!(
+                    "sequence and quality lengths differ for `{}`",
+                    String::from_utf8_lossy(&header)
+                )));
+            }
+
+            Ok(Some(FastxRecord {
+                id: header[1..].to_vec(),
+                seq,
+                qual: Some(line),
+            }))
+        }
+        Some(b'>') => {
+            let mut seq: Vec<u8> = Vec::new();
+
+            // Sequence lines until the next header.
+            while !matches!(reader.fill_buf()?.first(), None | Some(b'>')) {
+                read_line(reader, &mut line)?;
+                seq.extend_from_slice(&line);
+            }
+
+            Ok(Some(FastxRecord {
+                id: header[1..].to_vec(),
+                seq,
+                qual: None,
+            }))
+        }
+        _ => Err(AppError::IndexError(format!(
+            "expected a FASTQ/FASTA header, got `{}`",
+            String::from_utf8_lossy(&header)
+        ))),
+    }
+}
+
+/// Writes an index with one `read_id\tlength\toffset` line per read to
+/// `outfile` (defaults to `<fastq>.fqi`).
+pub fn fastq_index(fastq: PathBuf, outfile: Option<PathBuf>) -> Result<(), AppError> {
+    let mut reader = open_indexed(&fastq, 0)?;
+    let mut writer = get_bufwriter(Some(outfile.unwrap_or_else(|| index_path(&fastq))))?;
+
+    loop {
+        let offset = reader.tell();
+
+        let record = match read_record(&mut reader)? {
+            Some(record) => record,
+            None => break,
+        };
+
+        let (id, _) = split_header(&record.id);
+
+        writer.write_all(id)?;
+        writer.write_all(format!("\t{}\t{}\n", record.seq.len(), offset).as_bytes())?;
+    }
+
+    writer.flush()?;
+
+    Ok(())
+}
+
+#[rstest]
+#[case(b"@r1 desc\nACGT\n+\nIIII\n", Some("r1 desc"), "ACGT", true)]
+#[case(b"\n>r1\nAC\nGT\n>r2\nA\n", Some("r1"), "ACGT", false)]
+#[case(b"", None, "", false)]
+fn test_read_record(
+    #[case] data: &[u8],
+    #[case] expected_id: Option<&str>,
+    #[case] expected_seq: &str,
+    #[case] expected_fastq: bool,
+) {
+    let mut reader = std::io::Cursor::new(data);
+
+    match read_record(&mut reader).unwrap() {
+        Some(record) => {
+            assert_eq!(
+                Some(record.id.as_slice()),
+                expected_id.map(|id| id.as_bytes())
+            );
+            assert_eq!(record.seq, expected_seq.as_bytes());
+            assert_eq!(record.qual.is_some(), expected_fastq);
+        }
+        None => assert_eq!(expected_id, None),
+    }
+}
@@ -0,0 +1,2 @@
+pub mod index;
+pub use index::{FastxIndex, IndexEntry, fastq_index, index_path, open_indexed, read_record};
@@ -0,0 +1,161 @@
+use crate::errors::AppError;
+use crate::fastx::FastxRecord;
+#[cfg(test)]
+use crate::test_utils::TempFile;
+use bio_utils_rs::io::{get_bufwriter, needletail_reader};
+use rstest::rstest;
+use std::io::Write;
+use std::path::PathBuf;
+
+/// Splits a read header into the mate name and mate number (if present), supporting
+/// both `read/1` suffixes and Casava style `read 1:N:0:ATCACG` descriptions.
+pub fn parse_mate(header: &[u8]) -> (&[u8], Option<u8>) {
+    let (id, description) = match header.iter().position(|c| c.is_ascii_whitespace()) {
+        Some(i) => (&header[..i], &header[i + 1..]),
+        None => (header, &b""[..]),
+    };
+
+    match (id, description) {
+        ([name @ .., b'/', mate @ (b'1' | b'2')], _) => (name, Some(mate - b'0')),
+        (_, [mate @ (b'1' | b'2'), b':', ..]) => (id, Some(mate - b'0')),
+        _ => (id, None),
+    }
+}
+
+/// Checks that two records are mates, with mate numbers 1 and 2 if present.
+pub fn check_mates(id_1: &[u8], id_2: &[u8]) -> Result<(), AppError> {
+    let (name_1, mate_1) = parse_mate(id_1);
+    let (name_2, mate_2) = parse_mate(id_2);
+
+    if name_1 != name_2 || !matches!(mate_1, None | Some(1)) || !matches!(mate_2, None | Some(2)) {
+        return Err(AppError::PairingError(format!(
+            "`{}` and `{}` are not mates",
+            String::from_utf8_lossy(id_1),
+            String::from_utf8_lossy(id_2)
+        )));
+    }
+
+    Ok(())
+}
+
+/// Writes reads from two files in lockstep (R1, R2, R1, R2, ...).
+pub fn fastq_interleave(
+    fastq_1: PathBuf,
+    fastq_2: PathBuf,
+    outfile: Option<PathBuf>,
+) -> Result<(), AppError> {
+    let mut reader_1 = needletail_reader(Some(fastq_1))?;
+    let mut reader_2 = needletail_reader(Some(fastq_2))?;
+    let mut writer = get_bufwriter(outfile)?;
+
+    // Skipping malformatted records would break the pairing, so we fail instead.
+    loop {
+        let (read_1, read_2) = match (reader_1.next(), reader_2.next()) {
+            (Some(read_1), Some(read_2)) => (read_1?, read_2?),
+            (None, None) => break,
+            _ => {
+                return Err(AppError::PairingError(
+                    "input files have a different number of reads".to_string(),
+                ));
+            }
+        };
+
+        check_mates(read_1.id(), read_2.id())?;
+
+        read_1.write(&mut writer, None)?;
+        read_2.write(&mut writer, None)?;
+    }
+
+    writer.flush()?;
+
+    Ok(())
+}
+
+/// Splits interleaved reads into separate R1 and R2 files.
+pub fn fastq_deinterleave(
+    fastq: Option<PathBuf>,
+    outfile_1: PathBuf,
+    outfile_2: PathBuf,
+) -> Result<(), AppError> {
+    let mut reader = needletail_reader(fastq)?;
+    let mut writer_1 = get_bufwriter(Some(outfile_1))?;
+    let mut writer_2 = get_bufwriter(Some(outfile_2))?;
+
+    // needletail records borrow from the reader, so R1 is copied before reading R2.
+    while let Some(read_1) = reader.next() {
+        let read_1 = FastxRecord::from_needletail(&read_1?);
+
+        let read_2 = match reader.next() {
+            Some(read_2) => read_2?,
+            None => {
+                return Err(AppError::PairingError(
+                    "input has an odd number of reads".to_string(),
+                ));
+            }
+        };
+
+        check_mates(&read_1.id, read_2.id())?;
+
+        read_1.write(&mut writer_1)?;
+        read_2.write(&mut writer_2, None)?;
+    }
+
+    writer_1.flush()?;
+    writer_2.flush()?;
+
+    Ok(())
+}
+
+#[rstest]
+#[case(b"read_1", b"read_1", None)]
+#[case(b"read_1/1", b"read_1", Some(1))]
+#[case(b"read_1/2 extra", b"read_1", Some(2))]
+#[case(b"read_1 1:N:0:ATCACG", b"read_1", Some(1))]
+#[case(b"read_1 2:Y:0:ATCACG", b"read_1", Some(2))]
+#[case(b"read_1/3", b"read_1/3", None)]
+fn test_parse_mate(
+    #[case] header: &[u8],
+    #[case] expected_name: &[u8],
+    #[case] expected_mate: Option<u8>,
+) {
+    assert_eq!(parse_mate(header), (expected_name, expected_mate));
+}
+
+#[rstest]
+#[case(b"read_1", b"read_1", true)]
+#[case(b"read_1/1", b"read_1/2", true)]
+#[case(b"read_1 1:N:0:ATCACG", b"read_1 2:N:0:ATCACG", true)]
+#[case(b"read_1/1", b"read_2/2", false)]
+#[case(b"read_1", b"read_2", false)]
+#[case(b"read_1/2", b"read_1/1", false)]
+#[case(b"read_1/1", b"read_1/1", false)]
+fn test_check_mates(#[case] id_1: &[u8], #[case] id_2: &[u8], #[case] expected_ok: bool) {
+    assert_eq!(check_mates(id_1, id_2).is_ok(), expected_ok);
+}
+
+#[rstest]
+#[case("@r1/2\nTTTT\n+\nIIII\n@r2/2\nGG\n+\n#I\n", true)]
+#[case("@r1/2\nTTTT\n+\nIIII\n@r3/2\nGG\n+\n#I\n", false)]
+#[case("@r1/2\nTTTT\n+\nIIII\n", false)]
+fn test_interleave_round_trip(#[case] reads_2: &str, #[case] expected_ok: bool) {
+    let reads_1 = "@r1/1 desc\nACGT\n+\nII5I\n@r2/1\nCC\n+\nII\n";
+
+    let fastq_1 = TempFile::with_content("in_R1.fastq", reads_1);
+    let fastq_2 = TempFile::with_content("in_R2.fastq", reads_2);
+    let interleaved = TempFile::new("interleaved.fastq");
+    let outfile_1 = TempFile::new("out_R1.fastq");
+    let outfile_2 = TempFile::new("out_R2.fastq");
+
+    let result = fastq_interleave(fastq_1.path(), fastq_2.path(), Some(interleaved.path()));
+
+    if !expected_ok {
+        assert!(matches!(result, Err(AppError::PairingError(_))));
+        return;
+    }
+
+    result.unwrap();
+    fastq_deinterleave(Some(interleaved.path()), outfile_1.path(), outfile_2.path()).unwrap();
+
+    assert_eq!(outfile_1.read_to_string(), reads_1);
+    assert_eq!(outfile_2.read_to_string(), reads_2);
+}
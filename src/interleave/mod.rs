@@ -0,0 +1,2 @@
+pub mod interleave;
+pub use interleave::{check_mates, fastq_deinterleave, fastq_interleave, parse_mate};
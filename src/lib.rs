@@ -0,0 +1,43 @@
+#![allow(clippy::module_inception, clippy::too_many_arguments)]
+
+pub mod args;
+pub mod bam;
+pub mod concat;
+pub mod dispatch;
+pub mod errors;
+pub mod evaluate;
+pub mod extract_umi;
+pub mod fa2fq;
+pub mod fastx;
+pub mod fetch;
+pub mod filter;
+pub mod fq2fa;
+pub mod fq2tab;
+pub mod grep;
+pub mod head;
+pub mod index;
+pub mod interleave;
+pub mod locate;
+pub mod merge_pairs;
+pub mod mock;
+pub mod pipeline;
+pub mod profile;
+pub mod renumber;
+pub mod sample;
+pub mod sanitize;
+pub mod seq;
+pub mod sort;
+pub mod split;
+pub mod stats;
+pub mod subseq;
+pub mod tab2fq;
+pub mod table;
+pub mod tail;
+pub mod trim;
+pub mod umi_dedup;
+
+#[cfg(test)]
+pub mod test_utils;
+
+#[cfg(feature = "python")]
+pub mod python;
@@ -0,0 +1,331 @@
+use crate::args::LocateMode;
+use crate::bam::KeepTags;
+use crate::errors::AppError;
+use crate::fastx::fastx_records;
+use crate::fq2tab::split_header;
+use crate::table::{ColumnType, TableColumn, TableValue, TableWriter, table_writer};
+use crate::trim::trim::myers_builder;
+use bio::pattern_matching::myers::Myers;
+use bio_utils_rs::io::get_bufwriter;
+use bio_utils_rs::nucleotide::reverse_complement;
+use regex::bytes::Regex;
+use rstest::rstest;
+use std::io::Write;
+use std::path::PathBuf;
+
+/// Bases matched by an IUPAC nucleotide code (`U` is treated as `T`).
+pub fn iupac_bases(nt: u8) -> Option<&'static [u8]> {
+    let bases: &[u8] = match nt {
+        b'A' => b"A",
+        b'C' => b"C",
+        b'G' => b"G",
+        b'T' | b'U' => b"T",
+        b'R' => b"AG",
+        b'Y' => b"CT",
+        b'S' => b"CG",
+        b'W' => b"AT",
+        b'K' => b"GT",
+        b'M' => b"AC",
+        b'B' => b"CGT",
+        b'D' => b"AGT",
+        b'H' => b"ACT",
+        b'V' => b"ACG",
+        b'N' => b"ACGT",
+        _ => return None,
+    };
+
+    Some(bases)
+}
+
+enum Matcher {
+    Regex(Regex),
+    Myers { myers: Box<Myers>, max_distance: u8 },
+}
+
+/// A pattern to locate in reads. Reads are searched in upper case, so patterns are
+/// upper cased too (except for regexes).
+pub struct Motif {
+    pub pattern: String,
+    matcher: Matcher,
+}
+
+impl Motif {
+    /// `max_mismatches` is the maximum edit distance for `LocateMode::Fuzzy` and
+    /// ignored otherwise.
+    pub fn new(pattern: &str, mode: LocateMode, max_mismatches: u8) -> Result<Self, AppError> {
+        let upper = pattern.to_ascii_uppercase();
+
+        let regex = match mode {
+            LocateMode::Exact => Some(regex::escape(&upper)),
+            LocateMode::Iupac => Some(
+                upper
+                    .bytes()
+                    .map(|nt| match iupac_bases(nt) {
+                        Some([base]) => Ok((*base as char).to_string()),
+                        Some(bases) => Ok(format!("[{}]", String::from_utf8_lossy(bases))),
+                        None => Err(AppError::InvalidArgumentError(format!(
+                            "invalid IUPAC pattern `{pattern}`"
+                        ))),
+                    })
+                    .collect::<Result<String, AppError>>()?,
+            ),
+            LocateMode::Regex => Some(pattern.to_string()),
+            LocateMode::Fuzzy => None,
+        };
+
+        let matcher = match regex {
+            Some(regex) => Matcher::Regex(
+                Regex::new(&regex).map_err(|err| AppError::RegexParsingError(err.to_string()))?,
+            ),
+            None => {
+                if upper.is_empty() || upper.len() > 64 {
+                    return Err(AppError::InvalidArgumentError(format!(
+                        "fuzzy pattern `{pattern}` must be 1 to 64 bases long"
+                    )));
+                }
+
+                Matcher::Myers {
+                    myers: Box::new(myers_builder(upper.as_bytes())),
+                    max_distance: max_mismatches,
+                }
+            }
+        };
+
+        Ok(Self {
+            pattern: pattern.to_string(),
+            matcher,
+        })
+    }
+
+    /// All hits in `seq` as (start, end, mismatches), 0-based and end exclusive.
+    /// Regex hits may overlap. Fuzzy hits are the best (lowest edit distance, then
+    /// leftmost) non-overlapping alignments.
+    pub fn find(&mut self, seq: &[u8]) -> Vec<(usize, usize, usize)> {
+        let mut hits: Vec<(usize, usize, usize)> = Vec::new();
+
+        match &mut self.matcher {
+            Matcher::Regex(regex) => {
+                let mut pos = 0;
+
+                while pos <= seq.len() {
+                    let Some(m) = regex.find_at(seq, pos) else {
+                        break;
+                    };
+
+                    if m.end() > m.start() {
+                        hits.push((m.start(), m.end(), 0));
+                    }
+                    pos = m.start() + 1;
+                }
+            }
+            Matcher::Myers {
+                myers,
+                max_distance,
+            } => {
+                let mut matches = myers.find_all_lazy(seq, *max_distance);
+
+                let mut candidates: Vec<(usize, u8)> = matches.by_ref().collect();
+                candidates.sort_by_key(|&(end, dist)| (dist, end));
+
+                let mut ops = Vec::new();
+
+                for (end, dist) in candidates {
+                    let Some((start, _)) = matches.path_at(end, &mut ops) else {
+                        continue;
+                    };
+                    let end = end + 1;
+
+                    if hits.iter().all(|&(s, e, _)| end <= s || e <= start) {
+                        hits.push((start, end, dist as usize));
+                    }
+                }
+
+                hits.sort_unstable();
+            }
+        }
+
+        hits
+    }
+}
+
+/// A motif hit. Coordinates are 0-based, end exclusive, on the forward strand of the
+/// read, also for hits on the reverse strand.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MotifHit {
+    /// Index of the motif in the list of patterns.
+    pub motif: usize,
+    pub start: usize,
+    pub end: usize,
+    pub reverse: bool,
+    pub mismatches: usize,
+}
+
+impl MotifHit {
+    pub fn strand(&self) -> &'static str {
+        match self.reverse {
+            true => "-",
+            false => "+",
+        }
+    }
+}
+
+/// Searches both strands of `seq` for all `motifs`. Hits are sorted by position.
+pub fn locate_motifs(seq: &[u8], motifs: &mut [Motif]) -> Vec<MotifHit> {
+    let forward = seq.to_ascii_uppercase();
+    let reverse = reverse_complement(&forward);
+    let len = seq.len();
+
+    let mut hits: Vec<MotifHit> = Vec::new();
+
+    for (i, motif) in motifs.iter_mut().enumerate() {
+        for (start, end, mismatches) in motif.find(&forward) {
+            hits.push(MotifHit {
+                motif: i,
+                start,
+                end,
+                reverse: false,
+                mismatches,
+            });
+        }
+
+        for (start, end, mismatches) in motif.find(&reverse) {
+            hits.push(MotifHit {
+                motif: i,
+                start: len - end,
+                end: len - start,
+                reverse: true,
+                mismatches,
+            });
+        }
+    }
+
+    hits.sort_by_key(|hit| (hit.start, hit.end, hit.motif, hit.reverse));
+
+    hits
+}
+
+enum HitWriter {
+    /// BED6, with the pattern as name and the mismatches as score.
+    Bed(Box<dyn Write + Send>),
+    Table(Box<dyn TableWriter>),
+}
+
+impl HitWriter {
+    fn new(outfile: Option<PathBuf>) -> Result<Self, AppError> {
+        let is_bed = outfile.as_ref().is_some_and(|path| {
+            let name = path.to_string_lossy().to_lowercase();
+            name.ends_with(".bed") || name.ends_with(".bed.gz")
+        });
+
+        match is_bed {
+            true => Ok(Self::Bed(get_bufwriter(outfile)?)),
+            false => Ok(Self::Table(table_writer(
+                outfile,
+                vec![
+                    TableColumn::new("read_id", ColumnType::Utf8),
+                    TableColumn::new("start", ColumnType::UInt64),
+                    TableColumn::new("end", ColumnType::UInt64),
+                    TableColumn::new("strand", ColumnType::DictUtf8),
+                    TableColumn::new("pattern", ColumnType::DictUtf8),
+                    TableColumn::new("mismatches", ColumnType::UInt64),
+                ],
+            )?)),
+        }
+    }
+
+    fn write(&mut self, read_id: &[u8], hit: &MotifHit, pattern: &str) -> Result<(), AppError> {
+        match self {
+            Self::Bed(writer) => {
+                writer.write_all(read_id)?;
+                writeln!(
+                    writer,
+                    "\t{}\t{}\t{}\t{}\t{}",
+                    hit.start,
+                    hit.end,
+                    pattern,
+                    hit.mismatches,
+                    hit.strand()
+                )?;
+            }
+            Self::Table(writer) => writer.write_row(&[
+                TableValue::Str(read_id),
+                TableValue::UInt(hit.start as u64),
+                TableValue::UInt(hit.end as u64),
+                TableValue::Str(hit.strand().as_bytes()),
+                TableValue::Str(pattern.as_bytes()),
+                TableValue::UInt(hit.mismatches as u64),
+            ])?,
+        }
+
+        Ok(())
+    }
+
+    fn finish(self) -> Result<(), AppError> {
+        match self {
+            Self::Bed(mut writer) => writer.flush()?,
+            Self::Table(writer) => writer.finish()?,
+        }
+
+        Ok(())
+    }
+}
+
+/// # Arguments
+/// * `fastq` - Path to FASTQ/FASTA file (optional, defaults to stdin).
+/// * `patterns` - Patterns to locate, interpreted according to `mode`.
+/// * `max_mismatches` - Maximum edit distance of fuzzy hits.
+/// * `outfile` - Where to write the hits (optional, defaults to stdout). Written as BED
+///   for `.bed` files and as a table (tsv, Parquet or Arrow) otherwise.
+pub fn fastq_locate(
+    fastq: Option<PathBuf>,
+    patterns: Vec<String>,
+    mode: LocateMode,
+    max_mismatches: u8,
+    outfile: Option<PathBuf>,
+) -> Result<(), AppError> {
+    let mut motifs = patterns
+        .iter()
+        .map(|pattern| Motif::new(pattern, mode, max_mismatches))
+        .collect::<Result<Vec<Motif>, AppError>>()?;
+
+    let records = fastx_records(fastq, &KeepTags::default())?;
+    let mut writer = HitWriter::new(outfile)?;
+
+    for record in records {
+        let record = record?;
+        let read_id = split_header(&record.id).0;
+
+        for hit in locate_motifs(&record.seq, &mut motifs) {
+            writer.write(read_id, &hit, &motifs[hit.motif].pattern)?;
+        }
+    }
+
+    writer.finish()?;
+
+    Ok(())
+}
+
+#[rstest]
+#[case("AA", LocateMode::Exact, 0, b"TAAAG", vec![(1, 3, false), (2, 4, false)])]
+#[case("gaattc", LocateMode::Exact, 0, b"ccGAATTCcc", vec![(2, 8, false), (2, 8, true)])]
+#[case("GRC", LocateMode::Iupac, 0, b"GACTTGGC", vec![(0, 3, false), (5, 8, false)])]
+#[case("AC+G", LocateMode::Regex, 0, b"TTACCCGTT", vec![(2, 7, false), (5, 8, true)])]
+#[case("CCGGTA", LocateMode::Exact, 0, b"AATACCGGAA", vec![(2, 8, true)])]
+#[case("ACGTACGT", LocateMode::Fuzzy, 1, b"TTTTACGTTCGTTTTT", vec![(4, 12, false), (4, 12, true)])]
+#[case("ACGTACGT", LocateMode::Fuzzy, 0, b"TTTTACGTTCGTTTTT", vec![])]
+fn test_locate_motifs(
+    #[case] pattern: &str,
+    #[case] mode: LocateMode,
+    #[case] max_mismatches: u8,
+    #[case] seq: &[u8],
+    #[case] expected: Vec<(usize, usize, bool)>,
+) {
+    let mut motifs = vec![Motif::new(pattern, mode, max_mismatches).unwrap()];
+
+    let hits: Vec<(usize, usize, bool)> = locate_motifs(seq, &mut motifs)
+        .iter()
+        .map(|hit| (hit.start, hit.end, hit.reverse))
+        .collect();
+
+    assert_eq!(hits, expected);
+}
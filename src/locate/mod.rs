@@ -0,0 +1,2 @@
+pub mod locate;
+pub use locate::{Motif, MotifHit, fastq_locate, iupac_bases, locate_motifs};
@@ -3,25 +3,8 @@ use log::error;
 use rayon::ThreadPoolBuilder;
 use simple_logger::SimpleLogger;
 
-mod args;
-mod concat;
-mod dispatch;
-mod errors;
-mod filter;
-mod fq2fa;
-mod fq2tab;
-mod grep;
-mod head;
-mod mock;
-mod renumber;
-mod sample;
-mod sanitize;
-mod sort;
-mod stats;
-mod trim;
-
-use args::App;
-use dispatch::dispatch;
+use fastq_rs::args::App;
+use fastq_rs::dispatch::dispatch;
 
 fn main() {
     SimpleLogger::new().init().unwrap();
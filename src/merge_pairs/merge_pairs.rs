@@ -0,0 +1,409 @@
+use crate::errors::AppError;
+use crate::fastx::{FastxRecord, fastx_writer};
+use crate::interleave::check_mates;
+#[cfg(test)]
+use crate::test_utils::TempFile;
+use bio_utils_rs::io::{needletail_reader, write_json};
+use bio_utils_rs::nucleotide::{PHRED_OFFSET, reverse_complement};
+use rstest::rstest;
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+
+/// Maximum phred score of merged bases where both mates agree.
+const MAX_MERGED_PHRED: u8 = 41;
+
+/// Minimum phred score of merged bases where the mates disagree.
+const MIN_MERGED_PHRED: u8 = 2;
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct MergeReport {
+    pub pairs: usize,
+    pub merged: usize,
+    pub unmerged: usize,
+    pub mean_overlap: f64,
+    pub mean_merged_len: f64,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct MergedPair {
+    pub seq: Vec<u8>,
+    pub qual: Vec<u8>,
+    pub overlap: usize,
+}
+
+/// Best overlap of R1 and reverse complemented R2 as `(offset of R2 in R1, overlap, mismatches)`,
+/// i.e. the longest overlap within `max_mismatch_rate`.
+/// Negative offsets mean that R2 starts before R1 (the insert is shorter than the reads).
+fn best_overlap(
+    seq_1: &[u8],
+    seq_2: &[u8],
+    min_overlap: usize,
+    max_mismatch_rate: f64,
+    allow_dovetail: bool,
+) -> Option<(isize, usize, usize)> {
+    let (len_1, len_2) = (seq_1.len() as isize, seq_2.len() as isize);
+    let min_overlap = min_overlap.max(1) as isize;
+
+    let min_offset = match allow_dovetail {
+        true => min_overlap - len_2,
+        false => 0,
+    };
+
+    let mut best: Option<(isize, usize, usize)> = None;
+
+    for offset in min_offset..=(len_1 - min_overlap) {
+        let start = offset.max(0);
+        let end = len_1.min(offset + len_2);
+        let overlap = (end - start) as usize;
+
+        if overlap < min_overlap as usize {
+            continue;
+        }
+
+        let max_mismatches = (max_mismatch_rate * overlap as f64) as usize;
+
+        let mut mismatches = 0;
+        for i in start..end {
+            if seq_1[i as usize] != seq_2[(i - offset) as usize] {
+                mismatches += 1;
+
+                if mismatches > max_mismatches {
+                    break;
+                }
+            }
+        }
+
+        if mismatches > max_mismatches {
+            continue;
+        }
+
+        // Longest overlap, then fewest mismatches. Ranking by mismatch rate first would let
+        // short, perfect but spurious overlaps (e.g. in repeats) beat the true overlap with
+        // a single sequencing error.
+        let is_better = match best {
+            None => true,
+            Some((_, best_overlap, best_mismatches)) => {
+                overlap > best_overlap || (overlap == best_overlap && mismatches < best_mismatches)
+            }
+        };
+
+        if is_better {
+            best = Some((offset, overlap, mismatches));
+        }
+    }
+
+    best
+}
+
+/// Merges R1 and R2 (both 5' -> 3', qualities phred+33) if they overlap by at least
+/// `min_overlap` bases with at most `max_mismatch_rate` mismatches. In the overlap,
+/// phred scores of agreeing bases are summed (capped at 41), and for disagreeing bases
+/// the base with the highest quality is kept with the difference as its quality.
+pub fn merge_pair(
+    seq_1: &[u8],
+    qual_1: &[u8],
+    seq_2: &[u8],
+    qual_2: &[u8],
+    min_overlap: usize,
+    max_mismatch_rate: f64,
+    allow_dovetail: bool,
+) -> Option<MergedPair> {
+    let seq_2 = reverse_complement(seq_2);
+    let qual_2: Vec<u8> = qual_2.iter().rev().copied().collect();
+
+    let (offset, overlap, _) = best_overlap(
+        seq_1,
+        &seq_2,
+        min_overlap,
+        max_mismatch_rate,
+        allow_dovetail,
+    )?;
+
+    let (len_1, len_2) = (seq_1.len() as isize, seq_2.len() as isize);
+
+    // Dovetailed overhangs are adapter sequence, so the merged read is the overlap.
+    let end = match offset < 0 {
+        true => len_1.min(offset + len_2),
+        false => len_1.max(offset + len_2),
+    };
+
+    let mut seq: Vec<u8> = Vec::with_capacity(end as usize);
+    let mut qual: Vec<u8> = Vec::with_capacity(end as usize);
+
+    for i in 0..end {
+        let base_1 = (i < len_1).then(|| (seq_1[i as usize], qual_1[i as usize]));
+        let base_2 = (i >= offset && i < offset + len_2)
+            .then(|| (seq_2[(i - offset) as usize], qual_2[(i - offset) as usize]));
+
+        let (nt, q) = match (base_1, base_2) {
+            (Some((nt_1, q_1)), Some((nt_2, q_2))) => {
+                let (p_1, p_2) = (
+                    q_1.saturating_sub(PHRED_OFFSET as u8),
+                    q_2.saturating_sub(PHRED_OFFSET as u8),
+                );
+
+                match (nt_1 == nt_2, p_1 >= p_2) {
+                    (true, _) => (nt_1, (p_1 + p_2).min(MAX_MERGED_PHRED)),
+                    (false, true) => (nt_1, (p_1 - p_2).max(MIN_MERGED_PHRED)),
+                    (false, false) => (nt_2, (p_2 - p_1).max(MIN_MERGED_PHRED)),
+                }
+            }
+            (Some((nt, q)), None) | (None, Some((nt, q))) => {
+                (nt, q.saturating_sub(PHRED_OFFSET as u8))
+            }
+            (None, None) => unreachable!(),
+        };
+
+        seq.push(nt);
+        qual.push(q + PHRED_OFFSET as u8);
+    }
+
+    Some(MergedPair { seq, qual, overlap })
+}
+
+/// Read id of a merged pair, i.e. the R1 id without a `/1` mate suffix.
+fn merged_id(id_1: &[u8]) -> Vec<u8> {
+    let name_end = id_1
+        .iter()
+        .position(|c| c.is_ascii_whitespace())
+        .unwrap_or(id_1.len());
+
+    match id_1[..name_end].ends_with(b"/1") {
+        true => [&id_1[..name_end - 2], &id_1[name_end..]].concat(),
+        false => id_1.to_vec(),
+    }
+}
+
+pub fn fastq_merge_pairs(
+    fastq_1: PathBuf,
+    fastq_2: PathBuf,
+    min_overlap: usize,
+    max_mismatch_rate: f64,
+    allow_dovetail: bool,
+    outfile: Option<PathBuf>,
+    unmerged_1: PathBuf,
+    unmerged_2: PathBuf,
+    report: PathBuf,
+) -> Result<MergeReport, AppError> {
+    let mut reader_1 = needletail_reader(Some(fastq_1))?;
+    let mut reader_2 = needletail_reader(Some(fastq_2))?;
+
+    let mut writer = fastx_writer(outfile)?;
+    let mut writer_1 = fastx_writer(Some(unmerged_1))?;
+    let mut writer_2 = fastx_writer(Some(unmerged_2))?;
+
+    let mut merge_report = MergeReport::default();
+    let (mut total_overlap, mut total_merged_len) = (0, 0);
+
+    loop {
+        let (read_1, read_2) = match (reader_1.next(), reader_2.next()) {
+            (Some(read_1), Some(read_2)) => (
+                FastxRecord::from_needletail(&read_1?),
+                FastxRecord::from_needletail(&read_2?),
+            ),
+            (None, None) => break,
+            _ => {
+                return Err(AppError::PairingError(
+                    "input files have a different number of reads".to_string(),
+                ));
+            }
+        };
+
+        check_mates(&read_1.id, &read_2.id)?;
+
+        let (qual_1, qual_2) = match (read_1.qual.as_ref(), read_2.qual.as_ref()) {
+            (Some(qual_1), Some(qual_2)) => (qual_1, qual_2),
+            _ => return Err(AppError::MissingQualityError("merge-pairs".to_string())),
+        };
+
+        merge_report.pairs += 1;
+
+        let merged = merge_pair(
+            &read_1.seq,
+            qual_1,
+            &read_2.seq,
+            qual_2,
+            min_overlap,
+            max_mismatch_rate,
+            allow_dovetail,
+        );
+
+        match merged {
+            Some(merged) => {
+                merge_report.merged += 1;
+                total_overlap += merged.overlap;
+                total_merged_len += merged.seq.len();
+
+                writer.write_record(&FastxRecord {
+                    id: merged_id(&read_1.id),
+                    seq: merged.seq,
+                    qual: Some(merged.qual),
+                })?;
+            }
+            None => {
+                merge_report.unmerged += 1;
+
+                writer_1.write_record(&read_1)?;
+                writer_2.write_record(&read_2)?;
+            }
+        }
+    }
+
+    writer.finish()?;
+    writer_1.finish()?;
+    writer_2.finish()?;
+
+    if merge_report.merged > 0 {
+        merge_report.mean_overlap = total_overlap as f64 / merge_report.merged as f64;
+        merge_report.mean_merged_len = total_merged_len as f64 / merge_report.merged as f64;
+    }
+
+    write_json(Some(report), &merge_report)?;
+
+    Ok(merge_report)
+}
+
+#[rstest]
+// Fragment longer than the reads, 4 bp overlap (R2 is the reverse complement of GGTACCCA).
+#[case(b"AACCGGTA", b"TGGGTACC", 4, false, Some((b"AACCGGTACCCA".as_slice(), 4)))]
+// No overlap.
+#[case(b"AAAAAAAA", b"AAAAAAAA", 4, false, None)]
+// Insert shorter than the reads, R2 reads into adapter (GG).
+#[case(b"ACGTACCC", b"GTACGTGG", 6, true, Some((b"ACGTAC".as_slice(), 6)))]
+#[case(b"ACGTACCC", b"GTACGTGG", 6, false, None)]
+fn test_merge_pair(
+    #[case] seq_1: &[u8],
+    #[case] seq_2: &[u8],
+    #[case] min_overlap: usize,
+    #[case] allow_dovetail: bool,
+    #[case] expected: Option<(&[u8], usize)>,
+) {
+    let (qual_1, qual_2) = (vec![b'I'; seq_1.len()], vec![b'I'; seq_2.len()]);
+
+    let merged = merge_pair(
+        seq_1,
+        &qual_1,
+        seq_2,
+        &qual_2,
+        min_overlap,
+        0.0,
+        allow_dovetail,
+    );
+
+    assert_eq!(
+        merged.as_ref().map(|m| (m.seq.as_slice(), m.overlap)),
+        expected
+    );
+}
+
+#[rstest]
+// R1 is a tandem repeat, so the second copy overlaps the start of R2 perfectly, but the true
+// overlap is the full length, with one sequencing error.
+#[case(b"ACGTTGCAACACGTTGCAAC", b"ACGTTGCAACACGTTGCTAC", 0.1, Some((0, 20, 1)))]
+#[case(b"ACGTTGCAACACGTTGCAAC", b"ACGTTGCAACACGTTGCTAC", 0.0, Some((10, 10, 0)))]
+fn test_best_overlap(
+    #[case] seq_1: &[u8],
+    #[case] seq_2_rc: &[u8],
+    #[case] max_mismatch_rate: f64,
+    #[case] expected: Option<(isize, usize, usize)>,
+) {
+    assert_eq!(
+        best_overlap(seq_1, seq_2_rc, 10, max_mismatch_rate, false),
+        expected
+    );
+}
+
+#[rstest]
+#[case(b'I', b'I', b'A', (b'A', MAX_MERGED_PHRED))]
+#[case(b'I', b'5', b'T', (b'A', 40 - 20))]
+#[case(b'5', b'I', b'T', (b'T', 40 - 20))]
+#[case(b'#', b'#', b'T', (b'A', MIN_MERGED_PHRED))]
+fn test_merged_quality(
+    #[case] qual_1: u8,
+    #[case] qual_2: u8,
+    #[case] nt_2: u8,
+    #[case] expected: (u8, u8),
+) {
+    // Single base overlap, R2 is given as the reverse complement.
+    let seq_2 = reverse_complement(&[nt_2]);
+    let merged = merge_pair(b"A", &[qual_1], &seq_2, &[qual_2], 1, 1.0, false).unwrap();
+
+    assert_eq!(
+        (merged.seq[0], merged.qual[0] - PHRED_OFFSET as u8),
+        expected
+    );
+}
+
+#[rstest]
+#[case(b"read_1/1", b"read_1")]
+#[case(b"read_1/1 desc", b"read_1 desc")]
+#[case(b"read_1 1:N:0:ATCACG", b"read_1 1:N:0:ATCACG")]
+#[case(b"read_1", b"read_1")]
+fn test_merged_id(#[case] id_1: &[u8], #[case] expected: &[u8]) {
+    assert_eq!(merged_id(id_1), expected);
+}
+
+#[rstest]
+#[case(2, true)]
+#[case(1, false)]
+fn test_fastq_merge_pairs(#[case] num_reads_2: usize, #[case] expected_ok: bool) {
+    // The first pair overlaps by 4 bp, the second does not overlap.
+    let reads_2 = [
+        "@r1/2\nTGGGTACC\n+\nIIIIIIII\n",
+        "@r2/2\nAAAAAAAA\n+\nIIIIIIII\n",
+    ];
+
+    let fastq_1 = TempFile::with_content(
+        "in_R1.fastq",
+        "@r1/1\nAACCGGTA\n+\nIIIIIIII\n@r2/1\nAAAAAAAA\n+\nIIIIIIII\n",
+    );
+    let fastq_2 = TempFile::with_content("in_R2.fastq", reads_2[..num_reads_2].concat());
+    let outfile = TempFile::new("merged.fastq");
+    let unmerged_1 = TempFile::new("unmerged_R1.fastq");
+    let unmerged_2 = TempFile::new("unmerged_R2.fastq");
+    let report = TempFile::new("report.json");
+
+    let result = fastq_merge_pairs(
+        fastq_1.path(),
+        fastq_2.path(),
+        4,
+        0.0,
+        false,
+        Some(outfile.path()),
+        unmerged_1.path(),
+        unmerged_2.path(),
+        report.path(),
+    );
+
+    if !expected_ok {
+        assert!(matches!(result, Err(AppError::PairingError(_))));
+        return;
+    }
+
+    result.unwrap();
+
+    assert_eq!(
+        outfile.read_to_string(),
+        "@r1\nAACCGGTACCCA\n+\nIIIIJJJJIIII\n"
+    );
+    assert_eq!(
+        unmerged_1.read_to_string(),
+        "@r2/1\nAAAAAAAA\n+\nIIIIIIII\n"
+    );
+    assert_eq!(
+        unmerged_2.read_to_string(),
+        "@r2/2\nAAAAAAAA\n+\nIIIIIIII\n"
+    );
+
+    let merge_report: MergeReport = serde_json::from_str(&report.read_to_string()).unwrap();
+    assert_eq!(
+        (
+            merge_report.pairs,
+            merge_report.merged,
+            merge_report.unmerged
+        ),
+        (2, 1, 1)
+    );
+    assert_eq!(merge_report.mean_overlap, 4.0);
+    assert_eq!(merge_report.mean_merged_len, 12.0);
+}
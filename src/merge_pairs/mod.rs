@@ -0,0 +1,2 @@
+pub mod merge_pairs;
+pub use merge_pairs::{MergeReport, MergedPair, fastq_merge_pairs, merge_pair};
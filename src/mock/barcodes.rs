@@ -0,0 +1,332 @@
+use crate::errors::AppError;
+use crate::fastx::text_reader;
+use crate::trim::Orientation;
+use bio_utils_rs::nucleotide::reverse_complement;
+use rand::prelude::*;
+use rstest::rstest;
+use std::io::BufRead;
+use std::path::PathBuf;
+
+const NTS: [u8; 4] = [b'A', b'C', b'G', b'T'];
+
+/// One row of the barcode sheet. Barcodes and adapters are given 5' -> 3', like for
+/// `trim`. Reads are built as `adapter_forward + barcode_forward + insert +
+/// rc(barcode_reverse) + rc(adapter_reverse)`. Empty sequences are left out.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SampleBarcodes {
+    pub sample: String,
+    pub barcode_forward: Vec<u8>,
+    pub barcode_reverse: Vec<u8>,
+    pub adapter_forward: Vec<u8>,
+    pub adapter_reverse: Vec<u8>,
+}
+
+/// Tab separated, with a header containing `sample`, `barcode_forward` and
+/// `barcode_reverse` and optionally `adapter_forward` and `adapter_reverse`.
+pub fn read_barcode_sheet(path: PathBuf) -> Result<Vec<SampleBarcodes>, AppError> {
+    let reader = text_reader(Some(path.clone()))?;
+    let mut lines = reader.lines();
+
+    let header = match lines.next() {
+        Some(header) => header?,
+        None => {
+            return Err(AppError::InvalidArgumentError(format!(
+                "empty barcode sheet {}",
+                path.display()
+            )));
+        }
+    };
+    let columns: Vec<&str> = header.trim_end().split('\t').collect();
+
+    let column = |name: &str| columns.iter().position(|&c| c == name);
+
+    let (sample, barcode_forward, barcode_reverse) = match (
+        column("sample"),
+        column("barcode_forward"),
+        column("barcode_reverse"),
+    ) {
+        (Some(s), Some(f), Some(r)) => (s, f, r),
+        _ => {
+            return Err(AppError::InvalidArgumentError(format!(
+                "barcode sheet {} requires the columns sample, barcode_forward and barcode_reverse",
+                path.display()
+            )));
+        }
+    };
+    let adapter_forward = column("adapter_forward");
+    let adapter_reverse = column("adapter_reverse");
+
+    let mut samples: Vec<SampleBarcodes> = Vec::new();
+
+    for line in lines {
+        let line = line?;
+
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let fields: Vec<&str> = line.trim_end_matches(['\r', '\n']).split('\t').collect();
+        let field = |i: Option<usize>| {
+            i.and_then(|i| fields.get(i))
+                .map(|f| f.trim().to_ascii_uppercase().into_bytes())
+                .unwrap_or_default()
+        };
+
+        samples.push(SampleBarcodes {
+            sample: fields.get(sample).unwrap_or(&"").trim().to_string(),
+            barcode_forward: field(Some(barcode_forward)),
+            barcode_reverse: field(Some(barcode_reverse)),
+            adapter_forward: field(adapter_forward),
+            adapter_reverse: field(adapter_reverse),
+        });
+    }
+
+    if samples.is_empty() {
+        return Err(AppError::InvalidArgumentError(format!(
+            "no samples in barcode sheet {}",
+            path.display()
+        )));
+    }
+
+    Ok(samples)
+}
+
+/// Where the barcodes of a read truly are. Coordinates are 0-based, end exclusive,
+/// in the forward orientation of the read (i.e. after reverse complementing reads
+/// in reverse orientation), like the `trim` barcodes report.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BarcodeTruth {
+    pub sample: usize,
+    /// Sample of the second molecule for chimeric reads.
+    pub chimera_sample: Option<usize>,
+    pub orientation: Orientation,
+    pub forward: Option<(usize, usize)>,
+    pub reverse: Option<(usize, usize)>,
+    /// Number of mutations in the forward and reverse barcode.
+    pub forward_edits: usize,
+    pub reverse_edits: usize,
+}
+
+/// A single barcoded molecule, with the spans and number of mutations of both barcodes.
+struct Molecule {
+    seq: Vec<u8>,
+    forward: Option<(usize, usize)>,
+    reverse: Option<(usize, usize)>,
+    forward_edits: usize,
+    reverse_edits: usize,
+}
+
+/// Attaches barcodes (and adapters) from a barcode sheet to reads.
+pub struct BarcodeSimulator {
+    pub samples: Vec<SampleBarcodes>,
+    /// Per base probability of a substitution, insertion or deletion in a barcode.
+    pub mutation_rate: f64,
+    /// Probability that an adapter is truncated at a random length.
+    pub truncation_rate: f64,
+    pub random_orientation: bool,
+    /// Probability that a read is a chimera of two barcoded molecules.
+    pub chimera_rate: f64,
+}
+
+impl BarcodeSimulator {
+    pub fn new(
+        barcode_sheet: PathBuf,
+        mutation_rate: f64,
+        truncation_rate: f64,
+        random_orientation: bool,
+        chimera_rate: f64,
+    ) -> Result<Self, AppError> {
+        for (name, rate) in [
+            ("barcode_mutation_rate", mutation_rate),
+            ("adapter_truncation_rate", truncation_rate),
+            ("chimera_rate", chimera_rate),
+        ] {
+            if !(0.0..=1.0).contains(&rate) {
+                return Err(AppError::InvalidArgumentError(format!(
+                    "{name} must be between 0 and 1"
+                )));
+            }
+        }
+
+        Ok(Self {
+            samples: read_barcode_sheet(barcode_sheet)?,
+            mutation_rate,
+            truncation_rate,
+            random_orientation,
+            chimera_rate,
+        })
+    }
+
+    pub fn is_chimera<R: Rng>(&self, rng: &mut R) -> bool {
+        rng.random_bool(self.chimera_rate)
+    }
+
+    /// Builds a barcoded read from `insert` (and `chimera_insert` for chimeras), with a
+    /// random sample per molecule. Returns the true read sequence, before sequencing errors.
+    pub fn attach<R: Rng>(
+        &self,
+        insert: &[u8],
+        chimera_insert: Option<&[u8]>,
+        rng: &mut R,
+    ) -> (Vec<u8>, BarcodeTruth) {
+        let sample = rng.random_range(0..self.samples.len());
+        let mut molecule = self.molecule(&self.samples[sample], insert, rng);
+
+        // The second molecule is appended, so the read ends with its reverse barcode.
+        let chimera_sample = chimera_insert.map(|chimera_insert| {
+            let chimera_sample = rng.random_range(0..self.samples.len());
+            let chimera = self.molecule(&self.samples[chimera_sample], chimera_insert, rng);
+
+            let offset = molecule.seq.len();
+            molecule.seq.extend(chimera.seq);
+            molecule.reverse = chimera
+                .reverse
+                .map(|(start, end)| (start + offset, end + offset));
+            molecule.reverse_edits = chimera.reverse_edits;
+
+            chimera_sample
+        });
+
+        let mut seq = molecule.seq;
+
+        let orientation = match self.random_orientation && rng.random_bool(0.5) {
+            true => Orientation::Reverse,
+            false => Orientation::Forward,
+        };
+
+        if orientation == Orientation::Reverse {
+            seq = reverse_complement(&seq);
+        }
+
+        let truth = BarcodeTruth {
+            sample,
+            chimera_sample,
+            orientation,
+            forward: molecule.forward,
+            reverse: molecule.reverse,
+            forward_edits: molecule.forward_edits,
+            reverse_edits: molecule.reverse_edits,
+        };
+
+        (seq, truth)
+    }
+
+    fn molecule<R: Rng>(&self, barcodes: &SampleBarcodes, insert: &[u8], rng: &mut R) -> Molecule {
+        let mut seq: Vec<u8> = Vec::with_capacity(insert.len() + 128);
+
+        // Adapters are truncated from the outside.
+        let adapter_forward = &barcodes.adapter_forward;
+        let keep = self.truncated_len(adapter_forward.len(), rng);
+        seq.extend_from_slice(&adapter_forward[adapter_forward.len() - keep..]);
+
+        let (barcode_forward, forward_edits) =
+            mutate(&barcodes.barcode_forward, self.mutation_rate, rng);
+        let forward = (!barcodes.barcode_forward.is_empty())
+            .then(|| (seq.len(), seq.len() + barcode_forward.len()));
+        seq.extend(barcode_forward);
+
+        seq.extend_from_slice(insert);
+
+        let (barcode_reverse, reverse_edits) = mutate(
+            &reverse_complement(&barcodes.barcode_reverse),
+            self.mutation_rate,
+            rng,
+        );
+        let reverse = (!barcodes.barcode_reverse.is_empty())
+            .then(|| (seq.len(), seq.len() + barcode_reverse.len()));
+        seq.extend(barcode_reverse);
+
+        let adapter_reverse = reverse_complement(&barcodes.adapter_reverse);
+        let keep = self.truncated_len(adapter_reverse.len(), rng);
+        seq.extend_from_slice(&adapter_reverse[..keep]);
+
+        Molecule {
+            seq,
+            forward,
+            reverse,
+            forward_edits,
+            reverse_edits,
+        }
+    }
+
+    fn truncated_len<R: Rng>(&self, len: usize, rng: &mut R) -> usize {
+        match len > 0 && rng.random_bool(self.truncation_rate) {
+            true => rng.random_range(0..len),
+            false => len,
+        }
+    }
+}
+
+/// Applies random substitutions, insertions and deletions (in a 2:1:1 ratio) with a
+/// per base probability of `rate`. Returns the mutated sequence and the number of edits.
+fn mutate<R: Rng>(seq: &[u8], rate: f64, rng: &mut R) -> (Vec<u8>, usize) {
+    let mut mutated: Vec<u8> = Vec::with_capacity(seq.len() + 2);
+    let mut edits = 0;
+
+    for &base in seq {
+        if !rng.random_bool(rate) {
+            mutated.push(base);
+            continue;
+        }
+
+        edits += 1;
+
+        match rng.random_range(0..4) {
+            0 => {}
+            1 => mutated.extend([NTS[rng.random_range(0..NTS.len())], base]),
+            _ => mutated.push(
+                *NTS.iter()
+                    .filter(|&&nt| nt != base)
+                    .choose(rng)
+                    .expect("No substitute base"),
+            ),
+        }
+    }
+
+    (mutated, edits)
+}
+
+#[rstest]
+#[case(false, false)]
+#[case(true, false)]
+#[case(false, true)]
+fn test_attach_barcodes(#[case] reverse: bool, #[case] chimera: bool) {
+    let simulator = BarcodeSimulator {
+        samples: vec![SampleBarcodes {
+            sample: "sample_1".to_string(),
+            barcode_forward: b"AAAAAAAA".to_vec(),
+            barcode_reverse: b"CCCCCCCC".to_vec(),
+            adapter_forward: b"TTTT".to_vec(),
+            adapter_reverse: b"TTTT".to_vec(),
+        }],
+        mutation_rate: 0.0,
+        truncation_rate: 0.0,
+        random_orientation: reverse,
+        chimera_rate: 0.0,
+    };
+
+    let insert = b"ACGTACGTAC";
+    let chimera_insert = chimera.then_some(b"ACGT".as_slice());
+
+    // Draw until the orientation matches the case.
+    let mut rng = StdRng::seed_from_u64(42);
+    let (mut seq, truth) = loop {
+        let (seq, truth) = simulator.attach(insert, chimera_insert, &mut rng);
+        if (truth.orientation == Orientation::Reverse) == reverse {
+            break (seq, truth);
+        }
+    };
+
+    if truth.orientation == Orientation::Reverse {
+        seq = reverse_complement(&seq);
+    }
+
+    let (forward_start, forward_end) = truth.forward.unwrap();
+    let (reverse_start, reverse_end) = truth.reverse.unwrap();
+
+    assert_eq!(&seq[forward_start..forward_end], b"AAAAAAAA");
+    assert_eq!(&seq[reverse_start..reverse_end], b"GGGGGGGG");
+    assert_eq!(&seq[forward_end..forward_end + insert.len()], insert);
+    assert_eq!(truth.chimera_sample.is_some(), chimera);
+    assert_eq!(seq.len(), reverse_end + 4);
+}
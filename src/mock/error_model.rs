@@ -0,0 +1,293 @@
+use crate::args::ErrorModel;
+use rand::prelude::*;
+use rstest::rstest;
+
+const NTS: [u8; 4] = [b'A', b'C', b'G', b'T'];
+
+/// Emitted qualities are clamped to this range (phred, without offset).
+const MIN_PHRED: f64 = 2.0;
+const MAX_PHRED: f64 = 41.0;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ErrorType {
+    Substitution,
+    Insertion,
+    Deletion,
+}
+
+impl ErrorType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ErrorType::Substitution => "substitution",
+            ErrorType::Insertion => "insertion",
+            ErrorType::Deletion => "deletion",
+        }
+    }
+}
+
+/// A sequencing error injected into a read. `true_pos` is the position in the
+/// true sequence, `read_pos` the position in the emitted read. For deletions,
+/// `read_pos` is where the missing base would have been.
+#[derive(Debug, Clone, PartialEq)]
+pub struct InjectedError {
+    pub error_type: ErrorType,
+    pub true_pos: usize,
+    pub read_pos: usize,
+    pub true_base: Option<u8>,
+    pub read_base: Option<u8>,
+}
+
+/// Parameters of a sequencing error model. Each base gets an error probability
+/// which is converted to the emitted phred quality, and the error is then drawn with
+/// the probability of that (rounded) quality, so qualities are calibrated by construction.
+#[derive(Debug, Clone)]
+pub struct ErrorProfile {
+    /// Error probability at the first and the last base, linearly interpolated in between.
+    start_error_rate: f64,
+    end_error_rate: f64,
+    /// Fraction of errors that are insertions and deletions, the rest are substitutions.
+    insertion_fraction: f64,
+    deletion_fraction: f64,
+    /// Error probability is multiplied by `1 + homopolymer_factor * (run length - 1)`.
+    homopolymer_factor: f64,
+    /// Per base noise on the quality (standard deviation, phred units).
+    phred_sd: f64,
+}
+
+impl ErrorProfile {
+    pub fn from_model(model: &ErrorModel) -> Option<Self> {
+        match model {
+            ErrorModel::None => None,
+            // Mostly substitutions, quality dropping towards the 3' end.
+            ErrorModel::Illumina => Some(Self {
+                start_error_rate: 0.001,
+                end_error_rate: 0.02,
+                insertion_fraction: 0.01,
+                deletion_fraction: 0.01,
+                homopolymer_factor: 0.0,
+                phred_sd: 3.0,
+            }),
+            // High error rate, dominated by indels in homopolymers.
+            ErrorModel::Nanopore => Some(Self {
+                start_error_rate: 0.05,
+                end_error_rate: 0.05,
+                insertion_fraction: 0.25,
+                deletion_fraction: 0.35,
+                homopolymer_factor: 0.5,
+                phred_sd: 4.0,
+            }),
+            // HiFi-like, low error rate but the remaining errors are homopolymer indels.
+            ErrorModel::Pacbio => Some(Self {
+                start_error_rate: 0.003,
+                end_error_rate: 0.003,
+                insertion_fraction: 0.4,
+                deletion_fraction: 0.4,
+                homopolymer_factor: 1.0,
+                phred_sd: 5.0,
+            }),
+        }
+    }
+
+    /// Draws a phred quality for the base at `pos` in a read of `len` bases,
+    /// which is part of a homopolymer run of `run_len` bases.
+    fn draw_phred<R: Rng>(&self, pos: usize, len: usize, run_len: usize, rng: &mut R) -> u8 {
+        let fraction = match len {
+            0 | 1 => 0.0,
+            _ => pos as f64 / (len - 1) as f64,
+        };
+
+        let error_rate = (self.start_error_rate
+            + (self.end_error_rate - self.start_error_rate) * fraction)
+            * (1.0 + self.homopolymer_factor * (run_len - 1) as f64);
+
+        let phred = -10.0 * error_rate.min(1.0).log10() + self.phred_sd * standard_normal(rng);
+
+        phred.clamp(MIN_PHRED, MAX_PHRED).round() as u8
+    }
+
+    /// Simulates sequencing of `true_seq`. Returns the read sequence and phred
+    /// qualities (without offset) and the injected errors.
+    pub fn sequence<R: Rng>(
+        &self,
+        true_seq: &[u8],
+        rng: &mut R,
+    ) -> (Vec<u8>, Vec<u8>, Vec<InjectedError>) {
+        let run_lengths = homopolymer_run_lengths(true_seq);
+
+        let phreds: Vec<u8> = run_lengths
+            .iter()
+            .enumerate()
+            .map(|(pos, &run_len)| self.draw_phred(pos, true_seq.len(), run_len, rng))
+            .collect();
+
+        self.sequence_with_phreds(true_seq, &phreds, rng)
+    }
+
+    /// Like `sequence`, but with given phred qualities (without offset) per base of
+    /// `true_seq`, e.g. drawn from a read profile.
+    pub fn sequence_with_phreds<R: Rng>(
+        &self,
+        true_seq: &[u8],
+        phreds: &[u8],
+        rng: &mut R,
+    ) -> (Vec<u8>, Vec<u8>, Vec<InjectedError>) {
+        let mut seq: Vec<u8> = Vec::with_capacity(true_seq.len() + true_seq.len() / 10);
+        let mut qual: Vec<u8> = Vec::with_capacity(seq.capacity());
+        let mut errors: Vec<InjectedError> = Vec::new();
+
+        let run_lengths = homopolymer_run_lengths(true_seq);
+
+        for (true_pos, ((&base, &run_len), &phred)) in true_seq
+            .iter()
+            .zip(run_lengths.iter())
+            .zip(phreds.iter())
+            .enumerate()
+        {
+            let error_probability = 10_f64.powf(-(phred as f64) / 10.0);
+
+            if rng.random::<f64>() >= error_probability {
+                seq.push(base);
+                qual.push(phred);
+                continue;
+            }
+
+            let r = rng.random::<f64>();
+
+            if r < self.deletion_fraction {
+                errors.push(InjectedError {
+                    error_type: ErrorType::Deletion,
+                    true_pos,
+                    read_pos: seq.len(),
+                    true_base: Some(base),
+                    read_base: None,
+                });
+            } else if r < self.deletion_fraction + self.insertion_fraction {
+                // Homopolymers are extended, elsewhere a random base is inserted.
+                let inserted = match run_len {
+                    1 => NTS[rng.random_range(0..NTS.len())],
+                    _ => base,
+                };
+
+                errors.push(InjectedError {
+                    error_type: ErrorType::Insertion,
+                    true_pos,
+                    read_pos: seq.len(),
+                    true_base: None,
+                    read_base: Some(inserted),
+                });
+
+                seq.extend([inserted, base]);
+                qual.extend([phred, phred]);
+            } else {
+                let substituted = *NTS
+                    .iter()
+                    .filter(|&&nt| nt != base)
+                    .choose(rng)
+                    .expect("No substitute base");
+
+                errors.push(InjectedError {
+                    error_type: ErrorType::Substitution,
+                    true_pos,
+                    read_pos: seq.len(),
+                    true_base: Some(base),
+                    read_base: Some(substituted),
+                });
+
+                seq.push(substituted);
+                qual.push(phred);
+            }
+        }
+
+        (seq, qual, errors)
+    }
+}
+
+/// Position in the emitted read of position `true_pos` in the true sequence, i.e.
+/// shifted by the insertions and deletions before it.
+pub fn read_position(errors: &[InjectedError], true_pos: usize) -> usize {
+    errors
+        .iter()
+        .filter(|error| error.true_pos < true_pos)
+        .fold(true_pos, |pos, error| match error.error_type {
+            ErrorType::Insertion => pos + 1,
+            ErrorType::Deletion => pos - 1,
+            ErrorType::Substitution => pos,
+        })
+}
+
+/// Length of the homopolymer run that each base is part of.
+fn homopolymer_run_lengths(seq: &[u8]) -> Vec<usize> {
+    let mut run_lengths: Vec<usize> = Vec::with_capacity(seq.len());
+
+    for run in seq.chunk_by(|a, b| a == b) {
+        run_lengths.extend(std::iter::repeat_n(run.len(), run.len()));
+    }
+
+    run_lengths
+}
+
+/// Box-Muller transform.
+pub fn standard_normal<R: Rng>(rng: &mut R) -> f64 {
+    let u1: f64 = 1.0 - rng.random::<f64>();
+    let u2: f64 = rng.random::<f64>();
+
+    (-2.0 * u1.ln()).sqrt() * (2.0 * std::f64::consts::PI * u2).cos()
+}
+
+#[rstest]
+#[case(b"ACGT", vec![1, 1, 1, 1])]
+#[case(b"AACCCT", vec![2, 2, 3, 3, 3, 1])]
+#[case(b"", vec![])]
+fn test_homopolymer_run_lengths(#[case] seq: &[u8], #[case] expected: Vec<usize>) {
+    assert_eq!(homopolymer_run_lengths(seq), expected);
+}
+
+#[rstest]
+#[case(ErrorModel::Illumina)]
+#[case(ErrorModel::Nanopore)]
+#[case(ErrorModel::Pacbio)]
+fn test_injected_errors(#[case] model: ErrorModel) {
+    let mut rng = StdRng::seed_from_u64(42);
+    let profile = ErrorProfile::from_model(&model).unwrap();
+
+    let true_seq: Vec<u8> = (0..5000)
+        .map(|_| NTS[rng.random_range(0..NTS.len())])
+        .collect();
+
+    let (seq, qual, errors) = profile.sequence(&true_seq, &mut rng);
+    assert_eq!(seq.len(), qual.len());
+
+    // Undoing the errors gives back the true sequence.
+    let mut restored: Vec<u8> = Vec::with_capacity(true_seq.len());
+    let mut read_pos = 0;
+
+    for error in &errors {
+        restored.extend_from_slice(&seq[read_pos..error.read_pos]);
+        read_pos = error.read_pos;
+
+        assert_eq!(
+            error.true_base,
+            match error.error_type {
+                ErrorType::Insertion => None,
+                _ => Some(true_seq[error.true_pos]),
+            }
+        );
+
+        match error.error_type {
+            ErrorType::Substitution => {
+                restored.push(error.true_base.unwrap());
+                read_pos += 1;
+            }
+            ErrorType::Insertion => read_pos += 1,
+            ErrorType::Deletion => restored.push(error.true_base.unwrap()),
+        }
+    }
+    restored.extend_from_slice(&seq[read_pos..]);
+
+    assert_eq!(restored, true_seq);
+
+    // Observed error rate is in line with the emitted qualities.
+    let expected_errors: f64 = qual.iter().map(|&q| 10_f64.powf(-(q as f64) / 10.0)).sum();
+    let observed_errors = errors.len() as f64;
+    assert!((observed_errors - expected_errors).abs() < 5.0 * expected_errors.sqrt() + 5.0);
+}
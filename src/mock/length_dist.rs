@@ -0,0 +1,154 @@
+use crate::errors::AppError;
+use crate::fastx::text_reader;
+use crate::mock::error_model::standard_normal;
+use rand::prelude::*;
+use rstest::rstest;
+use std::io::BufRead;
+use std::path::PathBuf;
+
+/// Distribution that read (or insert) lengths are drawn from. Drawn lengths are at least 1.
+#[derive(Debug, Clone, PartialEq)]
+pub enum LengthDist {
+    /// Uniform in `min..max` (max exclusive).
+    Uniform(usize, usize),
+    /// Mean and standard deviation.
+    Normal(f64, f64),
+    /// Mean and standard deviation of the (not log-transformed) lengths.
+    LogNormal(f64, f64),
+    /// Lengths to sample from.
+    Empirical(Vec<usize>),
+    /// Lengths with their cumulative counts, e.g. from a read profile.
+    Histogram(Vec<(usize, usize)>),
+}
+
+impl LengthDist {
+    /// Parses `uniform`, `normal:MEAN,SD`, `lognormal:MEAN,SD` or `empirical:FILE`,
+    /// where FILE has one length per line. `uniform` uses `min_len..max_len`.
+    pub fn from_spec(spec: &str, min_len: usize, max_len: usize) -> Result<Self, AppError> {
+        let invalid =
+            || AppError::InvalidArgumentError(format!("invalid length distribution `{spec}`"));
+
+        let (name, params) = match spec.split_once(':') {
+            Some((name, params)) => (name, Some(params)),
+            None => (spec, None),
+        };
+
+        let mean_sd = || -> Result<(f64, f64), AppError> {
+            let (mean, sd) = params.and_then(|p| p.split_once(',')).ok_or_else(invalid)?;
+            let mean: f64 = mean.trim().parse().map_err(|_| invalid())?;
+            let sd: f64 = sd.trim().parse().map_err(|_| invalid())?;
+
+            match mean > 0.0 && sd >= 0.0 {
+                true => Ok((mean, sd)),
+                false => Err(invalid()),
+            }
+        };
+
+        match name {
+            "uniform" if params.is_none() => Ok(LengthDist::Uniform(min_len, max_len)),
+            "normal" => mean_sd().map(|(mean, sd)| LengthDist::Normal(mean, sd)),
+            "lognormal" => mean_sd().map(|(mean, sd)| LengthDist::LogNormal(mean, sd)),
+            "empirical" => {
+                let path = params.ok_or_else(invalid)?;
+                let lengths = read_lengths(PathBuf::from(path))?;
+
+                match lengths.is_empty() {
+                    true => Err(AppError::InvalidArgumentError(format!(
+                        "no lengths found in {path}"
+                    ))),
+                    false => Ok(LengthDist::Empirical(lengths)),
+                }
+            }
+            _ => Err(invalid()),
+        }
+    }
+
+    pub fn mean(&self) -> f64 {
+        match self {
+            LengthDist::Uniform(min, max) => (*min + *max - 1) as f64 / 2.0,
+            LengthDist::Normal(mean, _) | LengthDist::LogNormal(mean, _) => *mean,
+            LengthDist::Empirical(lengths) => {
+                lengths.iter().sum::<usize>() as f64 / lengths.len() as f64
+            }
+            LengthDist::Histogram(histogram) => {
+                let (bases, _) =
+                    histogram
+                        .iter()
+                        .fold((0, 0), |(bases, previous), &(len, cumulative)| {
+                            (bases + len * (cumulative - previous), cumulative)
+                        });
+
+                bases as f64 / histogram.last().map_or(1, |&(_, total)| total) as f64
+            }
+        }
+    }
+
+    pub fn sample<R: Rng>(&self, rng: &mut R) -> usize {
+        let length = match self {
+            LengthDist::Uniform(min, max) => rng.random_range(*min..(*max).max(*min + 1)) as f64,
+            LengthDist::Normal(mean, sd) => mean + sd * standard_normal(rng),
+            LengthDist::LogNormal(mean, sd) => {
+                let sigma2 = (1.0 + (sd / mean).powi(2)).ln();
+                let mu = mean.ln() - sigma2 / 2.0;
+
+                (mu + sigma2.sqrt() * standard_normal(rng)).exp()
+            }
+            LengthDist::Empirical(lengths) => *lengths.choose(rng).expect("No lengths") as f64,
+            LengthDist::Histogram(histogram) => {
+                let total = histogram.last().expect("No lengths").1;
+                let r = rng.random_range(0..total);
+
+                histogram[histogram.partition_point(|&(_, c)| c <= r)].0 as f64
+            }
+        };
+
+        (length.round() as usize).max(1)
+    }
+}
+
+fn read_lengths(path: PathBuf) -> Result<Vec<usize>, AppError> {
+    let reader = text_reader(Some(path))?;
+    let mut lengths: Vec<usize> = Vec::new();
+
+    for line in reader.lines() {
+        let line = line?;
+        let line = line.trim();
+
+        if line.is_empty() {
+            continue;
+        }
+
+        let length: usize = line
+            .parse()
+            .map_err(|_| AppError::InvalidArgumentError(format!("invalid length `{line}`")))?;
+        lengths.push(length);
+    }
+
+    Ok(lengths)
+}
+
+#[rstest]
+#[case("uniform", Some(LengthDist::Uniform(1, 10)))]
+#[case("normal:150,20", Some(LengthDist::Normal(150.0, 20.0)))]
+#[case("lognormal:5000, 2000", Some(LengthDist::LogNormal(5000.0, 2000.0)))]
+#[case("normal:150", None)]
+#[case("normal:-1,2", None)]
+#[case("uniform:1,2", None)]
+#[case("gamma:1,2", None)]
+fn test_length_dist_from_spec(#[case] spec: &str, #[case] expected: Option<LengthDist>) {
+    assert_eq!(LengthDist::from_spec(spec, 1, 10).ok(), expected);
+}
+
+#[rstest]
+#[case(LengthDist::Normal(150.0, 20.0))]
+#[case(LengthDist::LogNormal(5000.0, 2000.0))]
+#[case(LengthDist::Uniform(10, 20))]
+#[case(LengthDist::Histogram(vec![(100, 10), (150, 40), (1000, 41)]))]
+fn test_length_dist_mean(#[case] dist: LengthDist) {
+    let mut rng = StdRng::seed_from_u64(42);
+
+    let n = 20000;
+    let mean = (0..n).map(|_| dist.sample(&mut rng)).sum::<usize>() as f64 / n as f64;
+
+    assert!((mean - dist.mean()).abs() / dist.mean() < 0.02);
+}
@@ -1,21 +1,19 @@
+use crate::args::ErrorModel;
 use crate::errors::AppError;
-use bio_utils_rs::io::get_bufwriter;
-use bio_utils_rs::nucleotide::PHRED_OFFSET;
-use rand::{prelude::*, random_range, rng};
+use crate::fastx::{FastxRecord, fastx_writer};
+use crate::mock::barcodes::BarcodeSimulator;
+use crate::mock::error_model::{ErrorProfile, read_position};
+use crate::mock::length_dist::LengthDist;
+use crate::mock::reference::Reference;
+use crate::profile::ReadProfile;
+use crate::table::{ColumnType, TableColumn, TableValue, table_writer};
+use crate::trim::Orientation;
+use bio_utils_rs::nucleotide::{PHRED_OFFSET, reverse_complement};
+use rand::{prelude::*, rng};
 use std::path::PathBuf;
 
 const NTS: [u8; 4] = [b'A', b'C', b'G', b'T'];
 
-fn mock_fix_qual(fix: &Option<String>, actual_phred: u8) -> Option<Vec<u8>> {
-    fix.as_ref().map(|s| {
-        let mut v = Vec::with_capacity(s.len());
-        for _ in 0..s.len() {
-            v.push(actual_phred);
-        }
-        v
-    })
-}
-
 fn validate_input_arguments(
     num_reads: usize,
     min_len: usize,
@@ -54,68 +52,386 @@ fn validate_input_arguments(
 
     Ok(())
 }
+
+/// Number of attempts to draw a fragment that fits in the reference.
+const MAX_ATTEMPTS: usize = 100;
+
+/// A read before sequencing: read id, origin in the reference and true sequence.
+struct MockRead {
+    id: String,
+    origin: Option<String>,
+    seq: Vec<u8>,
+}
+
+/// Draws a fragment from the reference and returns it as a read or, with `insert_dist`,
+/// as a read pair sequenced from both ends of the fragment.
+fn reference_reads<R: Rng>(
+    reference: &Reference,
+    name: &str,
+    length_dist: &LengthDist,
+    insert_dist: Option<&LengthDist>,
+    rng: &mut R,
+) -> Result<Vec<MockRead>, AppError> {
+    for _ in 0..MAX_ATTEMPTS {
+        let fragment_len = insert_dist.unwrap_or(length_dist).sample(rng);
+
+        let fragment = match reference.sample(fragment_len, rng) {
+            Some(fragment) => fragment,
+            None => continue,
+        };
+
+        let seq = reference.fragment_seq(&fragment);
+        let origin = Some(reference.origin(&fragment));
+
+        if insert_dist.is_none() {
+            return Ok(vec![MockRead {
+                id: name.to_string(),
+                origin,
+                seq,
+            }]);
+        }
+
+        // Mates are clipped to the fragment for short inserts.
+        let len_1 = length_dist.sample(rng).min(seq.len());
+        let len_2 = length_dist.sample(rng).min(seq.len());
+        let seq_2 = reverse_complement(&seq);
+
+        return Ok(vec![
+            MockRead {
+                id: format!("{name}/1"),
+                origin: origin.clone(),
+                seq: seq[..len_1].to_vec(),
+            },
+            MockRead {
+                id: format!("{name}/2"),
+                origin,
+                seq: seq_2[..len_2].to_vec(),
+            },
+        ]);
+    }
+
+    Err(AppError::InvalidArgumentError(
+        "reference sequences are too short for the requested read/insert lengths".to_string(),
+    ))
+}
+
+/// A random read, or a read (pair) from the reference.
+fn mock_reads<R: Rng>(
+    reference: Option<&Reference>,
+    name: &str,
+    length_dist: &LengthDist,
+    insert_dist: Option<&LengthDist>,
+    rng: &mut R,
+) -> Result<Vec<MockRead>, AppError> {
+    match reference {
+        Some(reference) => reference_reads(reference, name, length_dist, insert_dist, rng),
+        None => {
+            let seq_len = length_dist.sample(rng);
+
+            Ok(vec![MockRead {
+                id: name.to_string(),
+                origin: None,
+                seq: (0..seq_len)
+                    .map(|_| NTS[rng.random_range(0..NTS.len())])
+                    .collect(),
+            }])
+        }
+    }
+}
+
+/// Emits `num_reads` random reads, or reads (pairs) sampled from `reference`. With an
+/// error model, sequencing errors are injected into the whole read (including
+/// prefix/suffix) and optionally written to `truth`. With a read `profile`, read lengths
+/// and qualities are drawn from the profile. With `barcodes`, barcodes and adapters
+/// are attached to each read and their true positions written to `barcodes_truth`.
 pub fn fastq_mock(
     num_reads: usize,
     min_len: usize,
     max_len: usize,
+    length_dist: String,
+    profile: Option<PathBuf>,
     phred: u8,
     prefix_seq: Option<String>,
     suffix_seq: Option<String>,
+    error_model: ErrorModel,
+    seed: Option<u64>,
+    truth: Option<PathBuf>,
+    reference: Option<PathBuf>,
+    coverage: Option<f64>,
+    paired: bool,
+    insert_dist: String,
+    barcodes: Option<BarcodeSimulator>,
+    barcodes_truth: PathBuf,
     outfile: Option<PathBuf>,
+    outfile_2: Option<PathBuf>,
 ) -> Result<(), AppError> {
-    let mut writer = get_bufwriter(outfile)?;
     let actual_phred = phred + PHRED_OFFSET as u8;
 
     validate_input_arguments(num_reads, min_len, max_len, phred)?;
 
-    let prefix_qual = mock_fix_qual(&prefix_seq, actual_phred);
-    let suffix_qual = mock_fix_qual(&suffix_seq, actual_phred);
+    let profile = match profile {
+        Some(path) => Some(ReadProfile::load(path)?),
+        None => None,
+    };
+    let quality_sampler = profile.as_ref().map(|p| p.quality_sampler());
+
+    let length_dist = match profile.as_ref() {
+        Some(profile) => LengthDist::Histogram(profile.length_histogram()),
+        None => LengthDist::from_spec(&length_dist, min_len, max_len)?,
+    };
+    let insert_dist = match paired {
+        true => Some(LengthDist::from_spec(&insert_dist, min_len, max_len)?),
+        false => None,
+    };
+
+    let error_profile = ErrorProfile::from_model(&error_model);
 
-    // Pre-allocate buffers for seq and qual
-    let mut seq: Vec<u8> = Vec::with_capacity(max_len);
-    let mut qual: Vec<u8> = Vec::with_capacity(max_len);
+    if truth.is_some() && error_profile.is_none() {
+        return Err(AppError::InvalidArgumentError(
+            "truth requires an error model".to_string(),
+        ));
+    }
 
-    let mut rng = rng();
+    if barcodes.is_some() && (paired || prefix_seq.is_some() || suffix_seq.is_some()) {
+        return Err(AppError::InvalidArgumentError(
+            "barcodes can not be combined with paired, prefix_seq or suffix_seq".to_string(),
+        ));
+    }
+
+    if (paired || coverage.is_some()) && reference.is_none() {
+        return Err(AppError::InvalidArgumentError(
+            "paired and coverage require a reference".to_string(),
+        ));
+    }
+
+    let reference = match reference {
+        Some(path) => Some(Reference::load(path)?),
+        None => None,
+    };
+
+    // Number of reads (pairs) to reach the coverage.
+    let num_reads = match (coverage, reference.as_ref()) {
+        (Some(coverage), Some(reference)) => {
+            let bases_per_fragment = length_dist.mean() * if paired { 2.0 } else { 1.0 };
+            ((coverage * reference.total_len() as f64 / bases_per_fragment).ceil() as usize).max(1)
+        }
+        _ => num_reads,
+    };
+
+    let mut writer_1 = fastx_writer(outfile)?;
+    let mut writer_2 = match outfile_2 {
+        Some(path) => Some(fastx_writer(Some(path))?),
+        None => None,
+    };
+
+    let mut truth_writer = match truth {
+        Some(path) => Some(table_writer(
+            Some(path),
+            vec![
+                TableColumn::new("read_name", ColumnType::Utf8),
+                TableColumn::new("error_type", ColumnType::DictUtf8),
+                TableColumn::new("true_pos", ColumnType::UInt64),
+                TableColumn::new("read_pos", ColumnType::UInt64),
+                TableColumn::new("true_base", ColumnType::Utf8),
+                TableColumn::new("read_base", ColumnType::Utf8),
+            ],
+        )?),
+        None => None,
+    };
+
+    let mut barcodes_truth_writer = match barcodes {
+        Some(_) => Some(table_writer(
+            Some(barcodes_truth),
+            vec![
+                TableColumn::new("read_name", ColumnType::Utf8),
+                TableColumn::new("sample", ColumnType::DictUtf8),
+                TableColumn::new("chimera_sample", ColumnType::DictUtf8),
+                TableColumn::new("orientation", ColumnType::DictUtf8),
+                TableColumn::new("barcode_forward", ColumnType::DictUtf8),
+                TableColumn::new("barcode_reverse", ColumnType::DictUtf8),
+                TableColumn::new("barcode_forward_start", ColumnType::UInt64),
+                TableColumn::new("barcode_forward_end", ColumnType::UInt64),
+                TableColumn::new("barcode_forward_edits", ColumnType::UInt64),
+                TableColumn::new("barcode_reverse_start", ColumnType::UInt64),
+                TableColumn::new("barcode_reverse_end", ColumnType::UInt64),
+                TableColumn::new("barcode_reverse_edits", ColumnType::UInt64),
+            ],
+        )?),
+        None => None,
+    };
+
+    let prefix_seq = prefix_seq.unwrap_or_default();
+    let suffix_seq = suffix_seq.unwrap_or_default();
+
+    let mut rng = match seed {
+        Some(seed) => StdRng::seed_from_u64(seed),
+        None => StdRng::from_rng(&mut rng()),
+    };
 
     for i in 0..num_reads {
-        seq.clear();
-        qual.clear();
+        let name = format!("read_{}", i + 1);
 
-        let seq_len = random_range(min_len..max_len);
+        let reads = mock_reads(
+            reference.as_ref(),
+            &name,
+            &length_dist,
+            insert_dist.as_ref(),
+            &mut rng,
+        )?;
 
-        seq.extend((0..seq_len).map(|_| NTS[rng.random_range(0..NTS.len())]));
-        qual.resize(seq_len, actual_phred);
+        for (mate, read) in reads.into_iter().enumerate() {
+            let (seq, barcode_truth) = match barcodes.as_ref() {
+                Some(simulator) => {
+                    let chimera_insert = match simulator.is_chimera(&mut rng) {
+                        true => Some(
+                            mock_reads(reference.as_ref(), &name, &length_dist, None, &mut rng)?
+                                .swap_remove(0)
+                                .seq,
+                        ),
+                        false => None,
+                    };
 
-        assert_eq!(seq.len(), qual.len());
+                    let (seq, truth) =
+                        simulator.attach(&read.seq, chimera_insert.as_deref(), &mut rng);
+                    (seq, Some(truth))
+                }
+                None => (
+                    [prefix_seq.as_bytes(), &read.seq, suffix_seq.as_bytes()].concat(),
+                    None,
+                ),
+            };
+            let true_len = seq.len();
 
-        // Read name.
-        writer.write_all(b"@read_")?;
-        writer.write_all((i + 1).to_string().as_bytes())?;
-        writer.write_all(b"\n")?;
+            let phreds = quality_sampler
+                .as_ref()
+                .map(|sampler| sampler.sample(seq.len(), &mut rng));
 
-        // Sequence
-        if let Some(s) = prefix_seq.as_ref() {
-            writer.write_all(s.as_bytes())?
-        }
-        writer.write_all(&seq)?;
-        if let Some(s) = suffix_seq.as_ref() {
-            writer.write_all(s.as_bytes())?
-        }
-        writer.write_all(b"\n")?;
-        writer.write_all(b"+\n")?;
+            let (seq, qual, errors) = match (error_profile.as_ref(), phreds) {
+                (None, None) => {
+                    let qual = vec![actual_phred; seq.len()];
+                    (seq, qual, Vec::new())
+                }
+                (None, Some(mut qual)) => {
+                    qual.iter_mut().for_each(|q| *q += PHRED_OFFSET as u8);
+                    (seq, qual, Vec::new())
+                }
+                (Some(model), phreds) => {
+                    let (seq, mut qual, errors) = match phreds {
+                        Some(phreds) => model.sequence_with_phreds(&seq, &phreds, &mut rng),
+                        None => model.sequence(&seq, &mut rng),
+                    };
 
-        // Qual
-        if let Some(q) = prefix_qual.as_ref() {
-            writer.write_all(q)?
-        }
-        writer.write_all(&qual)?;
-        if let Some(q) = suffix_qual.as_ref() {
-            writer.write_all(q)?
+                    qual.iter_mut().for_each(|q| *q += PHRED_OFFSET as u8);
+                    (seq, qual, errors)
+                }
+            };
+
+            assert_eq!(seq.len(), qual.len());
+
+            if let Some(w) = truth_writer.as_mut() {
+                for error in errors.iter() {
+                    let true_base = error.true_base.map(|b| [b]);
+                    let read_base = error.read_base.map(|b| [b]);
+
+                    w.write_row(&[
+                        TableValue::Str(read.id.as_bytes()),
+                        TableValue::Str(error.error_type.as_str().as_bytes()),
+                        TableValue::UInt(error.true_pos as u64),
+                        TableValue::UInt(error.read_pos as u64),
+                        true_base
+                            .as_ref()
+                            .map(|b| TableValue::Str(b))
+                            .unwrap_or(TableValue::Null),
+                        read_base
+                            .as_ref()
+                            .map(|b| TableValue::Str(b))
+                            .unwrap_or(TableValue::Null),
+                    ])?;
+                }
+            }
+
+            if let (Some(truth), Some(simulator), Some(w)) = (
+                barcode_truth,
+                barcodes.as_ref(),
+                barcodes_truth_writer.as_mut(),
+            ) {
+                let reverse = truth.orientation == Orientation::Reverse;
+
+                // Barcode span in the forward orientation of the emitted read.
+                let span = |(start, end): (usize, usize)| {
+                    let (start, end) = match reverse {
+                        true => (true_len - end, true_len - start),
+                        false => (start, end),
+                    };
+                    let (start, end) = (read_position(&errors, start), read_position(&errors, end));
+
+                    match reverse {
+                        true => (seq.len() - end, seq.len() - start),
+                        false => (start, end),
+                    }
+                };
+                let forward = truth.forward.map(span);
+                let reverse = truth.reverse.map(span);
+
+                let sample = &simulator.samples[truth.sample];
+                let reverse_sample =
+                    &simulator.samples[truth.chimera_sample.unwrap_or(truth.sample)];
+
+                let coordinate = |c: Option<usize>| {
+                    c.map(|c| TableValue::UInt(c as u64))
+                        .unwrap_or(TableValue::Null)
+                };
+
+                w.write_row(&[
+                    TableValue::Str(read.id.as_bytes()),
+                    TableValue::Str(sample.sample.as_bytes()),
+                    truth
+                        .chimera_sample
+                        .map(|s| TableValue::Str(simulator.samples[s].sample.as_bytes()))
+                        .unwrap_or(TableValue::Null),
+                    TableValue::Str(truth.orientation.as_str().as_bytes()),
+                    TableValue::Str(&sample.barcode_forward),
+                    TableValue::Str(&reverse_sample.barcode_reverse),
+                    coordinate(forward.map(|(start, _)| start)),
+                    coordinate(forward.map(|(_, end)| end)),
+                    TableValue::UInt(truth.forward_edits as u64),
+                    coordinate(reverse.map(|(start, _)| start)),
+                    coordinate(reverse.map(|(_, end)| end)),
+                    TableValue::UInt(truth.reverse_edits as u64),
+                ])?;
+            }
+
+            let id = match read.origin {
+                Some(origin) => format!("{} {}", read.id, origin),
+                None => read.id,
+            };
+
+            let record = FastxRecord {
+                id: id.into_bytes(),
+                seq,
+                qual: Some(qual),
+            };
+
+            match (mate, writer_2.as_mut()) {
+                (1, Some(w)) => w.write_record(&record)?,
+                _ => writer_1.write_record(&record)?,
+            }
         }
-        writer.write_all(b"\n")?;
     }
 
-    writer.flush()?;
+    writer_1.finish()?;
+
+    if let Some(w) = writer_2 {
+        w.finish()?;
+    }
+
+    if let Some(w) = truth_writer {
+        w.finish()?;
+    }
+
+    if let Some(w) = barcodes_truth_writer {
+        w.finish()?;
+    }
 
     Ok(())
 }
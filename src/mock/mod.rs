@@ -1,2 +1,6 @@
+pub mod barcodes;
+pub mod error_model;
+pub mod length_dist;
 pub mod mock;
+pub mod reference;
 pub use mock::fastq_mock;
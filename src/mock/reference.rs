@@ -0,0 +1,134 @@
+use crate::bam::KeepTags;
+use crate::errors::AppError;
+use crate::fastx::fastx_records;
+use crate::fq2tab::split_header;
+use bio_utils_rs::nucleotide::reverse_complement;
+use rand::prelude::*;
+use rstest::rstest;
+use std::path::PathBuf;
+
+/// A fragment sampled from the reference. `start..end` is 0-based, end exclusive.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Fragment {
+    pub contig: usize,
+    pub start: usize,
+    pub end: usize,
+    pub reverse: bool,
+}
+
+pub struct Reference {
+    contigs: Vec<(String, Vec<u8>)>,
+}
+
+impl Reference {
+    pub fn load(path: PathBuf) -> Result<Self, AppError> {
+        let mut contigs: Vec<(String, Vec<u8>)> = Vec::new();
+
+        for record in fastx_records(Some(path.clone()), &KeepTags::default())? {
+            let record = record?;
+            let name = String::from_utf8_lossy(split_header(&record.id).0).to_string();
+            contigs.push((name, record.seq.to_ascii_uppercase()));
+        }
+
+        if contigs.is_empty() {
+            return Err(AppError::InvalidArgumentError(format!(
+                "no sequences found in {}",
+                path.display()
+            )));
+        }
+
+        Ok(Self { contigs })
+    }
+
+    pub fn total_len(&self) -> usize {
+        self.contigs.iter().map(|(_, seq)| seq.len()).sum()
+    }
+
+    /// Draws a fragment of `len` bases from a uniformly random position and strand.
+    /// Returns None if no contig is long enough.
+    pub fn sample<R: Rng>(&self, len: usize, rng: &mut R) -> Option<Fragment> {
+        // Number of possible start positions per contig.
+        let starts = |seq: &[u8]| (seq.len() + 1).saturating_sub(len);
+
+        let total: usize = self.contigs.iter().map(|(_, seq)| starts(seq)).sum();
+
+        if len == 0 || total == 0 {
+            return None;
+        }
+
+        let mut r = rng.random_range(0..total);
+
+        for (contig, (_, seq)) in self.contigs.iter().enumerate() {
+            let n = starts(seq);
+
+            if r < n {
+                return Some(Fragment {
+                    contig,
+                    start: r,
+                    end: r + len,
+                    reverse: rng.random_bool(0.5),
+                });
+            }
+
+            r -= n;
+        }
+
+        None
+    }
+
+    /// Sequence of the fragment, reverse complemented for the reverse strand.
+    pub fn fragment_seq(&self, fragment: &Fragment) -> Vec<u8> {
+        let seq = &self.contigs[fragment.contig].1[fragment.start..fragment.end];
+
+        match fragment.reverse {
+            true => reverse_complement(seq),
+            false => seq.to_vec(),
+        }
+    }
+
+    /// Origin of the fragment as `contig:start-end:strand` (1-based, inclusive).
+    pub fn origin(&self, fragment: &Fragment) -> String {
+        format!(
+            "{}:{}-{}:{}",
+            self.contigs[fragment.contig].0,
+            fragment.start + 1,
+            fragment.end,
+            match fragment.reverse {
+                true => '-',
+                false => '+',
+            }
+        )
+    }
+}
+
+#[rstest]
+#[case(4, true)]
+#[case(8, true)]
+#[case(9, false)]
+fn test_reference_sample(#[case] len: usize, #[case] expected_some: bool) {
+    let reference = Reference {
+        contigs: vec![
+            ("chr1".to_string(), b"ACGTACGT".to_vec()),
+            ("chr2".to_string(), b"TTTT".to_vec()),
+        ],
+    };
+
+    let mut rng = StdRng::seed_from_u64(42);
+
+    for _ in 0..100 {
+        let fragment = reference.sample(len, &mut rng);
+        assert_eq!(fragment.is_some(), expected_some);
+
+        if let Some(fragment) = fragment {
+            let contig = &reference.contigs[fragment.contig].1;
+            assert!(fragment.end <= contig.len());
+            assert_eq!(fragment.end - fragment.start, len);
+
+            let mut seq = reference.fragment_seq(&fragment);
+            if fragment.reverse {
+                seq = reverse_complement(&seq);
+            }
+            assert_eq!(seq, contig[fragment.start..fragment.end]);
+        }
+    }
+}
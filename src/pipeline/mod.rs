@@ -0,0 +1,5 @@
+pub mod pipeline;
+pub use pipeline::fastq_pipeline;
+
+pub mod pipeline_steps;
+pub use pipeline_steps::{Step, StepConfig, StepReport};
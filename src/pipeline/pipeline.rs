@@ -0,0 +1,123 @@
+use crate::bam::KeepTags;
+use crate::errors::AppError;
+use crate::fastx::{fastx_records, fastx_writer};
+use crate::pipeline::{Step, StepConfig, StepReport};
+#[cfg(test)]
+use crate::test_utils::TempFile;
+use bio_utils_rs::io::write_json;
+use rstest::rstest;
+use serde::Deserialize;
+use std::path::PathBuf;
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Recipe {
+    pub steps: Vec<StepConfig>,
+}
+
+impl Recipe {
+    pub fn from_file(recipe: &PathBuf) -> Result<Self, AppError> {
+        let contents = std::fs::read_to_string(recipe)?;
+        toml::from_str(&contents).map_err(|err| AppError::RecipeParsingError(err.to_string()))
+    }
+}
+
+/// Runs every step of the recipe, in order, over a single parsed
+/// record stream. A record that is discarded by one step is not
+/// passed on to the following steps.
+pub fn fastq_pipeline(
+    fastq: Option<PathBuf>,
+    keep_tags: &KeepTags,
+    recipe: PathBuf,
+    outfile: Option<PathBuf>,
+    report: PathBuf,
+) -> Result<(), AppError> {
+    let recipe = Recipe::from_file(&recipe)?;
+
+    if recipe.steps.is_empty() {
+        return Err(AppError::InvalidArgumentError(
+            "pipeline recipe must contain at least one step".to_string(),
+        ));
+    }
+
+    let mut steps: Vec<Box<dyn Step>> = recipe
+        .steps
+        .into_iter()
+        .map(|step| step.build(fastq.as_deref()))
+        .collect::<Result<_, _>>()?;
+
+    let mut reports: Vec<StepReport> = steps
+        .iter()
+        .map(|step| StepReport {
+            step: step.name().to_string(),
+            ..Default::default()
+        })
+        .collect();
+
+    let records = fastx_records(fastq, keep_tags)?;
+    let mut writer = fastx_writer(outfile)?;
+
+    'records: for record in records {
+        let mut record = record?;
+
+        for (step, report) in steps.iter_mut().zip(reports.iter_mut()) {
+            report.reads_in += 1;
+            report.bases_in += record.seq.len();
+
+            if !step.apply(&mut record)? {
+                continue 'records;
+            }
+
+            report.reads_out += 1;
+            report.bases_out += record.seq.len();
+        }
+
+        writer.write_record(&record)?;
+    }
+
+    writer.finish()?;
+
+    write_json(Some(report), &reports)?;
+
+    Ok(())
+}
+
+#[rstest]
+#[case(
+    "[[steps]]\ntype = \"quality_trim\"\n[[steps]]\ntype = \"filter\"\nmin_len = 4\n[[steps]]\ntype = \"renumber\"\n",
+    Some("@read_1\nGTACG\n+\nIIIII\n@read_2\nAAAAAA\n+\nIIIIII\n"),
+    vec![(3, 3), (3, 2), (2, 2)],
+)]
+#[case("[[steps]]\ntype = \"quality_trim\"\nmin_phred = 250\n", None, vec![])]
+fn test_pipeline(
+    #[case] recipe: &str,
+    #[case] expected: Option<&str>,
+    #[case] expected_reads: Vec<(usize, usize)>,
+) {
+    let fastq = TempFile::with_content(
+        "in.fastq",
+        "@a\nACGTACGT\n+\n##IIIII#\n@b\nACGT\n+\n#II#\n@c\nAAAAAA\n+\nIIIIII\n",
+    );
+    let recipe = TempFile::with_content("recipe.toml", recipe);
+    let (outfile, report) = (TempFile::new("out.fastq"), TempFile::new("report.json"));
+
+    let result = fastq_pipeline(
+        Some(fastq.path()),
+        &KeepTags::default(),
+        recipe.path(),
+        Some(outfile.path()),
+        report.path(),
+    );
+
+    let output = result.is_ok().then(|| outfile.read_to_string());
+    let reads: Vec<(usize, usize)> = match result {
+        Ok(()) => {
+            let reports: Vec<StepReport> = serde_json::from_str(&report.read_to_string()).unwrap();
+            reports.iter().map(|r| (r.reads_in, r.reads_out)).collect()
+        }
+        Err(_) => vec![],
+    };
+
+    assert_eq!(output.as_deref(), expected);
+    assert_eq!(reads, expected_reads);
+}
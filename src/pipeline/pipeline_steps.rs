@@ -0,0 +1,321 @@
+use crate::errors::AppError;
+use crate::fastx::FastxRecord;
+use crate::filter::ReadFilter;
+use crate::renumber::{ReadTemplate, TemplateContext, file_stem};
+use crate::trim::ReadTrimmer;
+use bio_utils_rs::nucleotide::PHRED_OFFSET;
+use rand::{prelude::*, rng};
+use rstest::rstest;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+/// Per step read and base counts, aggregated into the pipeline report.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct StepReport {
+    pub step: String,
+    pub reads_in: usize,
+    pub reads_out: usize,
+    pub bases_in: usize,
+    pub bases_out: usize,
+}
+
+pub trait Step {
+    fn name(&self) -> &'static str;
+
+    /// Modifies the record in place. Returns `false` if the record should be discarded.
+    fn apply(&mut self, record: &mut FastxRecord) -> Result<bool, AppError>;
+}
+
+/// Step definitions, as written in the pipeline recipe.
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum StepConfig {
+    Trim {
+        #[serde(default)]
+        min_len: usize,
+        #[serde(default)]
+        trim_start: usize,
+        #[serde(default)]
+        trim_end: usize,
+        barcode_forward: Option<Vec<String>>,
+        barcode_reverse: Option<Vec<String>>,
+        #[serde(default = "default_max_mismatches")]
+        max_mismatches: u8,
+        #[serde(default = "default_max_indels")]
+        max_indels: u8,
+        #[serde(default = "default_barcode_margin")]
+        barcode_margin: usize,
+        #[serde(default)]
+        amplicon: bool,
+    },
+    QualityTrim {
+        #[serde(default = "default_min_phred")]
+        min_phred: u8,
+        #[serde(default)]
+        min_len: usize,
+    },
+    Filter(ReadFilter),
+    Renumber {
+        #[serde(default = "default_template")]
+        template: String,
+        #[serde(default = "default_prefix")]
+        prefix: String,
+        sample: Option<String>,
+    },
+    Sample {
+        fraction: f64,
+    },
+    #[serde(rename = "fq2fa")]
+    Fq2Fa,
+}
+
+fn default_max_mismatches() -> u8 {
+    2
+}
+
+fn default_max_indels() -> u8 {
+    1
+}
+
+fn default_barcode_margin() -> usize {
+    10
+}
+
+fn default_min_phred() -> u8 {
+    20
+}
+
+fn default_template() -> String {
+    "{prefix}_{n}".to_string()
+}
+
+fn default_prefix() -> String {
+    "read".to_string()
+}
+
+impl StepConfig {
+    /// `fastq` is the pipeline input, used by steps that refer to the file name.
+    pub fn build(self, fastq: Option<&Path>) -> Result<Box<dyn Step>, AppError> {
+        let step: Box<dyn Step> = match self {
+            StepConfig::Trim {
+                min_len,
+                trim_start,
+                trim_end,
+                barcode_forward,
+                barcode_reverse,
+                max_mismatches,
+                max_indels,
+                barcode_margin,
+                amplicon,
+            } => {
+                let trimmer = ReadTrimmer::new(
+                    trim_start,
+                    trim_end,
+                    barcode_forward,
+                    barcode_reverse,
+                    max_mismatches,
+                    max_indels,
+                    barcode_margin,
+                    amplicon,
+                );
+                trimmer.validate()?;
+
+                Box::new(TrimStep { trimmer, min_len })
+            }
+            StepConfig::QualityTrim { min_phred, min_len } => Box::new(QualityTrimStep {
+                min_qual: min_phred.checked_add(PHRED_OFFSET as u8).ok_or_else(|| {
+                    AppError::InvalidArgumentError(format!(
+                        "invalid quality_trim min_phred {min_phred}"
+                    ))
+                })?,
+                min_len,
+            }),
+            StepConfig::Filter(read_filter) => Box::new(FilterStep { read_filter }),
+            StepConfig::Renumber {
+                template,
+                prefix,
+                sample,
+            } => {
+                let file = file_stem(fastq);
+
+                Box::new(RenumberStep {
+                    template: ReadTemplate::parse(&template)?,
+                    prefix,
+                    sample: sample.unwrap_or_else(|| file.clone()),
+                    file,
+                    n: 0,
+                })
+            }
+            StepConfig::Sample { fraction } => {
+                if !(fraction > 0.0 && fraction <= 1.0) {
+                    return Err(AppError::InvalidArgumentError(format!(
+                        "pipeline sample fraction must be in (0.0, 1.0], got {fraction}"
+                    )));
+                }
+
+                Box::new(SampleStep {
+                    fraction,
+                    rng: rng(),
+                })
+            }
+            StepConfig::Fq2Fa => Box::new(Fq2FaStep {}),
+        };
+
+        Ok(step)
+    }
+}
+
+pub struct TrimStep {
+    trimmer: ReadTrimmer,
+    min_len: usize,
+}
+
+pub struct QualityTrimStep {
+    min_qual: u8,
+    min_len: usize,
+}
+
+pub struct FilterStep {
+    read_filter: ReadFilter,
+}
+
+pub struct RenumberStep {
+    template: ReadTemplate,
+    prefix: String,
+    sample: String,
+    file: String,
+    n: usize,
+}
+
+pub struct SampleStep {
+    fraction: f64,
+    rng: ThreadRng,
+}
+
+pub struct Fq2FaStep {}
+
+/// Returns the `[start, end)` range that remains after removing
+/// bases with quality below `min_qual` from both ends of the read.
+#[inline]
+fn quality_trim_range(qual: &[u8], min_qual: u8) -> (usize, usize) {
+    let start = qual
+        .iter()
+        .position(|q| *q >= min_qual)
+        .unwrap_or(qual.len());
+
+    let end = qual
+        .iter()
+        .rposition(|q| *q >= min_qual)
+        .map_or(start, |i| i + 1);
+
+    (start, end)
+}
+
+impl Step for TrimStep {
+    fn name(&self) -> &'static str {
+        "trim"
+    }
+
+    fn apply(&mut self, record: &mut FastxRecord) -> Result<bool, AppError> {
+        let trimmed_read = match self.trimmer.trim(&record.seq) {
+            Some(trimmed_read) => trimmed_read,
+            None => return Ok(false),
+        };
+
+        trimmed_read.apply(record);
+        Ok(record.seq.len() >= self.min_len)
+    }
+}
+
+impl Step for QualityTrimStep {
+    fn name(&self) -> &'static str {
+        "quality_trim"
+    }
+
+    fn apply(&mut self, record: &mut FastxRecord) -> Result<bool, AppError> {
+        let (start, end) = match record.qual.as_ref() {
+            Some(qual) => quality_trim_range(qual, self.min_qual),
+            None => return Err(AppError::MissingQualityError("quality_trim".to_string())),
+        };
+
+        record.keep(start, end);
+        Ok(!record.seq.is_empty() && record.seq.len() >= self.min_len)
+    }
+}
+
+impl Step for FilterStep {
+    fn name(&self) -> &'static str {
+        "filter"
+    }
+
+    fn apply(&mut self, record: &mut FastxRecord) -> Result<bool, AppError> {
+        self.read_filter.passes(&record.seq, record.qual.as_deref())
+    }
+}
+
+impl Step for RenumberStep {
+    fn name(&self) -> &'static str {
+        "renumber"
+    }
+
+    fn apply(&mut self, record: &mut FastxRecord) -> Result<bool, AppError> {
+        self.n += 1;
+
+        let original_id = record
+            .id
+            .split(|c| c.is_ascii_whitespace())
+            .next()
+            .unwrap_or_default();
+
+        let mut read_id: Vec<u8> = Vec::new();
+        self.template.render(
+            &TemplateContext {
+                n: self.n,
+                id: original_id,
+                prefix: &self.prefix,
+                sample: &self.sample,
+                file: &self.file,
+                seq: &record.seq,
+                qual: record.qual.as_deref(),
+            },
+            &mut read_id,
+        );
+
+        record.id = read_id;
+        Ok(true)
+    }
+}
+
+impl Step for SampleStep {
+    fn name(&self) -> &'static str {
+        "sample"
+    }
+
+    fn apply(&mut self, _record: &mut FastxRecord) -> Result<bool, AppError> {
+        Ok(self.rng.random_bool(self.fraction))
+    }
+}
+
+impl Step for Fq2FaStep {
+    fn name(&self) -> &'static str {
+        "fq2fa"
+    }
+
+    fn apply(&mut self, record: &mut FastxRecord) -> Result<bool, AppError> {
+        record.qual = None;
+        Ok(true)
+    }
+}
+
+#[rstest]
+#[case(b"IIII", b'5', (0, 4))]
+#[case(b"##II#", b'5', (2, 4))]
+#[case(b"####", b'5', (4, 4))]
+#[case(b"", b'5', (0, 0))]
+fn test_quality_trim_range(
+    #[case] qual: &[u8],
+    #[case] min_qual: u8,
+    #[case] expected: (usize, usize),
+) {
+    assert_eq!(quality_trim_range(qual, min_qual), expected);
+}
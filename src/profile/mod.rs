@@ -0,0 +1,2 @@
+pub mod profile;
+pub use profile::{QualitySampler, ReadProfile, fastq_profile};
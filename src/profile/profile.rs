@@ -0,0 +1,268 @@
+use crate::bam::KeepTags;
+use crate::errors::AppError;
+use crate::fastx::{fastx_records, text_reader};
+#[cfg(test)]
+use crate::test_utils::TempFile;
+use bio_utils_rs::io::write_json;
+use bio_utils_rs::nucleotide::PHRED_OFFSET;
+use rand::prelude::*;
+use rayon::prelude::*;
+use rstest::rstest;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+/// Phred scores above this are counted as this.
+const MAX_PHRED: usize = 93;
+
+/// Empirical read length and per position quality distributions of a FASTQ file.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ReadProfile {
+    pub num_reads: usize,
+    /// Number of reads per read length.
+    pub lengths: BTreeMap<usize, usize>,
+    /// Per position, the number of bases with each phred score (index = phred). The last
+    /// position also counts all positions after it.
+    pub qualities: Vec<Vec<usize>>,
+}
+
+impl ReadProfile {
+    fn new(max_positions: usize) -> Self {
+        Self {
+            num_reads: 0,
+            lengths: BTreeMap::new(),
+            qualities: vec![vec![0; MAX_PHRED + 1]; max_positions],
+        }
+    }
+
+    fn add(&mut self, qual: &[u8]) {
+        let last = self.qualities.len() - 1;
+
+        self.num_reads += 1;
+        *self.lengths.entry(qual.len()).or_insert(0) += 1;
+
+        for (pos, q) in qual.iter().enumerate() {
+            let phred = (q.saturating_sub(PHRED_OFFSET as u8) as usize).min(MAX_PHRED);
+            self.qualities[pos.min(last)][phred] += 1;
+        }
+    }
+
+    fn merge(mut self, other: Self) -> Self {
+        self.num_reads += other.num_reads;
+
+        for (len, count) in other.lengths {
+            *self.lengths.entry(len).or_insert(0) += count;
+        }
+
+        for (counts, other_counts) in self.qualities.iter_mut().zip(other.qualities) {
+            counts
+                .iter_mut()
+                .zip(other_counts)
+                .for_each(|(c, o)| *c += o);
+        }
+
+        self
+    }
+
+    /// Drops positions that were never observed, e.g. when all reads are shorter
+    /// than `max_positions`.
+    fn trim_positions(&mut self) {
+        while self.qualities.len() > 1
+            && self
+                .qualities
+                .last()
+                .is_some_and(|counts| counts.iter().all(|&c| c == 0))
+        {
+            self.qualities.pop();
+        }
+    }
+
+    pub fn load(path: PathBuf) -> Result<Self, AppError> {
+        let reader = text_reader(Some(path.clone()))?;
+
+        let profile: ReadProfile = serde_json::from_reader(reader).map_err(|e| {
+            AppError::InvalidArgumentError(format!("invalid profile {}: {e}", path.display()))
+        })?;
+
+        // Every position, and the read lengths, must have at least one observation to
+        // sample from.
+        if profile.num_reads == 0
+            || profile.lengths.values().sum::<usize>() == 0
+            || profile.qualities.is_empty()
+            || profile
+                .qualities
+                .iter()
+                .any(|counts| counts.iter().sum::<usize>() == 0)
+        {
+            return Err(AppError::InvalidArgumentError(format!(
+                "empty profile {}",
+                path.display()
+            )));
+        }
+
+        // Sampled qualities are used as phred scores, which must stay printable.
+        if profile
+            .qualities
+            .iter()
+            .any(|counts| counts.len() > MAX_PHRED + 1)
+        {
+            return Err(AppError::InvalidArgumentError(format!(
+                "invalid profile {}: qualities above phred {MAX_PHRED}",
+                path.display()
+            )));
+        }
+
+        Ok(profile)
+    }
+
+    /// Weighted read length distribution, as (length, cumulative count) pairs.
+    pub fn length_histogram(&self) -> Vec<(usize, usize)> {
+        self.lengths
+            .iter()
+            .scan(0, |cumulative, (&len, &count)| {
+                *cumulative += count;
+                Some((len, *cumulative))
+            })
+            .collect()
+    }
+
+    pub fn quality_sampler(&self) -> QualitySampler {
+        let cumulative = self
+            .qualities
+            .iter()
+            .map(|counts| {
+                counts
+                    .iter()
+                    .scan(0, |cumulative, &count| {
+                        *cumulative += count;
+                        Some(*cumulative)
+                    })
+                    .collect()
+            })
+            .collect();
+
+        QualitySampler { cumulative }
+    }
+}
+
+/// Draws per position phred scores (without offset) from a profile.
+pub struct QualitySampler {
+    cumulative: Vec<Vec<usize>>,
+}
+
+impl QualitySampler {
+    pub fn sample<R: Rng>(&self, len: usize, rng: &mut R) -> Vec<u8> {
+        (0..len)
+            .map(|pos| {
+                // Positions after the last one in the profile share its distribution.
+                let cumulative = &self.cumulative[pos.min(self.cumulative.len() - 1)];
+
+                let r = rng.random_range(0..*cumulative.last().expect("Empty profile"));
+                cumulative.partition_point(|&c| c <= r) as u8
+            })
+            .collect()
+    }
+}
+
+/// # Arguments
+/// * `fastq` - Path to FASTQ file (optional, defaults to stdin).
+/// * `max_positions` - Positions from this one on share one quality distribution.
+/// * `outfile` - Where to write the json profile (optional, defaults to stdout).
+pub fn fastq_profile(
+    fastq: Option<PathBuf>,
+    max_positions: usize,
+    outfile: Option<PathBuf>,
+) -> Result<ReadProfile, AppError> {
+    if max_positions == 0 {
+        return Err(AppError::InvalidArgumentError(
+            "max_positions must be greater than 0".to_string(),
+        ));
+    }
+
+    let records = fastx_records(fastq, &KeepTags::default())?;
+
+    let mut profile = records
+        .par_bridge()
+        .try_fold(
+            || ReadProfile::new(max_positions),
+            |mut profile, record| match record?.qual.as_ref() {
+                Some(qual) => {
+                    profile.add(qual);
+                    Ok(profile)
+                }
+                None => Err(AppError::MissingQualityError("profile".to_string())),
+            },
+        )
+        .try_reduce(|| ReadProfile::new(max_positions), |a, b| Ok(a.merge(b)))?;
+
+    profile.trim_positions();
+
+    write_json(outfile, &profile)?;
+
+    Ok(profile)
+}
+
+#[rstest]
+#[case(vec![b"IIII".as_slice(), b"5".as_slice()], 10, vec![(0, 2), (1, 1), (2, 1), (3, 1)])]
+#[case(vec![b"IIII".as_slice(), b"5".as_slice()], 2, vec![(0, 2), (1, 3)])]
+fn test_read_profile(
+    #[case] quals: Vec<&[u8]>,
+    #[case] max_positions: usize,
+    #[case] expected_bases_per_position: Vec<(usize, usize)>,
+) {
+    let mut profile = ReadProfile::new(max_positions);
+    for qual in &quals {
+        profile.add(qual);
+    }
+    profile.trim_positions();
+
+    assert_eq!(profile.num_reads, quals.len());
+    assert_eq!(profile.qualities.len(), expected_bases_per_position.len());
+
+    for (pos, num_bases) in expected_bases_per_position {
+        assert_eq!(profile.qualities[pos].iter().sum::<usize>(), num_bases);
+    }
+
+    // First position has one base with phred 40 and one with phred 20.
+    assert_eq!(profile.qualities[0][40], 1);
+    assert_eq!(profile.qualities[0][20], 1);
+
+    let sampler = profile.quality_sampler();
+    let mut rng = StdRng::seed_from_u64(42);
+
+    // Only phred 40 was observed after the first position.
+    let sampled = sampler.sample(20, &mut rng);
+    assert!(sampled[1..].iter().all(|&q| q == 40));
+    assert!([20, 40].contains(&sampled[0]));
+}
+
+#[rstest]
+#[case(
+    r#"{"num_reads": 1, "lengths": {"2": 1}, "qualities": [[0, 1], [1, 0]]}"#,
+    true
+)]
+#[case(
+    r#"{"num_reads": 1, "lengths": {}, "qualities": [[0, 1], [1, 0]]}"#,
+    false
+)]
+#[case(
+    r#"{"num_reads": 1, "lengths": {"2": 0}, "qualities": [[0, 1], [1, 0]]}"#,
+    false
+)]
+#[case(
+    r#"{"num_reads": 1, "lengths": {"2": 1}, "qualities": [[0, 1], [0, 0]]}"#,
+    false
+)]
+#[case(
+    &format!(r#"{{"num_reads": 1, "lengths": {{"2": 1}}, "qualities": [{:?}]}}"#, [1; MAX_PHRED + 1]),
+    true
+)]
+#[case(
+    &format!(r#"{{"num_reads": 1, "lengths": {{"2": 1}}, "qualities": [{:?}]}}"#, [1; MAX_PHRED + 2]),
+    false
+)]
+fn test_load_read_profile(#[case] json: &str, #[case] expected_ok: bool) {
+    let path = TempFile::with_content("profile.json", json);
+
+    assert_eq!(ReadProfile::load(path.path()).is_ok(), expected_ok);
+}
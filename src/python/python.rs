@@ -0,0 +1,247 @@
+use crate::bam::KeepTags;
+use crate::errors::AppError;
+use crate::filter::fastq_filter as filter;
+use crate::sample::fastq_sample as sample;
+use crate::stats::{FastqStats, compute_fastq_stats};
+#[cfg(test)]
+use crate::test_utils::TempFile;
+use crate::trim::fastq_trim as trim;
+use bio_utils_rs::io::needletail_reader;
+use needletail::FastxReader;
+use pyo3::exceptions::{PyIOError, PyValueError};
+use pyo3::prelude::*;
+use rstest::rstest;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+impl From<AppError> for PyErr {
+    fn from(err: AppError) -> Self {
+        match err {
+            AppError::BioError(e) => PyIOError::new_err(e.to_string()),
+            e => PyValueError::new_err(format!("{e:?}")),
+        }
+    }
+}
+
+/// Iterates over a FASTQ/FASTA file, yielding `(id, seq, qual)` tuples.
+/// `qual` is `None` for FASTA records. Malformatted records are skipped.
+#[pyclass(module = "fastq_rs")]
+pub struct FastqReader {
+    reader: Mutex<Box<dyn FastxReader>>,
+}
+
+#[pymethods]
+impl FastqReader {
+    #[new]
+    fn new(fastq: PathBuf) -> PyResult<Self> {
+        let reader = needletail_reader(Some(fastq)).map_err(AppError::from)?;
+
+        Ok(Self {
+            reader: Mutex::new(reader),
+        })
+    }
+
+    fn __iter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+
+    fn __next__(slf: PyRef<'_, Self>) -> Option<(String, String, Option<String>)> {
+        let mut reader = slf.reader.lock().expect("Failed to lock mutex");
+
+        while let Some(record) = reader.next() {
+            let record = match record {
+                Ok(record) => record,
+                Err(_) => continue,
+            };
+
+            let id = String::from_utf8_lossy(record.id()).into_owned();
+            let seq = String::from_utf8_lossy(&record.seq()).into_owned();
+            let qual = record
+                .qual()
+                .map(|q| String::from_utf8_lossy(q).into_owned());
+
+            return Some((id, seq, qual));
+        }
+
+        None
+    }
+}
+
+#[pymethods]
+impl FastqStats {
+    fn __repr__(&self) -> String {
+        format!("{self:?}")
+    }
+}
+
+/// Calculate basic stats for a FASTQ file.
+#[pyfunction]
+fn fastq_stats(py: Python<'_>, fastq: PathBuf) -> PyResult<FastqStats> {
+    let stats = py.detach(|| compute_fastq_stats(Some(fastq)))?;
+    Ok(stats)
+}
+
+/// Filter reads and write the passing ones to `outfile`.
+#[pyfunction]
+#[pyo3(signature = (
+    fastq,
+    outfile,
+    min_len = 0,
+    max_len = usize::MAX,
+    min_error = 0.0,
+    max_error = 1.0,
+    min_gc = 0.0,
+    max_gc = 1.0,
+    min_softmasked = 0,
+    max_softmasked = usize::MAX,
+    min_ambiguous = 0,
+    max_ambiguous = usize::MAX,
+    keep_tags = Vec::new(),
+))]
+fn fastq_filter(
+    py: Python<'_>,
+    fastq: PathBuf,
+    outfile: PathBuf,
+    min_len: usize,
+    max_len: usize,
+    min_error: f64,
+    max_error: f64,
+    min_gc: f64,
+    max_gc: f64,
+    min_softmasked: usize,
+    max_softmasked: usize,
+    min_ambiguous: usize,
+    max_ambiguous: usize,
+    keep_tags: Vec<String>,
+) -> PyResult<()> {
+    py.detach(|| {
+        filter(
+            Some(fastq),
+            &KeepTags::new(&keep_tags)?,
+            min_len,
+            max_len,
+            min_error,
+            max_error,
+            min_gc,
+            max_gc,
+            min_softmasked,
+            max_softmasked,
+            min_ambiguous,
+            max_ambiguous,
+            Some(outfile),
+        )
+    })?;
+
+    Ok(())
+}
+
+/// Trim reads and write them to `outfile`, with per read info in `barcodes_tsv` (if set).
+#[pyfunction]
+#[pyo3(signature = (
+    fastq,
+    outfile,
+    barcodes_tsv = None,
+    min_len = 0,
+    trim_start = 0,
+    trim_end = 0,
+    barcode_forward = None,
+    barcode_reverse = None,
+    max_mismatches = 2,
+    max_indels = 1,
+    barcode_margin = 10,
+    amplicon = false,
+    keep_tags = Vec::new(),
+))]
+fn fastq_trim(
+    py: Python<'_>,
+    fastq: PathBuf,
+    outfile: PathBuf,
+    barcodes_tsv: Option<PathBuf>,
+    min_len: usize,
+    trim_start: usize,
+    trim_end: usize,
+    barcode_forward: Option<Vec<String>>,
+    barcode_reverse: Option<Vec<String>>,
+    max_mismatches: u8,
+    max_indels: u8,
+    barcode_margin: usize,
+    amplicon: bool,
+    keep_tags: Vec<String>,
+) -> PyResult<()> {
+    py.detach(|| {
+        trim(
+            Some(fastq),
+            &KeepTags::new(&keep_tags)?,
+            min_len,
+            trim_start,
+            trim_end,
+            barcode_forward,
+            barcode_reverse,
+            max_mismatches,
+            max_indels,
+            barcode_margin,
+            amplicon,
+            Some(outfile),
+            barcodes_tsv,
+        )
+    })?;
+
+    Ok(())
+}
+
+/// Sample reads by fraction (`by <= 1.0`) or by number of reads.
+#[pyfunction]
+#[pyo3(signature = (fastq, outfile, by = 1.0, keep_tags = Vec::new()))]
+fn fastq_sample(
+    py: Python<'_>,
+    fastq: PathBuf,
+    outfile: PathBuf,
+    by: f32,
+    keep_tags: Vec<String>,
+) -> PyResult<()> {
+    py.detach(|| sample(Some(fastq), &KeepTags::new(&keep_tags)?, by, Some(outfile)))?;
+    Ok(())
+}
+
+#[pymodule]
+fn fastq_rs(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_class::<FastqStats>()?;
+    m.add_class::<FastqReader>()?;
+    m.add_function(wrap_pyfunction!(fastq_stats, m)?)?;
+    m.add_function(wrap_pyfunction!(fastq_filter, m)?)?;
+    m.add_function(wrap_pyfunction!(fastq_trim, m)?)?;
+    m.add_function(wrap_pyfunction!(fastq_sample, m)?)?;
+
+    Ok(())
+}
+
+#[rstest]
+#[case(0, 2)]
+#[case(5, 1)]
+fn test_trim_stats_round_trip(#[case] min_len: usize, #[case] expected_reads: usize) {
+    let fastq = TempFile::with_content("in.fastq", "@r1\nACGT\n+\nIIII\n@r2\nACGTAC\n+\nIIIIII\n");
+    let outfile = TempFile::new("out.fastq");
+
+    Python::initialize();
+    let stats = Python::attach(|py| {
+        fastq_trim(
+            py,
+            fastq.path(),
+            outfile.path(),
+            None,
+            min_len,
+            0,
+            0,
+            None,
+            None,
+            2,
+            1,
+            10,
+            false,
+            Vec::new(),
+        )?;
+        fastq_stats(py, outfile.path())
+    });
+
+    assert_eq!(stats.unwrap().num_reads, expected_reads);
+}
@@ -1,2 +1,5 @@
 pub mod renumber;
 pub use renumber::fastq_renumber;
+
+pub mod template;
+pub use template::{ReadTemplate, TemplateContext, file_stem, seq_hash};
@@ -1,32 +1,80 @@
+use crate::bam::KeepTags;
 use crate::errors::AppError;
-use bio_utils_rs::io::{get_bufwriter, needletail_reader};
+use crate::fastx::{fastx_records, fastx_writer};
+use crate::renumber::{ReadTemplate, TemplateContext, file_stem};
+use bio_utils_rs::io::get_bufwriter;
 use std::path::PathBuf;
 
-pub fn fastq_renumber(fastq: Option<PathBuf>, outfile: Option<PathBuf>) -> Result<(), AppError> {
-    let mut reader = needletail_reader(fastq)?;
-    let mut writer = get_bufwriter(outfile)?;
-
-    let mut n: usize = 0;
-
-    while let Some(record) = reader.next() {
-        let record = match record {
-            Ok(record) => {
-                n += 1;
-                record
-            }
-            Err(_) => continue,
-        };
-
-        let read_id = format!("@read_{n}");
-
-        writer.write_all(read_id.as_bytes())?;
-        writer.write_all(b"\n")?;
-        writer.write_all(&record.seq())?;
-        writer.write_all(b"\n")?;
-        writer.write_all(b"+\n")?;
-        writer.write_all(record.qual().unwrap())?;
-        writer.write_all(b"\n")?;
+pub fn fastq_renumber(
+    fastq: Option<PathBuf>,
+    keep_tags: &KeepTags,
+    template: String,
+    prefix: String,
+    sample: Option<String>,
+    keep_header: bool,
+    mapping: Option<PathBuf>,
+    outfile: Option<PathBuf>,
+) -> Result<(), AppError> {
+    let template = ReadTemplate::parse(&template)?;
+
+    let file = file_stem(fastq.as_deref());
+    let sample = sample.unwrap_or_else(|| file.clone());
+
+    let records = fastx_records(fastq, keep_tags)?;
+    let mut writer = fastx_writer(outfile)?;
+
+    let mut mapping_writer = match mapping {
+        Some(mapping) => {
+            let mut w = get_bufwriter(Some(mapping))?;
+            w.write_all(b"old_id\tnew_id\n")?;
+            Some(w)
+        }
+        None => None,
+    };
+
+    for (i, record) in records.enumerate() {
+        let mut record = record?;
+        let original_id = record
+            .id
+            .split(|c| c.is_ascii_whitespace())
+            .next()
+            .unwrap_or_default();
+
+        let mut read_id: Vec<u8> = Vec::new();
+        template.render(
+            &TemplateContext {
+                n: i + 1,
+                id: original_id,
+                prefix: &prefix,
+                sample: &sample,
+                file: &file,
+                seq: &record.seq,
+                qual: record.qual.as_deref(),
+            },
+            &mut read_id,
+        );
+
+        if let Some(w) = mapping_writer.as_mut() {
+            w.write_all(original_id)?;
+            w.write_all(b"\t")?;
+            w.write_all(&read_id)?;
+            w.write_all(b"\n")?;
+        }
+
+        if keep_header {
+            read_id.push(b' ');
+            read_id.extend_from_slice(&record.id);
+        }
+
+        record.id = read_id;
+        writer.write_record(&record)?;
     }
 
+    if let Some(mut w) = mapping_writer {
+        w.flush()?;
+    }
+
+    writer.finish()?;
+
     Ok(())
 }
@@ -0,0 +1,252 @@
+use crate::errors::AppError;
+use bio_utils_rs::nucleotide::mean_error_and_phred;
+use rstest::rstest;
+use std::io::Write;
+use std::path::Path;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Field {
+    Prefix,
+    Number,
+    Id,
+    Sample,
+    File,
+    Length,
+    MeanQual,
+    Hash,
+}
+
+impl TryFrom<&str> for Field {
+    type Error = AppError;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        match value {
+            "prefix" => Ok(Self::Prefix),
+            "n" => Ok(Self::Number),
+            "id" => Ok(Self::Id),
+            "sample" => Ok(Self::Sample),
+            "file" => Ok(Self::File),
+            "len" => Ok(Self::Length),
+            "mean_q" => Ok(Self::MeanQual),
+            "hash" => Ok(Self::Hash),
+            _ => Err(AppError::InvalidArgumentError(format!(
+                "unknown template placeholder: {{{value}}}"
+            ))),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum TemplatePart {
+    Literal(String),
+    Field {
+        field: Field,
+        width: usize,
+        zero_pad: bool,
+    },
+}
+
+/// Information about a single read that placeholders are filled from.
+pub struct TemplateContext<'a> {
+    pub n: usize,
+    pub id: &'a [u8],
+    pub prefix: &'a str,
+    pub sample: &'a str,
+    pub file: &'a str,
+    pub seq: &'a [u8],
+    pub qual: Option<&'a [u8]>,
+}
+
+/// Read name template, e.g. `{prefix}_{n:08}`. Supported placeholders are
+/// `prefix`, `n`, `id`, `sample`, `file`, `len`, `mean_q` and `hash`, with an
+/// optional `:<width>` (space padded) or `:0<width>` (zero padded) format spec.
+/// Literal braces are written as `{{` and `}}`.
+#[derive(Debug, Clone)]
+pub struct ReadTemplate {
+    parts: Vec<TemplatePart>,
+}
+
+impl ReadTemplate {
+    pub fn parse(template: &str) -> Result<Self, AppError> {
+        let mut parts: Vec<TemplatePart> = Vec::new();
+        let mut literal = String::new();
+        let mut chars = template.chars().peekable();
+
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.peek() == Some(&'{') => {
+                    chars.next();
+                    literal.push('{');
+                }
+                '}' if chars.peek() == Some(&'}') => {
+                    chars.next();
+                    literal.push('}');
+                }
+                '{' => {
+                    let mut placeholder = String::new();
+
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some(c) => placeholder.push(c),
+                            None => {
+                                return Err(AppError::InvalidArgumentError(format!(
+                                    "unmatched '{{' in template: {template}"
+                                )));
+                            }
+                        }
+                    }
+
+                    if !literal.is_empty() {
+                        parts.push(TemplatePart::Literal(std::mem::take(&mut literal)));
+                    }
+
+                    parts.push(Self::parse_placeholder(&placeholder)?);
+                }
+                '}' => {
+                    return Err(AppError::InvalidArgumentError(format!(
+                        "unmatched '}}' in template: {template}"
+                    )));
+                }
+                _ => literal.push(c),
+            }
+        }
+
+        if !literal.is_empty() {
+            parts.push(TemplatePart::Literal(literal));
+        }
+
+        Ok(Self { parts })
+    }
+
+    fn parse_placeholder(placeholder: &str) -> Result<TemplatePart, AppError> {
+        let (name, spec) = match placeholder.split_once(':') {
+            Some((name, spec)) => (name, spec),
+            None => (placeholder, ""),
+        };
+
+        let field = Field::try_from(name)?;
+
+        let width = match spec.is_empty() {
+            true => 0,
+            false => spec.parse::<usize>().map_err(|_| {
+                AppError::InvalidArgumentError(format!("invalid format spec: {{{placeholder}}}"))
+            })?,
+        };
+
+        Ok(TemplatePart::Field {
+            field,
+            width,
+            zero_pad: spec.starts_with('0'),
+        })
+    }
+
+    pub fn render(&self, ctx: &TemplateContext, out: &mut Vec<u8>) {
+        for part in &self.parts {
+            match part {
+                TemplatePart::Literal(s) => out.extend_from_slice(s.as_bytes()),
+                TemplatePart::Field {
+                    field,
+                    width,
+                    zero_pad,
+                } => {
+                    let value: String = match field {
+                        Field::Prefix => ctx.prefix.to_string(),
+                        Field::Number => ctx.n.to_string(),
+                        Field::Id => String::from_utf8_lossy(ctx.id).into_owned(),
+                        Field::Sample => ctx.sample.to_string(),
+                        Field::File => ctx.file.to_string(),
+                        Field::Length => ctx.seq.len().to_string(),
+                        Field::MeanQual => match ctx.qual {
+                            Some(qual) => mean_error_and_phred(qual).1.to_string(),
+                            None => "NA".to_string(),
+                        },
+                        Field::Hash => format!("{:016x}", seq_hash(ctx.seq)),
+                    };
+
+                    // Writing to a Vec<u8> cannot fail.
+                    let _ = match zero_pad {
+                        true => write!(out, "{value:0>width$}"),
+                        false => write!(out, "{value:>width$}"),
+                    };
+                }
+            }
+        }
+    }
+}
+
+/// 64-bit FNV-1a hash of the sequence. Unlike the std hasher,
+/// this is stable across platforms and Rust versions.
+#[inline]
+pub fn seq_hash(seq: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+
+    for nt in seq {
+        hash ^= *nt as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+
+    hash
+}
+
+/// Input file name without directory and sequence file extensions.
+pub fn file_stem(fastq: Option<&Path>) -> String {
+    let name = match fastq.and_then(|p| p.file_name()) {
+        Some(name) => name.to_string_lossy().into_owned(),
+        None => return "stdin".to_string(),
+    };
+
+    let name = name.strip_suffix(".gz").unwrap_or(&name);
+
+    for ext in [".fastq", ".fq", ".fasta", ".fa", ".bam", ".sam"] {
+        if let Some(stem) = name.strip_suffix(ext) {
+            return stem.to_string();
+        }
+    }
+
+    name.to_string()
+}
+
+#[rstest]
+#[case("{prefix}_{n}", "read_7")]
+#[case("{prefix}_{n:08}", "read_00000007")]
+#[case("{sample}.{id}.{len}", "s1.orig.4")]
+#[case("{n:3}|{mean_q}", "  7|40")]
+#[case("{{{file}}}", "{reads}")]
+#[case("{hash}", "9a90178ba8feda4e")]
+fn test_render_template(#[case] template: &str, #[case] expected: &str) {
+    let template = ReadTemplate::parse(template).unwrap();
+
+    let ctx = TemplateContext {
+        n: 7,
+        id: b"orig",
+        prefix: "read",
+        sample: "s1",
+        file: "reads",
+        seq: b"ACGT",
+        qual: Some(b"IIII"),
+    };
+
+    let mut out: Vec<u8> = Vec::new();
+    template.render(&ctx, &mut out);
+
+    assert_eq!(String::from_utf8(out).unwrap(), expected);
+}
+
+#[rstest]
+#[case("{unknown}")]
+#[case("{n:x}")]
+#[case("read}")]
+#[case("read_{n")]
+fn test_invalid_template(#[case] template: &str) {
+    assert!(ReadTemplate::parse(template).is_err());
+}
+
+#[rstest]
+#[case(Some("dir/reads.fastq.gz"), "reads")]
+#[case(Some("reads.fa"), "reads")]
+#[case(Some("reads.txt"), "reads.txt")]
+#[case(None, "stdin")]
+fn test_file_stem(#[case] fastq: Option<&str>, #[case] expected: &str) {
+    assert_eq!(file_stem(fastq.map(Path::new)), expected);
+}
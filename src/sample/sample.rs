@@ -1,18 +1,18 @@
+use crate::bam::KeepTags;
 use crate::errors::AppError;
-use bio::io::fastq::Record;
-use bio_utils_rs::io::{bio_fastq_reader, bio_fastq_writer};
+use crate::fastx::{FastxRecord, fastx_records, fastx_writer};
 use rand::{prelude::*, rng};
 use std::path::PathBuf;
 
 pub fn fastq_sample(
     fastq: Option<PathBuf>,
+    keep_tags: &KeepTags,
     by: f32,
     outfile: Option<PathBuf>,
 ) -> Result<(), AppError> {
-    let reader = bio_fastq_reader(fastq)?;
-    let mut writer = bio_fastq_writer(outfile)?;
+    let mut writer = fastx_writer(outfile)?;
 
-    let records: Vec<Record> = reader.records().filter_map(|record| record.ok()).collect();
+    let records: Vec<FastxRecord> = fastx_records(fastq, keep_tags)?.collect::<Result<_, _>>()?;
 
     // Check for valid sampling metric.
     if by <= 0.0 {
@@ -30,13 +30,13 @@ pub fn fastq_sample(
     };
 
     let mut rng = rng();
-    let sample = records.choose_multiple(&mut rng, sample_by);
+    let sample = records.sample(&mut rng, sample_by);
 
     for r in sample {
         writer.write_record(r)?;
     }
 
-    writer.flush()?;
+    writer.finish()?;
 
     Ok(())
 }
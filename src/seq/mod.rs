@@ -0,0 +1,2 @@
+pub mod seq;
+pub use seq::{Alphabet, Case, SeqTransform, fastq_seq};
@@ -0,0 +1,199 @@
+use crate::bam::KeepTags;
+use crate::errors::AppError;
+use crate::fastx::{FastxRecord, fastx_records, fastx_writer};
+use bio_utils_rs::nucleotide::PHRED_OFFSET;
+use rstest::rstest;
+use std::path::PathBuf;
+
+/// Complement of a (IUPAC) nucleotide, keeping the case. `U` is complemented to `A`.
+/// Unknown characters are kept as is.
+#[inline]
+fn complement(nt: u8) -> u8 {
+    let c = match nt.to_ascii_uppercase() {
+        b'A' => b'T',
+        b'C' => b'G',
+        b'G' => b'C',
+        b'T' | b'U' => b'A',
+        b'R' => b'Y',
+        b'Y' => b'R',
+        b'K' => b'M',
+        b'M' => b'K',
+        b'B' => b'V',
+        b'D' => b'H',
+        b'H' => b'D',
+        b'V' => b'B',
+        // S, W, N and unknown characters.
+        other => other,
+    };
+
+    match nt.is_ascii_lowercase() {
+        true => c.to_ascii_lowercase(),
+        false => c,
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Case {
+    Upper,
+    Lower,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Alphabet {
+    Rna,
+    Dna,
+}
+
+/// Per read sequence transformations, applied in the order reverse/complement,
+/// case, alphabet and low quality masking.
+#[derive(Debug, Default)]
+pub struct SeqTransform {
+    pub reverse: bool,
+    pub complement: bool,
+    pub case: Option<Case>,
+    pub alphabet: Option<Alphabet>,
+    /// Bases with a phred quality below this are replaced by N.
+    pub mask_lowqual: Option<u8>,
+}
+
+impl SeqTransform {
+    pub fn apply(&self, record: &mut FastxRecord) -> Result<(), AppError> {
+        if self.reverse {
+            record.seq.reverse();
+
+            if let Some(qual) = record.qual.as_mut() {
+                qual.reverse();
+            }
+        }
+
+        if self.complement {
+            record.seq.iter_mut().for_each(|nt| *nt = complement(*nt));
+        }
+
+        match self.case {
+            Some(Case::Upper) => record.seq.make_ascii_uppercase(),
+            Some(Case::Lower) => record.seq.make_ascii_lowercase(),
+            None => {}
+        }
+
+        if let Some(alphabet) = self.alphabet {
+            let (from, to) = match alphabet {
+                Alphabet::Rna => (b'T', b'U'),
+                Alphabet::Dna => (b'U', b'T'),
+            };
+
+            for nt in record.seq.iter_mut() {
+                if *nt == from {
+                    *nt = to;
+                } else if *nt == from.to_ascii_lowercase() {
+                    *nt = to.to_ascii_lowercase();
+                }
+            }
+        }
+
+        if let Some(min_phred) = self.mask_lowqual {
+            let qual = record.qual.as_ref().ok_or(AppError::MissingQualityError(
+                "seq --mask-lowqual".to_string(),
+            ))?;
+            let min_qual = min_phred.checked_add(PHRED_OFFSET as u8).ok_or_else(|| {
+                AppError::InvalidArgumentError(format!("invalid phred score {min_phred}"))
+            })?;
+
+            for (nt, q) in record.seq.iter_mut().zip(qual.iter()) {
+                if *q < min_qual {
+                    *nt = b'N';
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+pub fn fastq_seq(
+    fastq: Option<PathBuf>,
+    keep_tags: &KeepTags,
+    revcomp: bool,
+    complement: bool,
+    reverse: bool,
+    upper: bool,
+    lower: bool,
+    rna: bool,
+    dna: bool,
+    mask_lowqual: Option<u8>,
+    outfile: Option<PathBuf>,
+) -> Result<(), AppError> {
+    let transform = SeqTransform {
+        reverse: revcomp || reverse,
+        complement: revcomp || complement,
+        case: match (upper, lower) {
+            (true, _) => Some(Case::Upper),
+            (_, true) => Some(Case::Lower),
+            _ => None,
+        },
+        alphabet: match (rna, dna) {
+            (true, _) => Some(Alphabet::Rna),
+            (_, true) => Some(Alphabet::Dna),
+            _ => None,
+        },
+        mask_lowqual,
+    };
+
+    let records = fastx_records(fastq, keep_tags)?;
+    let mut writer = fastx_writer(outfile)?;
+
+    for record in records {
+        let mut record = record?;
+
+        transform.apply(&mut record)?;
+        writer.write_record(&record)?;
+    }
+
+    writer.finish()?;
+
+    Ok(())
+}
+
+#[rstest]
+#[case(SeqTransform { reverse: true, complement: true, ..Default::default() }, b"ACGTN", b"ABCDE", b"NACGT", b"EDCBA")]
+#[case(SeqTransform { complement: true, ..Default::default() }, b"acgTRY", b"ABCDEF", b"tgcAYR", b"ABCDEF")]
+#[case(SeqTransform { reverse: true, ..Default::default() }, b"ACGTT", b"ABCDE", b"TTGCA", b"EDCBA")]
+#[case(SeqTransform { case: Some(Case::Lower), alphabet: Some(Alphabet::Rna), ..Default::default() }, b"ACGTt", b"ABCDE", b"acguu", b"ABCDE")]
+#[case(SeqTransform { alphabet: Some(Alphabet::Dna), ..Default::default() }, b"ACGUu", b"ABCDE", b"ACGTt", b"ABCDE")]
+#[case(SeqTransform { mask_lowqual: Some(34), ..Default::default() }, b"ACGTA", b"ABCDE", b"NNGTA", b"ABCDE")]
+fn test_seq_transform(
+    #[case] transform: SeqTransform,
+    #[case] seq: &[u8],
+    #[case] qual: &[u8],
+    #[case] expected_seq: &[u8],
+    #[case] expected_qual: &[u8],
+) {
+    let mut record = FastxRecord {
+        id: b"read".to_vec(),
+        seq: seq.to_vec(),
+        qual: Some(qual.to_vec()),
+    };
+
+    transform.apply(&mut record).unwrap();
+
+    assert_eq!(record.seq, expected_seq);
+    assert_eq!(record.qual.unwrap(), expected_qual);
+}
+
+#[rstest]
+#[case(222, true)]
+#[case(223, false)]
+fn test_mask_lowqual_range(#[case] min_phred: u8, #[case] expected_ok: bool) {
+    let transform = SeqTransform {
+        mask_lowqual: Some(min_phred),
+        ..Default::default()
+    };
+
+    let mut record = FastxRecord {
+        id: b"read".to_vec(),
+        seq: b"ACGT".to_vec(),
+        qual: Some(b"IIII".to_vec()),
+    };
+
+    assert_eq!(transform.apply(&mut record).is_ok(), expected_ok);
+}
@@ -2,4 +2,4 @@ pub mod sort;
 pub use sort::fastq_sort;
 
 pub mod sort_types;
-pub use sort_types::{GcContent, Minimizer, ReadError, ReadLength, Score};
+pub use sort_types::{GcContent, Minimizer, ReadError, ReadLength, Score, min_minimizer};
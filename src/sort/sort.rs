@@ -1,8 +1,8 @@
 use crate::args::SortType;
+use crate::bam::KeepTags;
 use crate::errors::AppError;
+use crate::fastx::{FastxRecord, fastx_records, fastx_writer};
 use crate::sort::{GcContent, Minimizer, ReadError, ReadLength, Score};
-use bio::io::fastq::Record;
-use bio_utils_rs::io::{bio_fastq_reader, bio_fastq_writer};
 use rayon::prelude::*;
 use std::cmp::Ordering;
 use std::path::PathBuf;
@@ -19,6 +19,7 @@ fn check_reverse(a: f64, b: f64, reverse: bool) -> Ordering {
 
 pub fn fastq_sort(
     fastq: Option<PathBuf>,
+    keep_tags: &KeepTags,
     by: &SortType,
     reverse: bool,
     window_size: usize,
@@ -27,7 +28,7 @@ pub fn fastq_sort(
     max_minimizer_error: f64,
     outfile: Option<PathBuf>,
 ) -> Result<(), AppError> {
-    let reader = bio_fastq_reader(fastq)?;
+    let records = fastx_records(fastq, keep_tags)?;
 
     // Window size cannot be even, because Minimizer builder
     // will complain in this case (due to lexicographic tie breaking).
@@ -48,32 +49,34 @@ pub fn fastq_sort(
         }),
     };
 
-    let mut records_with_metrics: Vec<(f64, Record)> = reader
-        .records()
+    let records_with_metrics: Vec<Result<(f64, FastxRecord), AppError>> = records
         .par_bridge()
-        .filter_map(|record| {
-            let record = match record {
-                Ok(record) => record,
-                Err(_) => {
-                    return None;
+        .map(|record| {
+            let record = record?;
+            let score = match record.qual.as_deref() {
+                Some(qual) => metric.score(&record.seq, qual),
+                None if metric.requires_quality() => {
+                    return Err(AppError::MissingQualityError(format!("sort --by {by:?}")));
                 }
+                None => metric.score(&record.seq, &[]),
             };
 
-            let score = metric.score(record.seq(), record.qual());
-
-            Some((score, record))
+            Ok((score, record))
         })
         .collect();
 
+    let mut records_with_metrics: Vec<(f64, FastxRecord)> =
+        records_with_metrics.into_iter().collect::<Result<_, _>>()?;
+
     records_with_metrics.par_sort_by(|a, b| check_reverse(a.0, b.0, reverse));
 
-    let mut writer = bio_fastq_writer(outfile)?;
+    let mut writer = fastx_writer(outfile)?;
 
     for (_, record) in records_with_metrics {
         writer.write_record(&record)?;
     }
 
-    writer.flush()?;
+    writer.finish()?;
 
     Ok(())
 }
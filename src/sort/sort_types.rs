@@ -18,6 +18,20 @@ fn get_minimizers(seq: &[u8], kmer_size: usize, window_size: usize) -> Vec<(u64,
     m_iter
 }
 
+/// Smallest canonical minimizer of a read, as a strand independent signature of its
+/// sequence. Reads from the same molecule most likely share it, despite sequencing errors.
+/// `None` if the read is shorter than one window. `window_size` must be odd.
+pub fn min_minimizer(seq: &[u8], kmer_size: usize, window_size: usize) -> Option<u64> {
+    if kmer_size == 0 || seq.len() < kmer_size + window_size - 1 {
+        return None;
+    }
+
+    get_minimizers(seq, kmer_size, window_size)
+        .into_iter()
+        .map(|(mm_seq, _)| mm_seq)
+        .min()
+}
+
 #[inline]
 fn is_significant_minimizer(mm_qual: &[u8], max_err: f64) -> bool {
     let mut err = 1.0;
@@ -54,6 +68,11 @@ fn get_num_significant_minimizers(
 
 pub trait Score: Send + Sync {
     fn score(&self, seq: &[u8], qual: &[u8]) -> f64;
+
+    /// Metrics that use `qual` cannot score FASTA records.
+    fn requires_quality(&self) -> bool {
+        false
+    }
 }
 
 pub struct GcContent {}
@@ -90,6 +109,10 @@ impl Score for ReadError {
         let (mean_error, _) = mean_error_and_phred(qual);
         mean_error
     }
+
+    fn requires_quality(&self) -> bool {
+        true
+    }
 }
 
 impl Score for Minimizer {
@@ -112,4 +135,8 @@ impl Score for Minimizer {
             self.max_minimizer_error,
         ) as f64
     }
+
+    fn requires_quality(&self) -> bool {
+        true
+    }
 }
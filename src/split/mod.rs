@@ -0,0 +1,2 @@
+pub mod split;
+pub use split::{SplitBy, fastq_split, header_field};
@@ -0,0 +1,324 @@
+use crate::args::PartsMode;
+use crate::bam::KeepTags;
+use crate::errors::AppError;
+use crate::fastx::{FastxRecord, FastxWriter, fastx_records, fastx_writer};
+use crate::renumber::file_stem;
+#[cfg(test)]
+use crate::test_utils::TempFile;
+use flate2::Compression;
+use flate2::write::GzEncoder;
+use rstest::rstest;
+use std::collections::{HashMap, HashSet};
+use std::fs::{OpenOptions, create_dir_all};
+use std::io::BufWriter;
+use std::path::PathBuf;
+
+/// Maximum number of output files open at the same time, well below the usual limit of
+/// 1024 open files per process. Beyond that, the least recently used file is closed and
+/// later reopened in append mode (gzip members can be concatenated).
+pub const MAX_OPEN_FILES: usize = 512;
+
+/// How reads are assigned to output files.
+#[derive(Debug, Clone)]
+pub enum SplitBy {
+    /// At most this many reads per file.
+    Reads(usize),
+    /// Roughly this many bases per file (a file is closed once adding a read would exceed it).
+    Bases(usize),
+    /// A fixed number of files.
+    Parts(usize, PartsMode),
+    /// One file per value of a `key=value` header field.
+    Field(String),
+}
+
+/// Value of a `key=value` field in the read header description (e.g. `barcode=barcode01`).
+pub fn header_field<'a>(header: &'a [u8], key: &[u8]) -> Option<&'a [u8]> {
+    header
+        .split(|c| c.is_ascii_whitespace())
+        .skip(1)
+        .find_map(|field| match field.strip_prefix(key) {
+            Some([b'=', value @ ..]) => Some(value),
+            _ => None,
+        })
+}
+
+/// Output files are named `<stem>.<part>.fastq.gz` (or `.fasta.gz` for FASTA input).
+struct SplitWriters {
+    outdir: PathBuf,
+    stem: String,
+    /// Open writers, with the time they were last written to.
+    writers: HashMap<String, (FastxWriter, usize)>,
+    /// Parts that were written to before, which are appended to when reopened.
+    created: HashSet<String>,
+    time: usize,
+}
+
+impl SplitWriters {
+    fn new(outdir: PathBuf, stem: String) -> Self {
+        Self {
+            outdir,
+            stem,
+            writers: HashMap::new(),
+            created: HashSet::new(),
+            time: 0,
+        }
+    }
+
+    fn open(&mut self, part: &str, record: &FastxRecord) -> Result<FastxWriter, AppError> {
+        if self.writers.len() >= MAX_OPEN_FILES {
+            let least_recent = self
+                .writers
+                .iter()
+                .min_by_key(|(_, (_, time))| *time)
+                .map(|(part, _)| part.clone())
+                .expect("No open writers");
+
+            self.close(&least_recent)?;
+        }
+
+        let extension = match record.qual {
+            Some(_) => "fastq.gz",
+            None => "fasta.gz",
+        };
+        let outfile = self
+            .outdir
+            .join(format!("{}.{part}.{extension}", self.stem));
+
+        match self.created.insert(part.to_string()) {
+            true => fastx_writer(Some(outfile)),
+            false => {
+                let file = OpenOptions::new().append(true).open(outfile)?;
+                Ok(FastxWriter::Fastx(Box::new(BufWriter::new(
+                    GzEncoder::new(file, Compression::fast()),
+                ))))
+            }
+        }
+    }
+
+    fn write(&mut self, part: &str, record: &FastxRecord) -> Result<(), AppError> {
+        if !self.writers.contains_key(part) {
+            let writer = self.open(part, record)?;
+            self.writers.insert(part.to_string(), (writer, 0));
+        }
+
+        self.time += 1;
+
+        let (writer, time) = self.writers.get_mut(part).unwrap();
+        *time = self.time;
+        writer.write_record(record)
+    }
+
+    /// Finishes (and closes) a single output file.
+    fn close(&mut self, part: &str) -> Result<(), AppError> {
+        match self.writers.remove(part) {
+            Some((writer, _)) => writer.finish(),
+            None => Ok(()),
+        }
+    }
+
+    fn finish(self) -> Result<(), AppError> {
+        for (_, (writer, _)) in self.writers {
+            writer.finish()?;
+        }
+
+        Ok(())
+    }
+}
+
+#[inline]
+fn part_name(i: usize) -> String {
+    format!("part_{:03}", i + 1)
+}
+
+/// Field values are used in file names, so anything but alphanumerics, `-` and `_` is replaced.
+fn sanitize_field(value: &[u8]) -> String {
+    value
+        .iter()
+        .map(
+            |c| match c.is_ascii_alphanumeric() || matches!(c, b'-' | b'_') {
+                true => *c as char,
+                false => '_',
+            },
+        )
+        .collect()
+}
+
+pub fn fastq_split(
+    fastq: Option<PathBuf>,
+    keep_tags: &KeepTags,
+    split_by: SplitBy,
+    outdir: PathBuf,
+) -> Result<(), AppError> {
+    create_dir_all(&outdir)?;
+
+    let stem = file_stem(fastq.as_deref());
+    let mut writers = SplitWriters::new(outdir, stem);
+
+    match split_by {
+        SplitBy::Reads(0) | SplitBy::Bases(0) | SplitBy::Parts(0, _) => {
+            return Err(AppError::InvalidArgumentError(
+                "split size must be larger than 0".to_string(),
+            ));
+        }
+        SplitBy::Reads(num_reads) => {
+            for (i, record) in fastx_records(fastq, keep_tags)?.enumerate() {
+                let record = record?;
+                let part = i / num_reads;
+
+                if part > 0 && i % num_reads == 0 {
+                    writers.close(&part_name(part - 1))?;
+                }
+
+                writers.write(&part_name(part), &record)?;
+            }
+        }
+        SplitBy::Bases(num_bases) => {
+            let mut part = 0;
+            let mut part_bases = 0;
+
+            for record in fastx_records(fastq, keep_tags)? {
+                let record = record?;
+
+                if part_bases > 0 && part_bases + record.seq.len() > num_bases {
+                    writers.close(&part_name(part))?;
+                    part += 1;
+                    part_bases = 0;
+                }
+
+                part_bases += record.seq.len();
+                writers.write(&part_name(part), &record)?;
+            }
+        }
+        SplitBy::Parts(num_parts, PartsMode::RoundRobin) => {
+            for (i, record) in fastx_records(fastq, keep_tags)?.enumerate() {
+                let record = record?;
+
+                writers.write(&part_name(i % num_parts), &record)?;
+            }
+        }
+        SplitBy::Parts(num_parts, PartsMode::Contiguous) => {
+            // We need the total number of reads up front, so we read the file twice.
+            let fastq = match fastq {
+                Some(fastq) => fastq,
+                None => {
+                    return Err(AppError::InvalidArgumentError(
+                        "--parts-mode contiguous requires an input file".to_string(),
+                    ));
+                }
+            };
+
+            let num_reads: usize = fastx_records(Some(fastq.clone()), keep_tags)?
+                .try_fold(0, |n, record| record.map(|_| n + 1))?;
+            let reads_per_part = num_reads.div_ceil(num_parts).max(1);
+
+            for (i, record) in fastx_records(Some(fastq), keep_tags)?.enumerate() {
+                let record = record?;
+
+                writers.write(&part_name(i / reads_per_part), &record)?;
+            }
+        }
+        SplitBy::Field(key) => {
+            // Raw value per part, since different values can sanitize to the same name.
+            let mut values: HashMap<String, Vec<u8>> = HashMap::new();
+
+            for record in fastx_records(fastq, keep_tags)? {
+                let record = record?;
+                let part = match header_field(&record.id, key.as_bytes()) {
+                    Some(value) => {
+                        let part = format!("{key}_{}", sanitize_field(value));
+
+                        let raw = values.entry(part.clone()).or_insert_with(|| value.to_vec());
+                        if raw != value {
+                            return Err(AppError::InvalidArgumentError(format!(
+                                "{key} values `{}` and `{}` both map to file name part `{part}`",
+                                String::from_utf8_lossy(raw),
+                                String::from_utf8_lossy(value)
+                            )));
+                        }
+
+                        part
+                    }
+                    None => "unclassified".to_string(),
+                };
+
+                writers.write(&part, &record)?;
+            }
+        }
+    }
+
+    writers.finish()?;
+
+    Ok(())
+}
+
+#[rstest]
+#[case(b"read_1 barcode=barcode01 ch=12", b"barcode", Some(b"barcode01".as_slice()))]
+#[case(b"read_1 barcode=barcode01 ch=12", b"ch", Some(b"12".as_slice()))]
+#[case(b"read_1 barcodes=barcode01", b"barcode", None)]
+#[case(b"barcode=barcode01", b"barcode", None)]
+#[case(b"read_1", b"barcode", None)]
+fn test_header_field(#[case] header: &[u8], #[case] key: &[u8], #[case] expected: Option<&[u8]>) {
+    assert_eq!(header_field(header, key), expected);
+}
+
+#[rstest]
+#[case(b"barcode01", "barcode01")]
+#[case(b"../x y", "___x_y")]
+fn test_sanitize_field(#[case] value: &[u8], #[case] expected: &str) {
+    assert_eq!(sanitize_field(value), expected);
+}
+
+#[rstest]
+#[case(10, 1)]
+#[case(MAX_OPEN_FILES + 10, 3)]
+fn test_split_by_field(#[case] num_values: usize, #[case] reads_per_value: usize) {
+    // Values are visited round-robin, so with too many values every file is reopened.
+    let reads: String = (0..num_values * reads_per_value)
+        .map(|i| format!("@r{i} ch={}\nACGT\n+\nIIII\n", i % num_values))
+        .collect();
+    let fastq = TempFile::with_content("reads.fastq", reads);
+    let outdir = TempFile::new("split");
+
+    fastq_split(
+        Some(fastq.path()),
+        &KeepTags::default(),
+        SplitBy::Field("ch".to_string()),
+        outdir.path(),
+    )
+    .unwrap();
+
+    for value in [0, num_values - 1] {
+        let part = outdir.join(format!("{}.ch_{value}.fastq.gz", file_stem(Some(&fastq))));
+        let ids: Vec<Vec<u8>> = fastx_records(Some(part), &KeepTags::default())
+            .unwrap()
+            .map(|record| record.unwrap().id)
+            .collect();
+
+        let expected: Vec<Vec<u8>> = (0..reads_per_value)
+            .map(|i| format!("r{} ch={value}", i * num_values + value).into_bytes())
+            .collect();
+        assert_eq!(ids, expected);
+    }
+}
+
+#[rstest]
+#[case(["a/b", "a/b"], true)]
+#[case(["a/b", "a_b"], false)]
+#[case(["a.b", "a_b"], false)]
+fn test_split_by_field_collision(#[case] values: [&str; 2], #[case] expected_ok: bool) {
+    let reads: String = values
+        .iter()
+        .map(|value| format!("@r barcode={value}\nACGT\n+\nIIII\n"))
+        .collect();
+    let fastq = TempFile::with_content("reads.fastq", reads);
+    let outdir = TempFile::new("split");
+
+    let result = fastq_split(
+        Some(fastq.path()),
+        &KeepTags::default(),
+        SplitBy::Field("barcode".to_string()),
+        outdir.path(),
+    );
+
+    assert_eq!(result.is_ok(), expected_ok);
+}
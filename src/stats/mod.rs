@@ -1,2 +1,2 @@
 pub mod stats;
-pub use stats::fastq_stats;
+pub use stats::{FastqStats, compute_fastq_stats, fastq_stats};
@@ -1,37 +1,45 @@
+use crate::bam::KeepTags;
 use crate::errors::AppError;
-use bio_utils_rs::io::{bio_fastq_reader, write_json};
+use crate::fastx::fastx_records;
+use crate::table::{ColumnType, TableColumn, TableFormat, TableValue, table_writer};
+#[cfg(test)]
+use crate::test_utils::TempFile;
+use bio_utils_rs::io::write_json;
 use bio_utils_rs::nucleotide::{error_to_phred, mean_error_and_phred, mean_len};
-use log::error;
 use rayon::prelude::*;
+use rstest::rstest;
 use serde::{Deserialize, Serialize};
 use std::path::PathBuf;
 use std::sync::atomic::AtomicUsize;
 use std::sync::atomic::Ordering::Relaxed;
 use std::sync::{Arc, Mutex};
 
+#[cfg_attr(feature = "python", pyo3::pyclass(get_all, frozen))]
 #[derive(Debug, Serialize, Deserialize)]
 pub struct FastqStats {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub file: Option<String>,
     pub num_reads: usize,
     pub num_bases: usize,
-    pub mean_error: f64,
-    pub mean_phred: u8,
+    // Not available for FASTA input.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mean_error: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mean_phred: Option<u8>,
     pub mean_len: usize,
     pub shortest: Option<Vec<usize>>,
     pub longest: Option<Vec<usize>>,
 }
 
 /// # Arguments
-/// * `fastq` - Path to FASTQ file (optional, defaults to stdin).
-/// * `outfile` - Where to write results (optional, defaults to stdout).
+/// * `fastq` - Path to FASTQ/FASTA file (optional, defaults to stdin).
 ///
 /// # Returns
 /// * `Ok(FastqStats)` if successful.
 /// * `Err` if not.
-pub fn fastq_stats(
-    fastq: Option<PathBuf>,
-    outfile: Option<PathBuf>,
-) -> Result<FastqStats, AppError> {
-    let reader = bio_fastq_reader(fastq)?;
+pub fn compute_fastq_stats(fastq: Option<PathBuf>) -> Result<FastqStats, AppError> {
+    let file = fastq.as_ref().map(|f| f.display().to_string());
+    let records = fastx_records(fastq, &KeepTags::default())?;
 
     // Initialize thread safe variables.
     let num_reads = AtomicUsize::new(0);
@@ -39,34 +47,33 @@ pub fn fastq_stats(
     let mean_errors: Arc<Mutex<Vec<f64>>> = Arc::new(Mutex::new(Vec::new()));
     let read_lengths: Arc<Mutex<Vec<usize>>> = Arc::new(Mutex::new(Vec::new()));
 
-    reader.records().par_bridge().for_each(|record| {
-        let record = match record {
-            Ok(record) => record,
-            Err(e) => {
-                error!("{:?}", e);
-                return;
-            }
-        };
-
-        let record_len: usize = record.seq().len();
+    records.par_bridge().try_for_each(|record| {
+        let record = record?;
 
-        // Read error.
-        let (mean_error, _) = mean_error_and_phred(record.qual());
+        let record_len: usize = record.seq.len();
 
         num_reads.fetch_add(1, Relaxed);
         num_bases.fetch_add(record_len, Relaxed);
 
-        // Aggregate mean error and read length per read.
-        mean_errors.lock().unwrap().push(mean_error);
+        // Aggregate mean error (FASTQ only) and read length per read.
+        if let Some(qual) = record.qual.as_ref() {
+            let (mean_error, _) = mean_error_and_phred(qual);
+            mean_errors.lock().unwrap().push(mean_error);
+        }
         read_lengths.lock().unwrap().push(record_len);
-    });
+
+        Ok::<(), AppError>(())
+    })?;
 
     // NOTE that for performance reasons, we calculate the mean of the mean read error rates.
     // To get the true mean error, we'd have to store every single nucleotide error rate, sum
     // them up and divide by the total number of bases (unfeasible for large files).
     let mean_errors = Arc::try_unwrap(mean_errors).unwrap().into_inner().unwrap();
-    let mean_mean_error = mean_errors.iter().sum::<f64>() / mean_errors.len() as f64;
-    let mean_mean_phred = error_to_phred(mean_mean_error);
+    let mean_mean_error = match mean_errors.is_empty() {
+        true => None,
+        false => Some(mean_errors.iter().sum::<f64>() / mean_errors.len() as f64),
+    };
+    let mean_mean_phred = mean_mean_error.map(error_to_phred);
 
     // Mean read length.
     let mut read_lengths = Arc::try_unwrap(read_lengths).unwrap().into_inner().unwrap();
@@ -75,6 +82,7 @@ pub fn fastq_stats(
     let mean_len = mean_len(read_lengths.as_slice());
 
     let fastq_stats = FastqStats {
+        file,
         num_reads: num_reads.into_inner(),
         num_bases: num_bases.into_inner(),
         mean_error: mean_mean_error,
@@ -84,8 +92,95 @@ pub fn fastq_stats(
         longest: read_lengths.last_chunk::<5>().map(|c| c.to_vec()),
     };
 
-    // Write json to output file.
-    write_json(outfile, &fastq_stats)?;
+    Ok(fastq_stats)
+}
+
+fn write_stats_table(stats: &[FastqStats], outfile: Option<PathBuf>) -> Result<(), AppError> {
+    let columns = vec![
+        TableColumn::new("file", ColumnType::Utf8),
+        TableColumn::new("num_reads", ColumnType::UInt64),
+        TableColumn::new("num_bases", ColumnType::UInt64),
+        TableColumn::new("mean_error", ColumnType::Float64),
+        TableColumn::new("mean_phred", ColumnType::UInt64),
+        TableColumn::new("mean_len", ColumnType::UInt64),
+    ];
+    let mut writer = table_writer(outfile, columns)?;
+
+    for s in stats {
+        writer.write_row(&[
+            TableValue::Str(s.file.as_deref().unwrap_or("stdin").as_bytes()),
+            TableValue::UInt(s.num_reads as u64),
+            TableValue::UInt(s.num_bases as u64),
+            s.mean_error
+                .map(TableValue::Float)
+                .unwrap_or(TableValue::Null),
+            s.mean_phred
+                .map(|p| TableValue::UInt(p as u64))
+                .unwrap_or(TableValue::Null),
+            TableValue::UInt(s.mean_len as u64),
+        ])?;
+    }
+
+    writer.finish()
+}
+
+/// # Arguments
+/// * `fastqs` - Paths to FASTQ/FASTA files (reads from stdin if empty).
+/// * `outfile` - Where to write results (optional, defaults to stdout). Written as json
+///   unless the extension is `.parquet` or `.arrow`, in which case there is one row per file.
+///
+/// # Returns
+/// * `Ok(Vec<FastqStats>)` with stats per input file if successful.
+/// * `Err` if not.
+pub fn fastq_stats(
+    fastqs: Vec<PathBuf>,
+    outfile: Option<PathBuf>,
+) -> Result<Vec<FastqStats>, AppError> {
+    let fastqs: Vec<Option<PathBuf>> = match fastqs.is_empty() {
+        true => vec![None],
+        false => fastqs.into_iter().map(Some).collect(),
+    };
+
+    let mut fastq_stats = fastqs
+        .into_iter()
+        .map(compute_fastq_stats)
+        .collect::<Result<Vec<FastqStats>, AppError>>()?;
+
+    match TableFormat::from_path(outfile.as_deref()) {
+        // A single file gives the same json object as before multiple files were
+        // supported (without the file name), multiple files a json array.
+        TableFormat::Text => match fastq_stats.as_mut_slice() {
+            [single] => {
+                let file = single.file.take();
+                write_json(outfile, &*single)?;
+                single.file = file;
+            }
+            multiple => write_json(outfile, multiple)?,
+        },
+        _ => write_stats_table(&fastq_stats, outfile)?,
+    }
 
     Ok(fastq_stats)
 }
+
+#[rstest]
+#[case(
+    "stats.fastq",
+    "@r1\nACGT\n+\n5555\n@r2\nACGTAC\n+\n555555\n",
+    Some(20)
+)]
+#[case("stats.fasta", ">r1\nACGT\n>r2\nACGTAC\n", None)]
+fn test_compute_fastq_stats(
+    #[case] name: &str,
+    #[case] content: &str,
+    #[case] expected_phred: Option<u8>,
+) {
+    let path = TempFile::with_content(name, content);
+
+    let stats = compute_fastq_stats(Some(path.path())).unwrap();
+
+    assert_eq!(stats.num_reads, 2);
+    assert_eq!(stats.num_bases, 10);
+    assert_eq!(stats.mean_phred, expected_phred);
+    assert_eq!(stats.mean_error.is_some(), expected_phred.is_some());
+}
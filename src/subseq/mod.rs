@@ -0,0 +1,2 @@
+pub mod subseq;
+pub use subseq::{RelativeRegion, fastq_subseq};
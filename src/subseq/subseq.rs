@@ -0,0 +1,192 @@
+use crate::bam::KeepTags;
+use crate::errors::AppError;
+use crate::fastx::{fastx_records, fastx_writer, text_reader};
+use crate::fq2tab::split_header;
+use rstest::rstest;
+use std::collections::HashMap;
+use std::io::BufRead;
+use std::path::PathBuf;
+
+/// Region relative to the read, 1-based and inclusive. Negative positions count from
+/// the end of the read, i.e. -1 is the last base.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RelativeRegion {
+    pub start: i64,
+    pub end: i64,
+}
+
+impl RelativeRegion {
+    /// Parses `start:end`, where an empty start or end means the start or end of the read
+    /// (e.g. `1:100`, `-50:` or `11:-11`).
+    pub fn parse(region: &str) -> Result<Self, AppError> {
+        let invalid = || AppError::InvalidArgumentError(format!("invalid region `{region}`"));
+
+        let (start, end) = region.split_once(':').ok_or_else(invalid)?;
+
+        let parse = |s: &str, default: i64| -> Result<i64, AppError> {
+            match s.trim() {
+                "" => Ok(default),
+                s => match s.replace(',', "").parse::<i64>() {
+                    Ok(0) | Err(_) => Err(invalid()),
+                    Ok(pos) => Ok(pos),
+                },
+            }
+        };
+
+        Ok(Self {
+            start: parse(start, 1)?,
+            end: parse(end, -1)?,
+        })
+    }
+
+    /// Span (0-based, end exclusive) in a read of `len` bases, clipped to the read.
+    /// None if the region is empty.
+    pub fn resolve(&self, len: usize) -> Option<(usize, usize)> {
+        let len = len as i64;
+
+        let start = match self.start > 0 {
+            true => self.start - 1,
+            false => len + self.start,
+        };
+        let end = match self.end > 0 {
+            true => self.end,
+            false => len + self.end + 1,
+        };
+
+        let (start, end) = (start.clamp(0, len), end.clamp(0, len));
+
+        (start < end).then_some((start as usize, end as usize))
+    }
+}
+
+/// BED-like file of `read_id start end` (0-based, end exclusive) entries, separated by
+/// tabs or spaces. Further columns, empty lines, comments and track lines are ignored.
+fn read_bed(bed: PathBuf) -> Result<HashMap<String, Vec<(usize, usize)>>, AppError> {
+    let mut regions: HashMap<String, Vec<(usize, usize)>> = HashMap::new();
+
+    for line in text_reader(Some(bed))?.lines() {
+        let line = line?;
+
+        if line.trim().is_empty()
+            || line.starts_with('#')
+            || line.starts_with("track")
+            || line.starts_with("browser")
+        {
+            continue;
+        }
+
+        let invalid = || AppError::InvalidArgumentError(format!("invalid bed line `{line}`"));
+
+        let mut fields = line.split_whitespace();
+        let (id, start, end) = match (fields.next(), fields.next(), fields.next()) {
+            (Some(id), Some(start), Some(end)) => (id, start, end),
+            _ => return Err(invalid()),
+        };
+
+        let start: usize = start.parse().map_err(|_| invalid())?;
+        let end: usize = end.parse().map_err(|_| invalid())?;
+
+        if end < start {
+            return Err(invalid());
+        }
+
+        regions
+            .entry(id.to_string())
+            .or_default()
+            .push((start, end));
+    }
+
+    Ok(regions)
+}
+
+/// Extracts sub-sequences (and qualities) of reads, either the same `region` of every
+/// read or the regions per read in a `bed` file. The coordinates are added to the read
+/// id as `id:start-end` (1-based, inclusive, like `fetch`). Empty regions are skipped.
+pub fn fastq_subseq(
+    fastq: Option<PathBuf>,
+    keep_tags: &KeepTags,
+    region: Option<String>,
+    bed: Option<PathBuf>,
+    outfile: Option<PathBuf>,
+) -> Result<(), AppError> {
+    if region.is_some() == bed.is_some() {
+        return Err(AppError::InvalidArgumentError(
+            "exactly one of a region or a bed file is required".to_string(),
+        ));
+    }
+
+    let region = region.map(|r| RelativeRegion::parse(&r)).transpose()?;
+    let bed = bed.map(read_bed).transpose()?;
+
+    let records = fastx_records(fastq, keep_tags)?;
+    let mut writer = fastx_writer(outfile)?;
+
+    for record in records {
+        let record = record?;
+        let (id, description) = split_header(&record.id);
+
+        let spans: Vec<(usize, usize)> = match (&region, &bed) {
+            (Some(region), None) => region.resolve(record.seq.len()).into_iter().collect(),
+            (None, Some(bed)) => bed
+                .get(String::from_utf8_lossy(id).as_ref())
+                .map(|spans| {
+                    spans
+                        .iter()
+                        .map(|&(start, end)| (start, end.min(record.seq.len())))
+                        .filter(|(start, end)| start < end)
+                        .collect()
+                })
+                .unwrap_or_default(),
+            // Exactly one is set, which is checked above.
+            _ => unreachable!(),
+        };
+
+        for (start, end) in spans {
+            let mut subseq = record.clone();
+            subseq.keep(start, end);
+
+            let mut header = id.to_vec();
+            header.extend_from_slice(format!(":{}-{}", start + 1, end).as_bytes());
+            if !description.is_empty() {
+                header.push(b' ');
+                header.extend_from_slice(description);
+            }
+            subseq.id = header;
+
+            writer.write_record(&subseq)?;
+        }
+    }
+
+    writer.finish()?;
+
+    Ok(())
+}
+
+#[rstest]
+#[case("1:100", Some(RelativeRegion { start: 1, end: 100 }))]
+#[case("-50:", Some(RelativeRegion { start: -50, end: -1 }))]
+#[case(":10", Some(RelativeRegion { start: 1, end: 10 }))]
+#[case("11:-11", Some(RelativeRegion { start: 11, end: -11 }))]
+#[case("0:10", None)]
+#[case("10", None)]
+#[case("a:10", None)]
+fn test_parse_relative_region(#[case] region: &str, #[case] expected: Option<RelativeRegion>) {
+    assert_eq!(RelativeRegion::parse(region).ok(), expected);
+}
+
+#[rstest]
+#[case("1:5", 10, Some((0, 5)))]
+#[case("-3:", 10, Some((7, 10)))]
+#[case("2:-2", 10, Some((1, 9)))]
+#[case("5:100", 10, Some((4, 10)))]
+#[case("-100:3", 10, Some((0, 3)))]
+#[case("11:20", 10, None)]
+#[case("6:5", 10, None)]
+fn test_resolve_relative_region(
+    #[case] region: &str,
+    #[case] len: usize,
+    #[case] expected: Option<(usize, usize)>,
+) {
+    let region = RelativeRegion::parse(region).unwrap();
+    assert_eq!(region.resolve(len), expected);
+}
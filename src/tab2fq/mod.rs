@@ -0,0 +1,2 @@
+pub mod tab2fq;
+pub use tab2fq::fastq_tab2fq;
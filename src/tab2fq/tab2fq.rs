@@ -0,0 +1,143 @@
+use crate::args::TabColumn;
+use crate::errors::AppError;
+use crate::fastx::{FastxRecord, text_reader};
+use crate::fq2tab::unescape_tabs;
+#[cfg(test)]
+use crate::test_utils::TempFile;
+#[cfg(test)]
+use crate::{bam::KeepTags, fq2tab::fastq_fq2tab};
+use bio_utils_rs::io::get_bufwriter;
+use rstest::rstest;
+use std::io::BufRead;
+use std::path::PathBuf;
+
+/// Index of `column` in the tsv header, if present.
+fn column_index(header: &[&str], column: TabColumn) -> Option<usize> {
+    header.iter().position(|h| *h == column.header())
+}
+
+/// Reconstructs reads from a `fq2tab` table. Requires the `read_id` and `seq`
+/// columns. The read header is taken from the `header` column if present, else from
+/// `read_id` and `header_fields`. If there is no `qual` column, reads are written as FASTA.
+pub fn fastq_tab2fq(tsv: Option<PathBuf>, outfile: Option<PathBuf>) -> Result<(), AppError> {
+    let reader = text_reader(tsv)?;
+    let mut writer = get_bufwriter(outfile)?;
+
+    let mut lines = reader.lines();
+
+    let header = match lines.next() {
+        Some(header) => header?,
+        None => return Err(AppError::InvalidArgumentError("empty tsv file".to_string())),
+    };
+    let header: Vec<&str> = header.trim_end_matches('\r').split('\t').collect();
+
+    let missing_column = |column: TabColumn| {
+        AppError::InvalidArgumentError(format!("tsv is missing the {} column", column.header()))
+    };
+
+    let id_index = column_index(&header, TabColumn::Id).ok_or(missing_column(TabColumn::Id))?;
+    let seq_index = column_index(&header, TabColumn::Seq).ok_or(missing_column(TabColumn::Seq))?;
+    let qual_index = column_index(&header, TabColumn::Qual);
+    let header_fields_index = column_index(&header, TabColumn::HeaderFields);
+    let read_header_index = column_index(&header, TabColumn::Header);
+
+    for (i, line) in lines.enumerate() {
+        let line = line?;
+        let line = line.trim_end_matches('\r');
+
+        if line.is_empty() {
+            continue;
+        }
+
+        let fields: Vec<&str> = line.split('\t').collect();
+
+        if fields.len() != header.len() {
+            return Err(AppError::InvalidArgumentError(format!(
+                "line {} has {} columns, expected {}",
+                i + 2,
+                fields.len(),
+                header.len()
+            )));
+        }
+
+        let id = match read_header_index {
+            Some(i) => unescape_tabs(fields[i].as_bytes()),
+            None => {
+                let mut id = fields[id_index].as_bytes().to_vec();
+                if let Some(description) = header_fields_index.map(|i| fields[i])
+                    && !description.is_empty()
+                {
+                    id.push(b' ');
+                    id.extend_from_slice(description.as_bytes());
+                }
+                id
+            }
+        };
+
+        let record = FastxRecord {
+            id,
+            seq: fields[seq_index].as_bytes().to_vec(),
+            qual: qual_index.map(|i| fields[i].as_bytes().to_vec()),
+        };
+
+        if let Some(qual) = record.qual.as_ref()
+            && qual.len() != record.seq.len()
+        {
+            return Err(AppError::InvalidArgumentError(format!(
+                "line {} has a seq and qual of different lengths",
+                i + 2
+            )));
+        }
+
+        record.write(&mut writer)?;
+    }
+
+    writer.flush()?;
+
+    Ok(())
+}
+
+#[rstest]
+#[case(
+    "reads.fastq",
+    "@r1 desc  x\\y\nACGT\n+\nIIII\n@r2\tRX:Z:AC\tqs:i:1\nAC\n+\n5I\n"
+)]
+#[case("reads.fastq", "@r1\nA\n+\nI\n")]
+#[case("reads.fasta", ">r1 desc  x\nACGT\n>r2\tRX:Z:AC\nAC\n")]
+fn test_fq2tab_round_trip(#[case] name: &str, #[case] reads: &str) {
+    let fastx = TempFile::with_content(name, reads);
+    let (tsv, outfile) = (TempFile::new("reads.tsv"), TempFile::new(name));
+
+    fastq_fq2tab(
+        Some(fastx.path()),
+        &KeepTags::default(),
+        vec![TabColumn::Len],
+        true,
+        Some(tsv.path()),
+    )
+    .unwrap();
+    fastq_tab2fq(Some(tsv.path()), Some(outfile.path())).unwrap();
+
+    assert_eq!(outfile.read_to_string(), reads);
+}
+
+#[rstest]
+#[case("read_id\tseq\tqual\nr1\tACGT\tIIII\n", Some("@r1\nACGT\n+\nIIII\n"))]
+#[case(
+    "read_id\theader_fields\tseq\nr1\tdesc\tACGT\n",
+    Some(">r1 desc\nACGT\n")
+)]
+#[case("read_id\tqual\nr1\tIIII\n", None)]
+#[case("seq\tqual\nACGT\tIIII\n", None)]
+#[case("read_id\tseq\tqual\nr1\tACGT\tIII\n", None)]
+#[case("read_id\tseq\nr1\tACGT\textra\n", None)]
+fn test_tab2fq(#[case] table: &str, #[case] expected: Option<&str>) {
+    let tsv = TempFile::with_content("reads.tsv", table);
+    let outfile = TempFile::new("reads.fastq");
+
+    let output = fastq_tab2fq(Some(tsv.path()), Some(outfile.path()))
+        .ok()
+        .map(|_| outfile.read_to_string());
+
+    assert_eq!(output.as_deref(), expected);
+}
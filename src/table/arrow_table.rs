@@ -0,0 +1,268 @@
+use crate::errors::AppError;
+use crate::table::{ColumnType, TableColumn, TableFormat, TableValue, TableWriter};
+#[cfg(test)]
+use crate::test_utils::TempFile;
+use arrow_array::builder::{
+    BooleanBuilder, Float64Builder, StringBuilder, StringDictionaryBuilder, UInt64Builder,
+};
+use arrow_array::types::Int32Type;
+use arrow_array::{ArrayRef, RecordBatch};
+use arrow_ipc::writer::FileWriter;
+use arrow_schema::{DataType, Field, Schema, SchemaRef};
+use parquet::arrow::ArrowWriter;
+use rstest::rstest;
+use std::io::Write;
+use std::sync::Arc;
+
+/// Number of rows buffered before they are written as one record batch.
+const BATCH_SIZE: usize = 65_536;
+
+enum ColumnBuilder {
+    Utf8(StringBuilder),
+    DictUtf8(StringDictionaryBuilder<Int32Type>),
+    UInt64(UInt64Builder),
+    Float64(Float64Builder),
+    Boolean(BooleanBuilder),
+}
+
+impl ColumnBuilder {
+    fn new(column_type: ColumnType) -> Self {
+        match column_type {
+            ColumnType::Utf8 => Self::Utf8(StringBuilder::new()),
+            ColumnType::DictUtf8 => Self::DictUtf8(StringDictionaryBuilder::new()),
+            ColumnType::UInt64 => Self::UInt64(UInt64Builder::new()),
+            ColumnType::Float64 => Self::Float64(Float64Builder::new()),
+            ColumnType::Boolean => Self::Boolean(BooleanBuilder::new()),
+        }
+    }
+
+    fn data_type(column_type: ColumnType) -> DataType {
+        match column_type {
+            ColumnType::Utf8 => DataType::Utf8,
+            ColumnType::DictUtf8 => {
+                DataType::Dictionary(Box::new(DataType::Int32), Box::new(DataType::Utf8))
+            }
+            ColumnType::UInt64 => DataType::UInt64,
+            ColumnType::Float64 => DataType::Float64,
+            ColumnType::Boolean => DataType::Boolean,
+        }
+    }
+
+    fn append(&mut self, value: &TableValue) -> Result<(), AppError> {
+        match (self, value) {
+            (Self::Utf8(b), TableValue::Str(s)) => b.append_value(std::str::from_utf8(s)?),
+            (Self::DictUtf8(b), TableValue::Str(s)) => b.append_value(std::str::from_utf8(s)?),
+            (Self::UInt64(b), TableValue::UInt(v)) => b.append_value(*v),
+            (Self::Float64(b), TableValue::Float(v)) => b.append_value(*v),
+            (Self::Boolean(b), TableValue::Bool(v)) => b.append_value(*v),
+            (Self::Utf8(b), TableValue::Null) => b.append_null(),
+            (Self::DictUtf8(b), TableValue::Null) => b.append_null(),
+            (Self::UInt64(b), TableValue::Null) => b.append_null(),
+            (Self::Float64(b), TableValue::Null) => b.append_null(),
+            (Self::Boolean(b), TableValue::Null) => b.append_null(),
+            (_, value) => {
+                return Err(AppError::TableWriteError(format!(
+                    "value {value:?} does not match the column type"
+                )));
+            }
+        }
+
+        Ok(())
+    }
+
+    fn finish(&mut self) -> ArrayRef {
+        match self {
+            Self::Utf8(b) => Arc::new(b.finish()),
+            Self::DictUtf8(b) => Arc::new(b.finish()),
+            Self::UInt64(b) => Arc::new(b.finish()),
+            Self::Float64(b) => Arc::new(b.finish()),
+            Self::Boolean(b) => Arc::new(b.finish()),
+        }
+    }
+}
+
+enum Sink {
+    Parquet(ArrowWriter<Box<dyn Write + Send>>),
+    ArrowIpc(FileWriter<Box<dyn Write + Send>>),
+}
+
+/// Writes rows as Parquet or Arrow IPC, in record batches of `BATCH_SIZE` rows.
+pub struct ArrowTableWriter {
+    schema: SchemaRef,
+    builders: Vec<ColumnBuilder>,
+    num_rows: usize,
+    sink: Sink,
+}
+
+impl ArrowTableWriter {
+    pub fn new(
+        writer: Box<dyn Write + Send>,
+        columns: Vec<TableColumn>,
+        format: TableFormat,
+    ) -> Result<Self, AppError> {
+        // Dictionaries differ between batches, which the IPC file format does not allow.
+        let columns: Vec<TableColumn> = columns
+            .into_iter()
+            .map(|c| match (format, c.column_type) {
+                (TableFormat::ArrowIpc, ColumnType::DictUtf8) => {
+                    TableColumn::new(&c.name, ColumnType::Utf8)
+                }
+                _ => c,
+            })
+            .collect();
+
+        let fields: Vec<Field> = columns
+            .iter()
+            .map(|c| Field::new(&c.name, ColumnBuilder::data_type(c.column_type), true))
+            .collect();
+        let schema: SchemaRef = Arc::new(Schema::new(fields));
+
+        let sink = match format {
+            TableFormat::Parquet => {
+                Sink::Parquet(ArrowWriter::try_new(writer, schema.clone(), None)?)
+            }
+            TableFormat::ArrowIpc => Sink::ArrowIpc(FileWriter::try_new(writer, &schema)?),
+            TableFormat::Text => {
+                return Err(AppError::TableWriteError(
+                    "text output is not supported by the arrow writer".to_string(),
+                ));
+            }
+        };
+
+        Ok(Self {
+            schema,
+            builders: columns
+                .iter()
+                .map(|c| ColumnBuilder::new(c.column_type))
+                .collect(),
+            num_rows: 0,
+            sink,
+        })
+    }
+
+    fn write_batch(&mut self) -> Result<(), AppError> {
+        if self.num_rows == 0 {
+            return Ok(());
+        }
+
+        let arrays: Vec<ArrayRef> = self.builders.iter_mut().map(|b| b.finish()).collect();
+        let batch = RecordBatch::try_new(self.schema.clone(), arrays)?;
+
+        match &mut self.sink {
+            Sink::Parquet(w) => w.write(&batch)?,
+            Sink::ArrowIpc(w) => w.write(&batch)?,
+        }
+
+        self.num_rows = 0;
+
+        Ok(())
+    }
+}
+
+impl TableWriter for ArrowTableWriter {
+    fn write_row(&mut self, row: &[TableValue]) -> Result<(), AppError> {
+        if row.len() != self.builders.len() {
+            return Err(AppError::TableWriteError(format!(
+                "expected {} values, got {}",
+                self.builders.len(),
+                row.len()
+            )));
+        }
+
+        for (builder, value) in self.builders.iter_mut().zip(row) {
+            builder.append(value)?;
+        }
+
+        self.num_rows += 1;
+
+        if self.num_rows >= BATCH_SIZE {
+            self.write_batch()?;
+        }
+
+        Ok(())
+    }
+
+    fn finish(mut self: Box<Self>) -> Result<(), AppError> {
+        self.write_batch()?;
+
+        let mut writer = match self.sink {
+            Sink::Parquet(w) => w.into_inner()?,
+            Sink::ArrowIpc(mut w) => {
+                w.finish()?;
+                w.into_inner()?
+            }
+        };
+
+        writer.flush()?;
+
+        Ok(())
+    }
+}
+
+#[rstest]
+#[case("table.parquet", TableFormat::Parquet)]
+#[case("table.arrow", TableFormat::ArrowIpc)]
+fn test_arrow_table_writer(#[case] name: &str, #[case] format: TableFormat) {
+    let path = TempFile::new(name);
+
+    let columns = vec![
+        TableColumn::new("read_name", ColumnType::Utf8),
+        TableColumn::new("barcode", ColumnType::DictUtf8),
+        TableColumn::new("length", ColumnType::UInt64),
+        TableColumn::new("error", ColumnType::Float64),
+        TableColumn::new("trimmed", ColumnType::Boolean),
+    ];
+
+    let mut writer: Box<dyn TableWriter> = Box::new(
+        ArrowTableWriter::new(
+            Box::new(std::fs::File::create(&*path).unwrap()),
+            columns,
+            format,
+        )
+        .unwrap(),
+    );
+
+    // Later batches have other barcodes, so that their dictionaries differ.
+    for i in 0..BATCH_SIZE * 2 + 10 {
+        let barcode = format!("bc{:02}", i % 3 + 3 * (i / BATCH_SIZE));
+
+        writer
+            .write_row(&[
+                TableValue::Str(b"read"),
+                match i % 2 {
+                    0 => TableValue::Str(barcode.as_bytes()),
+                    _ => TableValue::Null,
+                },
+                TableValue::UInt(i as u64),
+                TableValue::Float(0.1),
+                TableValue::Bool(true),
+            ])
+            .unwrap();
+    }
+
+    // Wrong number of values and wrong types are errors.
+    assert!(writer.write_row(&[TableValue::Null]).is_err());
+    writer.finish().unwrap();
+
+    let file = std::fs::File::open(&*path).unwrap();
+    let batches: Vec<RecordBatch> = match format {
+        TableFormat::Parquet => {
+            parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder::try_new(file)
+                .unwrap()
+                .build()
+                .unwrap()
+                .map(|b| b.unwrap())
+                .collect()
+        }
+        _ => arrow_ipc::reader::FileReader::try_new(file, None)
+            .unwrap()
+            .map(|b| b.unwrap())
+            .collect(),
+    };
+
+    let num_rows: usize = batches.iter().map(|b| b.num_rows()).sum();
+    let num_nulls: usize = batches.iter().map(|b| b.column(1).null_count()).sum();
+
+    assert_eq!(num_rows, BATCH_SIZE * 2 + 10);
+    assert_eq!(num_nulls, (BATCH_SIZE * 2 + 10) / 2);
+}
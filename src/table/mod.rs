@@ -0,0 +1,7 @@
+pub mod table;
+pub use table::{ColumnType, TableColumn, TableFormat, TableValue, TableWriter, table_writer};
+
+#[cfg(feature = "parquet")]
+pub mod arrow_table;
+#[cfg(feature = "parquet")]
+pub use arrow_table::ArrowTableWriter;
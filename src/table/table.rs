@@ -0,0 +1,155 @@
+use crate::errors::AppError;
+use bio_utils_rs::io::get_bufwriter;
+use rstest::rstest;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// Output format of tabular reports, inferred from the file extension.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TableFormat {
+    /// Plain text (tsv or json, depending on the subcommand). Used for stdout.
+    Text,
+    Parquet,
+    ArrowIpc,
+}
+
+impl TableFormat {
+    pub fn from_path(path: Option<&Path>) -> Self {
+        let extension = path
+            .and_then(|p| p.extension())
+            .map(|e| e.to_string_lossy().to_lowercase());
+
+        match extension.as_deref() {
+            Some("parquet") => Self::Parquet,
+            Some("arrow") | Some("ipc") | Some("feather") => Self::ArrowIpc,
+            _ => Self::Text,
+        }
+    }
+}
+
+/// Column types. `DictUtf8` is for low cardinality strings (e.g. barcodes),
+/// which are dictionary encoded in Parquet output. Arrow IPC files only allow one
+/// dictionary per column for the whole file, so they are written as plain strings there.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ColumnType {
+    Utf8,
+    DictUtf8,
+    UInt64,
+    Float64,
+    Boolean,
+}
+
+#[derive(Debug, Clone)]
+pub struct TableColumn {
+    pub name: String,
+    pub column_type: ColumnType,
+}
+
+impl TableColumn {
+    pub fn new(name: &str, column_type: ColumnType) -> Self {
+        Self {
+            name: name.to_string(),
+            column_type,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TableValue<'a> {
+    Str(&'a [u8]),
+    UInt(u64),
+    Float(f64),
+    Bool(bool),
+    /// Written as `N/A` in tsv output.
+    Null,
+}
+
+pub trait TableWriter: Send {
+    fn write_row(&mut self, row: &[TableValue]) -> Result<(), AppError>;
+
+    /// Flushes remaining rows and finalizes the file (e.g. Parquet footer).
+    fn finish(self: Box<Self>) -> Result<(), AppError>;
+}
+
+pub struct TsvTableWriter {
+    writer: Box<dyn Write + Send>,
+}
+
+impl TsvTableWriter {
+    pub fn new(
+        mut writer: Box<dyn Write + Send>,
+        columns: &[TableColumn],
+    ) -> Result<Self, AppError> {
+        let header: Vec<&str> = columns.iter().map(|c| c.name.as_str()).collect();
+        writer.write_all(header.join("\t").as_bytes())?;
+        writer.write_all(b"\n")?;
+
+        Ok(Self { writer })
+    }
+}
+
+impl TableWriter for TsvTableWriter {
+    fn write_row(&mut self, row: &[TableValue]) -> Result<(), AppError> {
+        for (i, value) in row.iter().enumerate() {
+            if i > 0 {
+                self.writer.write_all(b"\t")?;
+            }
+
+            match value {
+                TableValue::Str(s) => self.writer.write_all(s)?,
+                TableValue::UInt(v) => self.writer.write_all(v.to_string().as_bytes())?,
+                TableValue::Float(v) => self.writer.write_all(v.to_string().as_bytes())?,
+                TableValue::Bool(v) => self.writer.write_all(v.to_string().as_bytes())?,
+                TableValue::Null => self.writer.write_all(b"N/A")?,
+            }
+        }
+
+        self.writer.write_all(b"\n")?;
+
+        Ok(())
+    }
+
+    fn finish(mut self: Box<Self>) -> Result<(), AppError> {
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+/// Creates a table writer for `outfile` (stdout if `None`). Files ending in
+/// `.parquet` are written as Parquet, `.arrow`/`.ipc`/`.feather` as Arrow IPC
+/// and everything else as tsv.
+pub fn table_writer(
+    outfile: Option<PathBuf>,
+    columns: Vec<TableColumn>,
+) -> Result<Box<dyn TableWriter>, AppError> {
+    let format = TableFormat::from_path(outfile.as_deref());
+
+    match format {
+        TableFormat::Text => Ok(Box::new(TsvTableWriter::new(
+            get_bufwriter(outfile)?,
+            &columns,
+        )?)),
+
+        #[cfg(feature = "parquet")]
+        _ => Ok(Box::new(crate::table::ArrowTableWriter::new(
+            get_bufwriter(outfile)?,
+            columns,
+            format,
+        )?)),
+
+        #[cfg(not(feature = "parquet"))]
+        _ => Err(AppError::TableWriteError(
+            "fastq_rs was compiled without the `parquet` feature".to_string(),
+        )),
+    }
+}
+
+#[rstest]
+#[case(Some("out.parquet"), TableFormat::Parquet)]
+#[case(Some("out.arrow"), TableFormat::ArrowIpc)]
+#[case(Some("out.feather"), TableFormat::ArrowIpc)]
+#[case(Some("out.tsv.gz"), TableFormat::Text)]
+#[case(None, TableFormat::Text)]
+fn test_table_format(#[case] path: Option<&str>, #[case] expected: TableFormat) {
+    assert_eq!(TableFormat::from_path(path.map(Path::new)), expected);
+}
@@ -0,0 +1,2 @@
+pub mod tail;
+pub use tail::fastq_tail;
@@ -0,0 +1,80 @@
+use crate::bam::KeepTags;
+use crate::errors::AppError;
+use crate::fastx::{FastxRecord, fastx_records, fastx_writer};
+use rstest::rstest;
+use std::collections::VecDeque;
+use std::path::PathBuf;
+
+/// The last `num_reads` records, kept in a ring buffer.
+fn last_records(
+    records: impl Iterator<Item = Result<FastxRecord, AppError>>,
+    num_reads: usize,
+) -> Result<VecDeque<FastxRecord>, AppError> {
+    // The capacity grows with the input, so a huge `num_reads` does not allocate up front.
+    let mut last_reads: VecDeque<FastxRecord> = VecDeque::with_capacity(num_reads.min(1024));
+
+    if num_reads == 0 {
+        return Ok(last_reads);
+    }
+
+    for record in records {
+        let record = record?;
+
+        if last_reads.len() == num_reads {
+            last_reads.pop_front();
+        }
+
+        last_reads.push_back(record);
+    }
+
+    Ok(last_reads)
+}
+
+/// Writes the last `num_reads` reads, in the input format (FASTQ/FASTA). Only `num_reads` records are kept in memory.
+pub fn fastq_tail(
+    fastq: Option<PathBuf>,
+    keep_tags: &KeepTags,
+    num_reads: usize,
+    outfile: Option<PathBuf>,
+) -> Result<(), AppError> {
+    let last_reads = last_records(fastx_records(fastq, keep_tags)?, num_reads)?;
+    let mut writer = fastx_writer(outfile)?;
+
+    for record in &last_reads {
+        writer.write_record(record)?;
+    }
+
+    writer.finish()?;
+
+    Ok(())
+}
+
+#[rstest]
+#[case(0, vec![])]
+#[case(2, vec!["r4", "r5"])]
+#[case(5, vec!["r1", "r2", "r3", "r4", "r5"])]
+#[case(10, vec!["r1", "r2", "r3", "r4", "r5"])]
+#[case(usize::MAX, vec!["r1", "r2", "r3", "r4", "r5"])]
+fn test_last_records(#[case] num_reads: usize, #[case] expected: Vec<&str>) {
+    let records = (1..=5).map(|i| {
+        Ok(FastxRecord {
+            id: format!("r{i}").into_bytes(),
+            seq: b"ACGT".to_vec(),
+            qual: None,
+        })
+    });
+
+    let ids: Vec<Vec<u8>> = last_records(records, num_reads)
+        .unwrap()
+        .into_iter()
+        .map(|record| record.id)
+        .collect();
+
+    assert_eq!(
+        ids,
+        expected
+            .iter()
+            .map(|id| id.as_bytes().to_vec())
+            .collect::<Vec<_>>()
+    );
+}
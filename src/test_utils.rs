@@ -0,0 +1,56 @@
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering::Relaxed;
+
+static NUM_FILES: AtomicUsize = AtomicUsize::new(0);
+
+/// Unique file path in the temp directory, ending in `name` (so that the extension
+/// selects the file format). The file (or directory) is removed on drop, also when a
+/// test fails.
+pub struct TempFile {
+    path: PathBuf,
+}
+
+impl TempFile {
+    pub fn new(name: &str) -> Self {
+        let path = std::env::temp_dir().join(format!(
+            "fastq_rs_{}_{}_{name}",
+            std::process::id(),
+            NUM_FILES.fetch_add(1, Relaxed)
+        ));
+
+        Self { path }
+    }
+
+    pub fn with_content(name: &str, content: impl AsRef<[u8]>) -> Self {
+        let file = Self::new(name);
+        std::fs::write(&file.path, content).expect("Failed to write temp file");
+        file
+    }
+
+    pub fn path(&self) -> PathBuf {
+        self.path.clone()
+    }
+
+    pub fn read_to_string(&self) -> String {
+        std::fs::read_to_string(&self.path).expect("Failed to read temp file")
+    }
+}
+
+impl Deref for TempFile {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        let _ = match self.path.is_dir() {
+            true => std::fs::remove_dir_all(&self.path),
+            false => std::fs::remove_file(&self.path),
+        };
+    }
+}
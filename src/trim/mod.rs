@@ -1,2 +1,2 @@
 pub mod trim;
-pub use trim::fastq_trim;
+pub use trim::{Orientation, ReadTrimmer, TrimmedRead, fastq_trim};
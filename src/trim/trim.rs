@@ -1,6 +1,10 @@
+use crate::bam::KeepTags;
 use crate::errors::AppError;
+use crate::fastx::{FastxRecord, fastx_records, fastx_writer};
+use crate::fq2tab::split_header;
+use crate::table::{ColumnType, TableColumn, TableValue, table_writer};
+use bio::alignment::AlignmentOperation;
 use bio::pattern_matching::myers::MyersBuilder;
-use bio_utils_rs::io::{bio_fastq_reader, get_bufwriter};
 use bio_utils_rs::nucleotide::reverse_complement;
 use rayon::prelude::*;
 use rstest::rstest;
@@ -10,7 +14,7 @@ use std::sync::{Arc, Mutex};
 // Allow for ambiguous nucleotide matches.
 // We should probably build this once and reuse of possible.
 #[inline]
-fn myers_builder(primer_seq: &[u8]) -> bio::pattern_matching::myers::Myers {
+pub fn myers_builder(primer_seq: &[u8]) -> bio::pattern_matching::myers::Myers {
     MyersBuilder::new()
         .ambig(b'N', b"ACGT")
         .ambig(b'R', b"AG")
@@ -26,96 +30,236 @@ fn myers_builder(primer_seq: &[u8]) -> bio::pattern_matching::myers::Myers {
         .build_64(primer_seq)
 }
 
-fn find_fuzzy(seq: &[u8], barcode: &[u8], max_mismatches: u8) -> Option<usize> {
-    let myers = myers_builder(barcode);
+/// Best alignment of a barcode in a read. `start..end` is the aligned part of the
+/// read (including indels), so trimming at `start` or `end` removes exactly the barcode.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BarcodeHit {
+    /// Index of the barcode in the list of forward or reverse barcodes.
+    pub index: usize,
+    pub start: usize,
+    pub end: usize,
+    pub mismatches: usize,
+    pub indels: usize,
+}
+
+impl BarcodeHit {
+    #[inline]
+    pub fn edit_distance(&self) -> usize {
+        self.mismatches + self.indels
+    }
 
-    let (start, num_mismatches) = myers.find_best_end(seq);
+    /// Moves the hit coordinates from a search window to the full read.
+    #[inline]
+    fn shift(self, offset: usize) -> Self {
+        Self {
+            start: self.start + offset,
+            end: self.end + offset,
+            ..self
+        }
+    }
+}
 
-    if num_mismatches <= max_mismatches {
-        return Some(start);
+/// Finds the best (lowest edit distance, then leftmost) alignment of `barcode` in `seq`
+/// with at most `max_mismatches` substitutions and `max_indels` insertions/deletions.
+fn find_barcode(
+    seq: &[u8],
+    barcode: &[u8],
+    index: usize,
+    max_mismatches: u8,
+    max_indels: u8,
+) -> Option<BarcodeHit> {
+    let mut myers = myers_builder(barcode);
+    let mut matches = myers.find_all_lazy(seq, max_mismatches.saturating_add(max_indels));
+
+    let mut candidates: Vec<(usize, u8)> = matches.by_ref().collect();
+    candidates.sort_by_key(|&(end, dist)| (dist, end));
+
+    let mut ops: Vec<AlignmentOperation> = Vec::new();
+
+    for (end, _) in candidates {
+        let (start, _) = matches.path_at(end, &mut ops)?;
+
+        let mismatches = ops
+            .iter()
+            .filter(|op| matches!(op, AlignmentOperation::Subst))
+            .count();
+        let indels = ops
+            .iter()
+            .filter(|op| matches!(op, AlignmentOperation::Ins | AlignmentOperation::Del))
+            .count();
+
+        if mismatches <= max_mismatches as usize && indels <= max_indels as usize {
+            return Some(BarcodeHit {
+                index,
+                start,
+                end: end + 1,
+                mismatches,
+                indels,
+            });
+        }
     }
 
     None
 }
 
-/// I'm not happy with the multi-thread implementation with
-/// lots of Arcs and Mutexes floating around. It might be the
-/// case that needletail single-thread is actually faster.
-pub fn fastq_trim(
-    fastq: Option<PathBuf>,
-    min_len: usize,
-    trim_start: usize,
-    trim_end: usize,
-    barcodes_forward: Option<Vec<String>>,
-    barcodes_reverse: Option<Vec<String>>,
-    max_mismatches: u8,
-    barcode_margin: usize,
-    outfile: Option<PathBuf>,
-    barcodes_tsv: PathBuf,
-) -> Result<(), AppError> {
-    // Fastq reader/writer.
-    let reader = bio_fastq_reader(fastq)?;
-    let fastq_writer = Arc::new(Mutex::new(get_bufwriter(outfile)?));
-
-    // Tsv writer (to file).
-    let tsv_writer = Arc::new(Mutex::new(get_bufwriter(Some(barcodes_tsv.clone()))?));
-
-    // If not supplied, empty vec means no iterating.
-    let barcodes_start: Vec<String> = barcodes_forward.unwrap_or_default();
-
-    // For reverse barcodes, we need to first reverse complement.
-    let barcodes_end: Vec<String> = barcodes_reverse
-        .as_ref()
-        .map(|vec| {
-            vec.iter()
-                .map(|s| String::from_utf8(reverse_complement(s.as_bytes())).unwrap())
-                .collect()
-        })
-        .unwrap_or_default();
+/// Read orientation relative to the amplicon (forward primer first).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Orientation {
+    Forward,
+    Reverse,
+}
 
-    // Writer tsv header
-    {
-        let mut s = tsv_writer.lock().expect("Failed to lock mutex");
-        s.write_all(
-            b"read_name\tlength_before\tlength_after\ttrimmed\tbarcode_forward\tbarcode_reverse\n",
-        )
-        .unwrap();
+impl Orientation {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Orientation::Forward => "forward",
+            Orientation::Reverse => "reverse",
+        }
     }
+}
 
-    reader.records().par_bridge().for_each(|record| {
-        let record = match record {
-            Ok(record) => record,
-            Err(_) => return,
-        };
+/// Result of trimming a single read. `start` and `end` describe
+/// the retained part of the read, i.e. `seq[start..end]`. For reads in
+/// reverse orientation (amplicon mode only), these refer to the
+/// reverse complemented read.
+#[derive(Debug, PartialEq)]
+pub struct TrimmedRead {
+    pub start: usize,
+    pub end: usize,
+    pub trimmed: bool,
+    pub barcode_forward: Option<BarcodeHit>,
+    pub barcode_reverse: Option<BarcodeHit>,
+    pub orientation: Option<Orientation>,
+}
+
+impl TrimmedRead {
+    /// Trims the record, after reverse complementing it if in reverse orientation.
+    pub fn apply(&self, record: &mut FastxRecord) {
+        if self.orientation == Some(Orientation::Reverse) {
+            record.reverse_complement();
+        }
+
+        record.keep(self.start, self.end);
+    }
+}
+
+/// Barcode and hard trimming settings, shared between `trim` and `pipeline`.
+pub struct ReadTrimmer {
+    pub trim_start: usize,
+    pub trim_end: usize,
+    pub barcodes_start: Vec<String>,
+    pub barcodes_end: Vec<String>,
+    pub max_mismatches: u8,
+    pub max_indels: u8,
+    pub barcode_margin: usize,
+    pub amplicon: bool,
+}
+
+impl ReadTrimmer {
+    pub fn new(
+        trim_start: usize,
+        trim_end: usize,
+        barcodes_forward: Option<Vec<String>>,
+        barcodes_reverse: Option<Vec<String>>,
+        max_mismatches: u8,
+        max_indels: u8,
+        barcode_margin: usize,
+        amplicon: bool,
+    ) -> Self {
+        // If not supplied, empty vec means no iterating.
+        let barcodes_start: Vec<String> = barcodes_forward.unwrap_or_default();
+
+        // For reverse barcodes, we need to first reverse complement.
+        let barcodes_end: Vec<String> = barcodes_reverse
+            .as_ref()
+            .map(|vec| {
+                vec.iter()
+                    .map(|s| String::from_utf8(reverse_complement(s.as_bytes())).unwrap())
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        Self {
+            trim_start,
+            trim_end,
+            barcodes_start,
+            barcodes_end,
+            max_mismatches,
+            max_indels,
+            barcode_margin,
+            amplicon,
+        }
+    }
+
+    /// Amplicon mode needs both a forward and a reverse primer.
+    pub fn validate(&self) -> Result<(), AppError> {
+        if self.amplicon && (self.barcodes_start.is_empty() || self.barcodes_end.is_empty()) {
+            return Err(AppError::InvalidArgumentError(
+                "amplicon mode requires both forward and reverse barcodes".to_string(),
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Returns `None` if the entire read is hard-trimmed. In amplicon mode, both
+    /// orientations are tried and `None` is also returned unless both barcodes are found.
+    pub fn trim(&self, seq: &[u8]) -> Option<TrimmedRead> {
+        if !self.amplicon {
+            return self.trim_strand(seq);
+        }
+
+        let is_linked =
+            |t: &TrimmedRead| t.barcode_forward.is_some() && t.barcode_reverse.is_some();
 
-        let mut seq = record.seq();
-        let mut qual = record.qual();
+        if let Some(trimmed_read) = self.trim_strand(seq).filter(is_linked) {
+            return Some(TrimmedRead {
+                orientation: Some(Orientation::Forward),
+                ..trimmed_read
+            });
+        }
+
+        self.trim_strand(&reverse_complement(seq))
+            .filter(is_linked)
+            .map(|trimmed_read| TrimmedRead {
+                orientation: Some(Orientation::Reverse),
+                ..trimmed_read
+            })
+    }
+
+    fn trim_strand(&self, seq: &[u8]) -> Option<TrimmedRead> {
+        let mut start: usize = 0;
+        let mut end: usize = seq.len();
         let mut trimmed: bool = false;
-        let mut found_barcode_forward: Option<&[u8]> = None;
-        let mut found_barcode_reverse: Option<&[u8]> = None;
+        let mut found_barcode_forward: Option<BarcodeHit> = None;
+        let mut found_barcode_reverse: Option<BarcodeHit> = None;
 
-        for barcode_forward in &barcodes_start {
+        for (i, barcode_forward) in self.barcodes_start.iter().enumerate() {
             let barcode_len = barcode_forward.len();
-            let total_margin = barcode_len + barcode_margin + 2;
+            let total_margin = barcode_len + self.barcode_margin + 2;
 
             // Skip too short sequences.
-            if seq.len() <= total_margin {
+            if end - start <= total_margin {
                 continue;
             }
 
             // Only look in relevant part of seq.
-            let forward_start = find_fuzzy(
-                &seq[..total_margin],
+            let hit = find_barcode(
+                &seq[start..start + total_margin],
                 barcode_forward.as_bytes(),
-                max_mismatches,
+                i,
+                self.max_mismatches,
+                self.max_indels,
             );
 
-            match forward_start {
+            match hit {
                 None => continue,
-                Some(forward_start) => {
-                    seq = &seq[forward_start + 1..];
-                    qual = &qual[forward_start + 1..];
-                    found_barcode_forward = Some(barcode_forward.as_bytes());
+                Some(hit) => {
+                    let hit = hit.shift(start);
+
+                    // Keep everything after the barcode.
+                    start = hit.end;
+                    found_barcode_forward = Some(hit);
                     trimmed = true;
 
                     break;
@@ -123,11 +267,11 @@ pub fn fastq_trim(
             }
         }
 
-        for barcode_reverse in &barcodes_end {
+        for (i, barcode_reverse) in self.barcodes_end.iter().enumerate() {
             let barcode_len = barcode_reverse.len();
-            let seq_len = seq.len();
+            let seq_len = end - start;
 
-            let total_margin: usize = barcode_len + barcode_margin + 2;
+            let total_margin: usize = barcode_len + self.barcode_margin + 2;
 
             // Skip too short sequences.
             if seq_len <= total_margin {
@@ -135,18 +279,23 @@ pub fn fastq_trim(
             }
 
             // Only look in relevant part of seq.
-            let reverse_start = find_fuzzy(
-                &seq[seq_len - total_margin..],
+            let window_start = end - total_margin;
+            let hit = find_barcode(
+                &seq[window_start..end],
                 barcode_reverse.as_bytes(),
-                max_mismatches,
+                i,
+                self.max_mismatches,
+                self.max_indels,
             );
 
-            match reverse_start {
+            match hit {
                 None => continue,
-                Some(reverse_start) => {
-                    seq = &seq[..seq_len - reverse_start];
-                    qual = &qual[..seq_len - reverse_start];
-                    found_barcode_reverse = Some(barcode_reverse.as_bytes());
+                Some(hit) => {
+                    let hit = hit.shift(window_start);
+
+                    // Keep everything before the barcode.
+                    end = hit.start;
+                    found_barcode_reverse = Some(hit);
                     trimmed = true;
 
                     break;
@@ -154,95 +303,250 @@ pub fn fastq_trim(
             }
         }
 
+        let seq_len = end - start;
+
         // We want to hard-trim the entire remaining seq.
-        if trim_start >= seq.len() || trim_end >= seq.len() {
-            return;
+        if self.trim_start >= seq_len || self.trim_end >= seq_len {
+            return None;
         }
 
         // We want to hard-trim the entire remaining seq.
-        if trim_start >= seq.len() - trim_end {
-            return;
+        if self.trim_start >= seq_len - self.trim_end {
+            return None;
         }
 
-        seq = &seq[trim_start..seq.len() - trim_end];
-        qual = &qual[trim_start..qual.len() - trim_end];
+        Some(TrimmedRead {
+            start: start + self.trim_start,
+            end: end - self.trim_end,
+            trimmed,
+            barcode_forward: found_barcode_forward,
+            barcode_reverse: found_barcode_reverse,
+            orientation: None,
+        })
+    }
+}
 
-        if seq.len() >= min_len {
-            let mut w = fastq_writer.lock().expect("Failed to lock mutex");
+/// I'm not happy with the multi-thread implementation with
+/// lots of Arcs and Mutexes floating around. It might be the
+/// case that needletail single-thread is actually faster.
+pub fn fastq_trim(
+    fastq: Option<PathBuf>,
+    keep_tags: &KeepTags,
+    min_len: usize,
+    trim_start: usize,
+    trim_end: usize,
+    barcodes_forward: Option<Vec<String>>,
+    barcodes_reverse: Option<Vec<String>>,
+    max_mismatches: u8,
+    max_indels: u8,
+    barcode_margin: usize,
+    amplicon: bool,
+    outfile: Option<PathBuf>,
+    barcodes_tsv: Option<PathBuf>,
+) -> Result<(), AppError> {
+    // Fastq reader/writer.
+    let records = fastx_records(fastq, keep_tags)?;
+    let fastq_writer = Arc::new(Mutex::new(fastx_writer(outfile)?));
+
+    // Per read report (to file). Tsv, Parquet or Arrow IPC depending on the extension.
+    let report_columns = vec![
+        TableColumn::new("read_name", ColumnType::Utf8),
+        TableColumn::new("length_before", ColumnType::UInt64),
+        TableColumn::new("length_after", ColumnType::UInt64),
+        TableColumn::new("trimmed", ColumnType::Boolean),
+        TableColumn::new("barcode_forward", ColumnType::DictUtf8),
+        TableColumn::new("barcode_reverse", ColumnType::DictUtf8),
+        TableColumn::new("orientation", ColumnType::DictUtf8),
+    ];
+    let report_columns = report_columns
+        .into_iter()
+        .chain(["forward", "reverse"].iter().flat_map(|side| {
+            ["start", "end", "mismatches", "indels", "edit_distance"]
+                .iter()
+                .map(move |field| {
+                    TableColumn::new(&format!("barcode_{side}_{field}"), ColumnType::UInt64)
+                })
+        }))
+        .collect();
+    let tsv_writer = barcodes_tsv
+        .map(|barcodes_tsv| table_writer(Some(barcodes_tsv), report_columns))
+        .transpose()?
+        .map(|writer| Arc::new(Mutex::new(writer)));
+
+    let trimmer = ReadTrimmer::new(
+        trim_start,
+        trim_end,
+        barcodes_forward,
+        barcodes_reverse,
+        max_mismatches,
+        max_indels,
+        barcode_margin,
+        amplicon,
+    );
+    trimmer.validate()?;
+
+    records.par_bridge().try_for_each(|record| {
+        let record = record?;
+
+        let trimmed_read = match trimmer.trim(&record.seq) {
+            Some(trimmed_read) => trimmed_read,
+            None => return Ok(()),
+        };
+
+        let length_before = record.seq.len();
+
+        let mut trimmed_record = record;
+        trimmed_read.apply(&mut trimmed_record);
 
-            let write_read = (|| -> Result<(), AppError> {
-                w.write_all(b"@")?;
-                w.write_all(record.id().as_bytes())?;
-                w.write_all(b"\n")?;
-                w.write_all(seq)?;
-                w.write_all(b"\n")?;
-                w.write_all(b"+\n")?;
-                w.write_all(qual)?;
-                w.write_all(b"\n")?;
+        let length_after = trimmed_record.seq.len();
 
-                Ok(())
-            })();
+        if length_after >= min_len {
+            let mut w = fastq_writer.lock().expect("Failed to lock mutex");
+
+            let write_read = w.write_record(&trimmed_record);
 
             if write_read.is_err() {
                 panic!("Failed to write line: {:?}", write_read);
             }
         }
 
-        let mut s = tsv_writer.lock().expect("Failed to lock mutex");
-
-        let info_write = (|| -> Result<(), AppError> {
-            s.write_all(record.id().as_bytes())?;
-            s.write_all(b"\t")?;
-
-            // Length before.
-            s.write_all(record.seq().len().to_string().as_bytes())?;
-            s.write_all(b"\t")?;
-
-            // Length after.
-            s.write_all(seq.len().to_string().as_bytes())?;
-            s.write_all(b"\t")?;
-
-            // Was trimmed?
-            s.write_all(trimmed.to_string().as_bytes())?;
-            s.write_all(b"\t")?;
+        // The per read report is optional.
+        let Some(tsv_writer) = tsv_writer.as_ref() else {
+            return Ok(());
+        };
 
-            // Forward barcode.
-            let bf = found_barcode_forward.unwrap_or(b"N/A");
-            s.write_all(bf)?;
-            s.write_all(b"\t")?;
+        let mut s = tsv_writer.lock().expect("Failed to lock mutex");
 
-            // Reverse barcode.
-            let br = found_barcode_reverse.unwrap_or(b"N/A");
-            s.write_all(br)?;
-            s.write_all(b"\n")?;
+        let mut row = vec![
+            TableValue::Str(split_header(&trimmed_record.id).0),
+            TableValue::UInt(length_before as u64),
+            TableValue::UInt(length_after as u64),
+            TableValue::Bool(trimmed_read.trimmed),
+            trimmed_read
+                .barcode_forward
+                .map(|hit| TableValue::Str(trimmer.barcodes_start[hit.index].as_bytes()))
+                .unwrap_or(TableValue::Null),
+            trimmed_read
+                .barcode_reverse
+                .map(|hit| TableValue::Str(trimmer.barcodes_end[hit.index].as_bytes()))
+                .unwrap_or(TableValue::Null),
+            trimmed_read
+                .orientation
+                .map(|o| TableValue::Str(o.as_str().as_bytes()))
+                .unwrap_or(TableValue::Null),
+        ];
+        for hit in [trimmed_read.barcode_forward, trimmed_read.barcode_reverse] {
+            match hit {
+                Some(hit) => row.extend(
+                    [
+                        hit.start,
+                        hit.end,
+                        hit.mismatches,
+                        hit.indels,
+                        hit.edit_distance(),
+                    ]
+                    .map(|v| TableValue::UInt(v as u64)),
+                ),
+                None => row.extend([TableValue::Null; 5]),
+            }
+        }
 
-            Ok(())
-        })();
+        let info_write = s.write_row(&row);
 
         if info_write.is_err() {
             panic!("Failed to write line: {:?}", info_write);
         }
-    });
 
-    let mut tsv_writer = Arc::into_inner(tsv_writer).unwrap().into_inner().unwrap();
-    tsv_writer.flush()?;
+        Ok::<(), AppError>(())
+    })?;
 
-    let mut fastq_writer = Arc::into_inner(fastq_writer).unwrap().into_inner().unwrap();
-    fastq_writer.flush()?;
+    if let Some(tsv_writer) = tsv_writer {
+        let tsv_writer = Arc::into_inner(tsv_writer).unwrap().into_inner().unwrap();
+        tsv_writer.finish()?;
+    }
+
+    let fastq_writer = Arc::into_inner(fastq_writer).unwrap().into_inner().unwrap();
+    fastq_writer.finish()?;
 
     Ok(())
 }
 
 #[rstest]
-#[case(b"AATTTTAA", b"TTTT", 0, Some(5))]
-#[case(b"TTTTTTTTTTTTTTT", b"AAAAAA", 0, None)]
-#[case(b"TTTTTTGGAGGTTTTTTT", b"GGGGG", 1, Some(10))]
-fn test_fuzzy_match(
+#[case(b"AATTTTAA", b"TTTT", 0, 0, Some((2, 6, 0, 0)))]
+#[case(b"TTTTTTTTTTTTTTT", b"AAAAAA", 0, 0, None)]
+#[case(b"TTTTTTGGAGGTTTTTTT", b"GGGGG", 1, 0, Some((6, 11, 1, 0)))]
+#[case(b"TTTTACGTTACGTTTTT", b"ACGTACGT", 0, 1, Some((4, 13, 0, 1)))]
+#[case(b"TTTTACGTTACGTTTTT", b"ACGTACGT", 1, 0, None)]
+#[case(b"TTTTACGACGTTTTT", b"ACGTACGT", 0, 1, Some((4, 11, 0, 1)))]
+#[case(b"TTACGTTT", b"ACGT", u8::MAX, u8::MAX, Some((2, 6, 0, 0)))]
+fn test_find_barcode(
     #[case] seq: &[u8],
     #[case] barcode: &[u8],
     #[case] max_mismatches: u8,
-    #[case] expected_match_start: Option<usize>,
+    #[case] max_indels: u8,
+    #[case] expected: Option<(usize, usize, usize, usize)>,
+) {
+    let result = find_barcode(seq, barcode, 0, max_mismatches, max_indels)
+        .map(|hit| (hit.start, hit.end, hit.mismatches, hit.indels));
+    assert_eq!(result, expected);
+}
+
+#[rstest]
+#[case(false, Some(Orientation::Forward))]
+#[case(true, Some(Orientation::Reverse))]
+fn test_amplicon_orientation(
+    #[case] reverse_read: bool,
+    #[case] expected_orientation: Option<Orientation>,
 ) {
-    let result = find_fuzzy(seq, barcode, max_mismatches);
-    assert_eq!(result, expected_match_start);
+    let primer_forward = "ACGTTGCAAGCT";
+    let primer_reverse = "GGATCCTTAAGC";
+
+    let trimmer = ReadTrimmer::new(
+        0,
+        0,
+        Some(vec![primer_forward.to_string()]),
+        Some(vec![primer_reverse.to_string()]),
+        0,
+        0,
+        10,
+        true,
+    );
+
+    let insert = b"TTTTTGGGGGCCCCCAAAAATTTTTGGGGGCCCCCAAAAA";
+    let amplicon = [
+        primer_forward.as_bytes(),
+        insert,
+        &reverse_complement(primer_reverse.as_bytes()),
+    ]
+    .concat();
+
+    let seq = match reverse_read {
+        true => reverse_complement(&amplicon),
+        false => amplicon.clone(),
+    };
+
+    let trimmed_read = trimmer.trim(&seq).unwrap();
+    assert_eq!(trimmed_read.orientation, expected_orientation);
+    assert_eq!(trimmed_read.barcode_forward.map(|hit| hit.index), Some(0));
+    assert_eq!(trimmed_read.barcode_reverse.map(|hit| hit.index), Some(0));
+
+    // Both orientations give the same output.
+    let mut record = FastxRecord {
+        id: b"read".to_vec(),
+        seq,
+        qual: None,
+    };
+    trimmed_read.apply(&mut record);
+
+    let mut expected = FastxRecord {
+        id: b"read".to_vec(),
+        seq: amplicon.clone(),
+        qual: None,
+    };
+    trimmer.trim(&amplicon).unwrap().apply(&mut expected);
+
+    assert_eq!(record, expected);
+
+    // Without the reverse primer, the read is discarded.
+    assert_eq!(trimmer.trim(&amplicon[..60]), None);
 }
@@ -0,0 +1,2 @@
+pub mod umi_dedup;
+pub use umi_dedup::{cluster_umis, consensus, fastq_umi_dedup};
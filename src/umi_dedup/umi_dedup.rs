@@ -0,0 +1,484 @@
+use crate::args::UmiMethod;
+use crate::bam::KeepTags;
+use crate::errors::AppError;
+use crate::extract_umi::read_umi;
+use crate::fastx::{FastxRecord, fastx_records, fastx_writer};
+use crate::sort::min_minimizer;
+use crate::table::{ColumnType, TableColumn, TableValue, table_writer};
+use bio_utils_rs::nucleotide::{PHRED_OFFSET, mean_error_and_phred};
+use rstest::rstest;
+use std::collections::{BTreeMap, BTreeSet, HashMap, VecDeque};
+use std::path::PathBuf;
+
+/// Minimizer window for the sequence signature. Must be odd.
+const SIGNATURE_WINDOW: usize = 11;
+
+/// Consensus qualities are capped at this phred score.
+const MAX_CONSENSUS_PHRED: usize = 60;
+
+#[inline]
+fn hamming(a: &[u8], b: &[u8]) -> Option<usize> {
+    (a.len() == b.len()).then(|| a.iter().zip(b).filter(|(x, y)| x != y).count())
+}
+
+/// Finds candidate neighbours of a UMI without comparing it to all other UMIs.
+enum NeighbourIndex<'a> {
+    /// Single substitution variants of a UMI are looked up directly (3 * L lookups for
+    /// ACGT UMIs of length L), like umi_tools does.
+    Substitutions {
+        umis: HashMap<&'a [u8], usize>,
+        alphabet: Vec<u8>,
+    },
+    /// UMIs within distance d share at least one of d + 1 segments.
+    Segments {
+        buckets: HashMap<(usize, usize, &'a [u8]), Vec<usize>>,
+        num_segments: usize,
+    },
+}
+
+impl<'a> NeighbourIndex<'a> {
+    fn new(counts: &[(&'a [u8], usize)], max_distance: usize) -> Self {
+        if max_distance == 1 {
+            let alphabet: BTreeSet<u8> = counts
+                .iter()
+                .flat_map(|(umi, _)| umi.iter().copied())
+                .collect();
+
+            return Self::Substitutions {
+                umis: counts
+                    .iter()
+                    .enumerate()
+                    .map(|(i, &(umi, _))| (umi, i))
+                    .collect(),
+                alphabet: alphabet.into_iter().collect(),
+            };
+        }
+
+        let num_segments = max_distance + 1;
+        let mut buckets: HashMap<(usize, usize, &[u8]), Vec<usize>> = HashMap::new();
+
+        for (i, &(umi, _)) in counts.iter().enumerate() {
+            for (segment, key) in segments(umi, num_segments) {
+                buckets
+                    .entry((umi.len(), segment, key))
+                    .or_default()
+                    .push(i);
+            }
+        }
+
+        Self::Segments {
+            buckets,
+            num_segments,
+        }
+    }
+
+    /// UMIs of the same length that may be within `max_distance` of `umi` (at index
+    /// `node`), excluding itself. Distances still need to be checked.
+    fn candidates(&self, umi: &[u8], node: usize) -> Vec<usize> {
+        let mut candidates = Vec::new();
+
+        match self {
+            Self::Substitutions { umis, alphabet } => {
+                let mut variant = umi.to_vec();
+
+                for pos in 0..umi.len() {
+                    for &base in alphabet.iter().filter(|&&base| base != umi[pos]) {
+                        variant[pos] = base;
+                        candidates.extend(umis.get(variant.as_slice()));
+                    }
+                    variant[pos] = umi[pos];
+                }
+            }
+            Self::Segments {
+                buckets,
+                num_segments,
+            } => {
+                for (segment, key) in segments(umi, *num_segments) {
+                    candidates.extend(
+                        buckets
+                            .get(&(umi.len(), segment, key))
+                            .into_iter()
+                            .flatten(),
+                    );
+                }
+                candidates.sort_unstable();
+                candidates.dedup();
+                candidates.retain(|&i| i != node);
+            }
+        }
+
+        candidates
+    }
+}
+
+/// Splits `umi` into `num_segments` (possibly empty) segments, with their index.
+fn segments(umi: &[u8], num_segments: usize) -> impl Iterator<Item = (usize, &[u8])> {
+    (0..num_segments).map(move |i| {
+        let (start, end) = (
+            i * umi.len() / num_segments,
+            (i + 1) * umi.len() / num_segments,
+        );
+        (i, &umi[start..end])
+    })
+}
+
+/// Clusters UMIs (with their read counts) that likely stem from the same molecule, like
+/// umi_tools. UMIs are visited by decreasing count and each unassigned UMI starts a new
+/// cluster, of which it is the first (representative) UMI:
+/// * `Unique` - Every UMI is its own cluster.
+/// * `Cluster` - Connected components of UMIs within `max_distance`.
+/// * `Adjacency` - A UMI absorbs its unassigned neighbours within `max_distance`.
+/// * `Directional` - Like `Cluster`, but only following edges from UMI a to b if
+///   count(a) >= 2 * count(b) - 1.
+pub fn cluster_umis<'a>(
+    counts: &[(&'a [u8], usize)],
+    method: UmiMethod,
+    max_distance: usize,
+) -> Vec<Vec<&'a [u8]>> {
+    let mut order: Vec<usize> = (0..counts.len()).collect();
+    order.sort_by(|&a, &b| {
+        counts[b]
+            .1
+            .cmp(&counts[a].1)
+            .then(counts[a].0.cmp(counts[b].0))
+    });
+
+    let mut rank = vec![0; counts.len()];
+    for (i, &node) in order.iter().enumerate() {
+        rank[node] = i;
+    }
+
+    let index = NeighbourIndex::new(counts, max_distance);
+
+    let is_edge = |from: usize, to: usize| {
+        let ((from_umi, from_count), (to_umi, to_count)) = (counts[from], counts[to]);
+
+        hamming(from_umi, to_umi).is_some_and(|d| d <= max_distance)
+            && match method {
+                UmiMethod::Directional => from_count + 1 >= 2 * to_count,
+                _ => true,
+            }
+    };
+
+    let mut assigned = vec![false; counts.len()];
+    let mut clusters: Vec<Vec<&[u8]>> = Vec::new();
+
+    for &root in &order {
+        if assigned[root] {
+            continue;
+        }
+        assigned[root] = true;
+
+        let mut cluster = vec![counts[root].0];
+        let mut queue = VecDeque::from([root]);
+
+        while let Some(node) = queue.pop_front() {
+            if method == UmiMethod::Unique {
+                break;
+            }
+
+            // Neighbours are visited in the same order as the roots.
+            let mut neighbours = index.candidates(counts[node].0, node);
+            neighbours.retain(|&next| !assigned[next] && is_edge(node, next));
+            neighbours.sort_unstable_by_key(|&next| rank[next]);
+
+            for next in neighbours {
+                assigned[next] = true;
+                cluster.push(counts[next].0);
+
+                // Adjacency only absorbs the direct neighbours of the representative UMI.
+                if method != UmiMethod::Adjacency {
+                    queue.push_back(next);
+                }
+            }
+        }
+
+        clusters.push(cluster);
+    }
+
+    clusters
+}
+
+/// Read with the lowest mean error (the first one for FASTA).
+fn best_read(records: &[FastxRecord], reads: &[usize]) -> usize {
+    *reads
+        .iter()
+        .min_by(|&&a, &&b| {
+            let error = |i: usize| {
+                records[i]
+                    .qual
+                    .as_ref()
+                    .map_or(0.0, |qual| mean_error_and_phred(qual).0)
+            };
+            error(a).total_cmp(&error(b))
+        })
+        .expect("Empty UMI group")
+}
+
+/// Per position consensus of reads of the most common length (other reads do not vote).
+/// Each base is voted for with its phred score. The consensus quality is the score of
+/// the winning base minus the scores of the others.
+pub fn consensus(reads: &[&FastxRecord]) -> Result<(Vec<u8>, Vec<u8>), AppError> {
+    let mut lengths: BTreeMap<usize, usize> = BTreeMap::new();
+    for read in reads {
+        *lengths.entry(read.seq.len()).or_insert(0) += 1;
+    }
+
+    // Most common, then longest length.
+    let len = lengths
+        .iter()
+        .max_by_key(|&(&len, &count)| (count, len))
+        .map_or(0, |(&len, _)| len);
+
+    let mut seq: Vec<u8> = Vec::with_capacity(len);
+    let mut qual: Vec<u8> = Vec::with_capacity(len);
+
+    for pos in 0..len {
+        let mut scores = [0usize; 4];
+
+        for read in reads.iter().filter(|read| read.seq.len() == len) {
+            let read_qual = read.qual.as_ref().ok_or(AppError::MissingQualityError(
+                "umi-dedup --consensus".to_string(),
+            ))?;
+
+            let base = match read.seq[pos].to_ascii_uppercase() {
+                b'A' => 0,
+                b'C' => 1,
+                b'G' => 2,
+                b'T' => 3,
+                _ => continue,
+            };
+            scores[base] += read_qual[pos].saturating_sub(PHRED_OFFSET as u8) as usize;
+        }
+
+        let (best, &best_score) = scores
+            .iter()
+            .enumerate()
+            .max_by_key(|&(i, &score)| (score, std::cmp::Reverse(i)))
+            .expect("No bases");
+        let other_score: usize = scores.iter().sum::<usize>() - best_score;
+
+        match best_score {
+            0 => {
+                seq.push(b'N');
+                qual.push(PHRED_OFFSET as u8);
+            }
+            _ => {
+                seq.push(b"ACGT"[best]);
+                let phred = best_score
+                    .saturating_sub(other_score)
+                    .min(MAX_CONSENSUS_PHRED);
+                qual.push((phred + PHRED_OFFSET) as u8);
+            }
+        }
+    }
+
+    Ok((seq, qual))
+}
+
+/// # Arguments
+/// * `fastq` - Path to FASTQ/FASTA file with UMIs in the read headers, e.g. from
+///   `extract-umi` (optional, defaults to stdin).
+/// * `keep_tags` - SAM/BAM aux tags to keep in the read headers (e.g. `RX`).
+/// * `method` - How UMIs with sequencing errors are clustered.
+/// * `max_distance` - Maximum hamming distance between UMIs of one cluster.
+/// * `kmer_size` - Minimizer size for the sequence signature reads are grouped by
+///   before clustering UMIs. 0 groups reads by UMI only.
+/// * `consensus` - Write a consensus read per group, instead of the best read.
+/// * `outfile` - Output file (optional, defaults to stdout).
+/// * `group_stats` - Number of groups (and reads) per group size.
+pub fn fastq_umi_dedup(
+    fastq: Option<PathBuf>,
+    keep_tags: &KeepTags,
+    method: UmiMethod,
+    max_distance: usize,
+    kmer_size: usize,
+    consensus_reads: bool,
+    outfile: Option<PathBuf>,
+    group_stats: PathBuf,
+) -> Result<(), AppError> {
+    if kmer_size > 31 {
+        return Err(AppError::InvalidArgumentError(
+            "kmer_size must be at most 31".to_string(),
+        ));
+    }
+
+    let records: Vec<FastxRecord> = fastx_records(fastq, keep_tags)?.collect::<Result<_, _>>()?;
+
+    // Read indices per UMI, per sequence signature.
+    let mut signatures: HashMap<u64, HashMap<&[u8], Vec<usize>>> = HashMap::new();
+    let mut umi_len: Option<usize> = None;
+
+    for (i, record) in records.iter().enumerate() {
+        let umi = read_umi(&record.id).ok_or_else(|| {
+            AppError::InvalidArgumentError(format!(
+                "read {} has no UMI, run extract-umi first",
+                String::from_utf8_lossy(&record.id)
+            ))
+        })?;
+
+        // UMIs of different lengths point to a read name that merely ends in bases.
+        if *umi_len.get_or_insert(umi.len()) != umi.len() {
+            return Err(AppError::InvalidArgumentError(format!(
+                "read {} has a UMI of another length than the first read",
+                String::from_utf8_lossy(&record.id)
+            )));
+        }
+
+        let signature = min_minimizer(
+            &record.seq.to_ascii_uppercase(),
+            kmer_size,
+            SIGNATURE_WINDOW,
+        )
+        .unwrap_or(0);
+
+        signatures
+            .entry(signature)
+            .or_default()
+            .entry(umi)
+            .or_default()
+            .push(i);
+    }
+
+    // Reads per molecule, the first ones carrying the representative UMI.
+    let mut groups: Vec<(Vec<usize>, usize)> = Vec::new();
+
+    for umis in signatures.values() {
+        let counts: Vec<(&[u8], usize)> = umis
+            .iter()
+            .map(|(&umi, reads)| (umi, reads.len()))
+            .collect();
+
+        for cluster in cluster_umis(&counts, method, max_distance) {
+            let num_representative = umis[cluster[0]].len();
+            let reads: Vec<usize> = cluster
+                .iter()
+                .flat_map(|umi| umis[umi].iter().copied())
+                .collect();
+
+            groups.push((reads, num_representative));
+        }
+    }
+
+    // Keep the input order, by the first read of each group.
+    groups.sort_by_key(|(reads, _)| reads.iter().min().copied());
+
+    let mut writer = fastx_writer(outfile)?;
+    let mut sizes: BTreeMap<usize, usize> = BTreeMap::new();
+
+    for (reads, num_representative) in &groups {
+        *sizes.entry(reads.len()).or_insert(0) += 1;
+
+        let mut record = records[best_read(&records, &reads[..*num_representative])].clone();
+
+        if consensus_reads {
+            let group: Vec<&FastxRecord> = reads.iter().map(|&i| &records[i]).collect();
+            let (seq, qual) = consensus(&group)?;
+
+            record.seq = seq;
+            record.qual = Some(qual);
+        }
+
+        writer.write_record(&record)?;
+    }
+
+    writer.finish()?;
+
+    let mut stats_writer = table_writer(
+        Some(group_stats),
+        vec![
+            TableColumn::new("group_size", ColumnType::UInt64),
+            TableColumn::new("num_groups", ColumnType::UInt64),
+            TableColumn::new("num_reads", ColumnType::UInt64),
+        ],
+    )?;
+
+    for (size, num_groups) in sizes {
+        stats_writer.write_row(&[
+            TableValue::UInt(size as u64),
+            TableValue::UInt(num_groups as u64),
+            TableValue::UInt((size * num_groups) as u64),
+        ])?;
+    }
+
+    stats_writer.finish()?;
+
+    Ok(())
+}
+
+#[rstest]
+#[case(UmiMethod::Unique, vec![vec!["AAAA"], vec!["AAAT"], vec!["AATT"], vec!["CCCC"]])]
+#[case(UmiMethod::Cluster, vec![vec!["AAAA", "AAAT", "AATT"], vec!["CCCC"]])]
+#[case(UmiMethod::Adjacency, vec![vec!["AAAA", "AAAT"], vec!["AATT"], vec!["CCCC"]])]
+#[case(UmiMethod::Directional, vec![vec!["AAAA", "AAAT"], vec!["AATT"], vec!["CCCC"]])]
+fn test_cluster_umis(#[case] method: UmiMethod, #[case] expected: Vec<Vec<&str>>) {
+    // AATT has too many reads to be an error of AAAT in directional mode.
+    let counts: Vec<(&[u8], usize)> = vec![(b"AAAA", 10), (b"AAAT", 3), (b"AATT", 3), (b"CCCC", 1)];
+
+    let clusters: Vec<Vec<&str>> = cluster_umis(&counts, method, 1)
+        .iter()
+        .map(|cluster| {
+            cluster
+                .iter()
+                .map(|umi| std::str::from_utf8(umi).unwrap())
+                .collect()
+        })
+        .collect();
+
+    assert_eq!(clusters, expected);
+}
+
+#[rstest]
+#[case(0, vec![vec!["AAAA"], vec!["AAAT"], vec!["CCCC"], vec!["AATT"], vec!["AAA"]])]
+#[case(1, vec![vec!["AAAA", "AAAT", "AATT"], vec!["CCCC"], vec!["AAA"]])]
+#[case(2, vec![vec!["AAAA", "AAAT", "AATT"], vec!["CCCC"], vec!["AAA"]])]
+#[case(3, vec![vec!["AAAA", "AAAT", "AATT"], vec!["CCCC"], vec!["AAA"]])]
+#[case(4, vec![vec!["AAAA", "AAAT", "CCCC", "AATT"], vec!["AAA"]])]
+#[case(10, vec![vec!["AAAA", "AAAT", "CCCC", "AATT"], vec!["AAA"]])]
+fn test_cluster_umis_distance(#[case] max_distance: usize, #[case] expected: Vec<Vec<&str>>) {
+    // UMIs of different lengths are never clustered.
+    let counts: Vec<(&[u8], usize)> = vec![
+        (b"AATT", 2),
+        (b"AAAA", 10),
+        (b"CCCC", 3),
+        (b"AAAT", 5),
+        (b"AAA", 1),
+    ];
+
+    let clusters: Vec<Vec<&str>> = cluster_umis(&counts, UmiMethod::Cluster, max_distance)
+        .iter()
+        .map(|cluster| {
+            cluster
+                .iter()
+                .map(|umi| std::str::from_utf8(umi).unwrap())
+                .collect()
+        })
+        .collect();
+
+    assert_eq!(clusters, expected);
+}
+
+#[rstest]
+#[case(b"55+5", b"]]?]")]
+#[case(b"5555", b"]]5]")]
+fn test_consensus(#[case] minority_qual: &[u8], #[case] expected_qual: &[u8]) {
+    let read = |seq: &[u8], qual: &[u8]| FastxRecord {
+        id: b"read".to_vec(),
+        seq: seq.to_vec(),
+        qual: Some(qual.to_vec()),
+    };
+
+    let reads = [
+        read(b"ACGT", b"5555"),
+        read(b"ACCT", minority_qual),
+        read(b"ACGT", b"5555"),
+        read(b"AC", b"II"),
+    ];
+    let group: Vec<&FastxRecord> = reads.iter().collect();
+
+    let (seq, qual) = consensus(&group).unwrap();
+
+    assert_eq!(seq, b"ACGT");
+    // Phred 20 + 20 minus the minority base at the third position, 60 elsewhere.
+    assert_eq!(qual, expected_qual);
+}